@@ -0,0 +1,172 @@
+//! Blocking client for scripts and tests that don't run an async runtime
+//!
+//! Each client brings its own single threaded runtime. The connection only makes progress while
+//! a call blocks, which is all a client waiting for every reply needs.
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use shared::config::{Calibration, Profile, Settings};
+use shared::event_log::LogEntry;
+use shared::mission::Mission;
+use shared::{CarCommand, DriveMode, Melody, ServerMessage, Telemetry};
+use tokio::runtime::{self, Runtime};
+
+use crate::session::{Record, Recorder};
+use crate::{Result, Timeouts, Transport};
+
+pub struct CarClient {
+    runtime: Runtime,
+    client: crate::CarClient,
+}
+
+impl CarClient {
+    /// Connect to the car with the default timeouts, authenticating with `passphrase` if the car asks for it
+    pub fn connect(host: &str, port: u16, passphrase: Option<&str>) -> Result<Self> {
+        Self::connect_with(host, port, passphrase, Timeouts::default())
+    }
+
+    /// Connect to the car, authenticating with `passphrase` if the car asks for it
+    pub fn connect_with(host: &str, port: u16, passphrase: Option<&str>, timeouts: Timeouts) -> Result<Self> {
+        Self::connect_over(host, port, passphrase, timeouts, Transport::Tcp)
+    }
+
+    /// Connect to the car, sending drive setpoints over `transport`
+    pub fn connect_over(
+        host: &str,
+        port: u16,
+        passphrase: Option<&str>,
+        timeouts: Timeouts,
+        transport: Transport,
+    ) -> Result<Self> {
+        let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+        let client = runtime.block_on(crate::CarClient::connect_over(host, port, passphrase, timeouts, transport))?;
+        Ok(Self { runtime, client })
+    }
+
+    /// Whether commands are signed with a session key
+    pub fn is_authenticated(&self) -> bool {
+        self.client.is_authenticated()
+    }
+
+    /// Send a command to the car and return its reply
+    pub fn request(&self, command: CarCommand) -> Result<ServerMessage> {
+        self.runtime.block_on(self.client.request(command))
+    }
+
+    pub fn go_forward(&self, speed: u8) -> Result<()> {
+        self.runtime.block_on(self.client.go_forward(speed))
+    }
+
+    pub fn go_backward(&self, speed: u8) -> Result<()> {
+        self.runtime.block_on(self.client.go_backward(speed))
+    }
+
+    pub fn turn_left(&self, speed: u8) -> Result<()> {
+        self.runtime.block_on(self.client.turn_left(speed))
+    }
+
+    pub fn turn_right(&self, speed: u8) -> Result<()> {
+        self.runtime.block_on(self.client.turn_right(speed))
+    }
+
+    /// Drive with `throttle` and `steer` from -100 to 100, positive steer turns right
+    pub fn drive(&self, throttle: i8, steer: i8) -> Result<()> {
+        self.runtime.block_on(self.client.drive(throttle, steer))
+    }
+
+    /// Drive forward, rightward and clockwise at `vx`, `vy` and `omega` from -100 to 100
+    pub fn holonomic(&self, vx: i8, vy: i8, omega: i8) -> Result<()> {
+        self.runtime.block_on(self.client.holonomic(vx, vy, omega))
+    }
+
+    /// Turn on the spot by `degrees`, see [`crate::CarClient::turn_by`]
+    pub fn turn_by(&self, degrees: i16) -> Result<()> {
+        self.runtime.block_on(self.client.turn_by(degrees))
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.runtime.block_on(self.client.stop())
+    }
+
+    pub fn beep(&self, freq: u16, ms: u16) -> Result<()> {
+        self.runtime.block_on(self.client.beep(freq, ms))
+    }
+
+    pub fn play_melody(&self, melody: Melody) -> Result<()> {
+        self.runtime.block_on(self.client.play_melody(melody))
+    }
+
+    pub fn set_mode(&self, mode: DriveMode) -> Result<()> {
+        self.runtime.block_on(self.client.set_mode(mode))
+    }
+
+    /// Have the car run `mission` with its own timing, its progress shows in the telemetry
+    pub fn run_mission(&self, mission: Mission) -> Result<()> {
+        self.runtime.block_on(self.client.run_mission(mission))
+    }
+
+    pub fn telemetry(&self) -> Result<Telemetry> {
+        self.runtime.block_on(self.client.telemetry())
+    }
+
+    /// Telemetry sampled every `period`, see [`crate::CarClient::subscribe`]
+    pub fn subscribe(&self, period: Duration) -> impl Iterator<Item = Result<Telemetry>> + '_ {
+        let mut samples = Box::pin(self.runtime.block_on(async { self.client.subscribe(period) }));
+        std::iter::from_fn(move || self.runtime.block_on(samples.next()))
+    }
+
+    /// The car's runtime settings
+    pub fn settings(&self) -> Result<Settings> {
+        self.runtime.block_on(self.client.settings())
+    }
+
+    /// Apply and store new runtime settings on the car
+    pub fn set_settings(&self, settings: Settings) -> Result<()> {
+        self.runtime.block_on(self.client.set_settings(settings))
+    }
+
+    /// The motor calibration stored on the car
+    pub fn calibration(&self) -> Result<Calibration> {
+        self.runtime.block_on(self.client.calibration())
+    }
+
+    /// Every entry the car's event log still holds, oldest first
+    pub fn event_log(&self) -> Result<Vec<LogEntry>> {
+        self.runtime.block_on(self.client.event_log())
+    }
+
+    /// Correct the motors with `calibration`, storing it on the car if `store` is set
+    pub fn set_calibration(&self, calibration: Calibration, store: bool) -> Result<()> {
+        self.runtime.block_on(self.client.set_calibration(calibration, store))
+    }
+
+    /// Switch the driving profile, see [`crate::CarClient::set_profile`]
+    pub fn set_profile(&self, profile: Profile) -> Result<()> {
+        self.runtime.block_on(self.client.set_profile(profile))
+    }
+
+    /// Lock or unlock the driving profile, see [`crate::CarClient::lock_profile`]
+    pub fn lock_profile(&self, admin_passphrase: &str, lock: bool) -> Result<()> {
+        self.runtime.block_on(self.client.lock_profile(admin_passphrase, lock))
+    }
+
+    /// Store a new key on the car, an empty passphrase disables authentication
+    pub fn set_key(&self, passphrase: &str) -> Result<()> {
+        self.runtime.block_on(self.client.set_key(passphrase))
+    }
+
+    /// Record the session, see [`crate::CarClient::start_recording`]
+    pub fn start_recording(&self, recorder: Recorder) {
+        self.client.start_recording(recorder);
+    }
+
+    pub fn stop_recording(&self) -> Option<Recorder> {
+        self.client.stop_recording()
+    }
+
+    /// Send the driving commands of `records` again, see [`crate::CarClient::replay`]
+    pub fn replay(&self, records: &[Record], speed: f32) -> Result<()> {
+        self.runtime.block_on(self.client.replay(records, speed))
+    }
+}
@@ -0,0 +1,60 @@
+use core::fmt;
+use std::io;
+
+use shared::frame::FrameError;
+use shared::{ErrorCode, ServerMessage};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The car didn't connect or answer within the configured timeout
+    Timeout,
+    /// The connection couldn't be made or broke down
+    Io(io::Error),
+    /// The connection to the car is closed
+    Closed,
+    /// A frame couldn't be encoded or decoded
+    Frame(FrameError),
+    /// The car asked for a key and none was given
+    KeyRequired,
+    /// The car refused the request
+    Rejected(ErrorCode),
+    /// The car replied with something that doesn't answer the request
+    Unexpected(Box<ServerMessage>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "Timed out waiting for the car"),
+            Error::Io(err) => write!(f, "Connection failed: {}", err),
+            Error::Closed => write!(f, "The car closed the connection"),
+            Error::Frame(err) => write!(f, "Bad frame: {:?}", err),
+            Error::KeyRequired => write!(f, "The car requires a key, please enter it"),
+            Error::Rejected(code) => write!(f, "Car rejected command: {:?}", code),
+            Error::Unexpected(reply) => write!(f, "Unexpected reply: {:?}", reply),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(err: FrameError) -> Self {
+        Error::Frame(err)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,32 @@
+//! Reading the car's event log, see [`CarClient::event_log`](crate::CarClient::event_log)
+//!
+//! [`describe`] names an event for people and [`write_csv`] exports the entries for a
+//! spreadsheet.
+
+use std::io::{self, Write};
+
+use shared::event_log::{Event, LogEntry, ResetReason};
+
+/// Short description of `event`
+pub fn describe(event: &Event) -> String {
+    match event {
+        Event::Boot(ResetReason::PowerOn) => "boot after power on".into(),
+        Event::Boot(ResetReason::Watchdog) => "boot after a watchdog timeout".into(),
+        Event::Boot(ResetReason::Forced) => "boot after a forced reset".into(),
+        Event::Connected => "client connected".into(),
+        Event::Disconnected => "client disconnected".into(),
+        Event::Failsafe => "deadman stopped the car".into(),
+        Event::Rejected { code, count: 1 } => format!("rejected a message: {:?}", code),
+        Event::Rejected { code, count } => format!("rejected {} messages, the first: {:?}", count, code),
+        Event::LowBattery => "battery low".into(),
+    }
+}
+
+/// Write `entries` as CSV with a header row
+pub fn write_csv(entries: &[LogEntry], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "seq,boot,uptime_ms,event")?;
+    for entry in entries {
+        writeln!(out, "{},{},{},{}", entry.seq, entry.boot, entry.uptime_ms, describe(&entry.event))?;
+    }
+    Ok(())
+}
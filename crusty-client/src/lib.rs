@@ -0,0 +1,558 @@
+//! Client for the car's command port, shared by the GUI and the command line
+//!
+//! [`CarClient::connect`] reads the car's greeting and answers the challenge when the car has a
+//! pre-shared key, then hands the socket to a task that keeps the connection. The client is a
+//! cheap handle to that task: clones share the connection and requests from all of them can be
+//! in flight at once. The car answers every message in the order it received them, so replies
+//! are matched to requests by their position. The connection closes when the last handle is
+//! dropped.
+//!
+//! With [`Transport::Udp`] drive setpoints skip the command stream and go to the car as
+//! datagrams, see [`shared::drive`].
+//!
+//! [`blocking::CarClient`] wraps the client for scripts and tests without an async runtime,
+//! [`session`] records driving sessions and replays them and [`event_log`] exports the car's event
+//! log.
+
+pub mod blocking;
+mod error;
+pub mod event_log;
+pub mod session;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::Stream;
+use log::{debug, warn};
+use shared::auth::{self, ClientSession};
+use shared::config::{Calibration, Profile, Settings};
+use shared::drive::{self, MAX_DATAGRAM_LEN};
+use shared::event_log::{LOG_PAGE_LEN, LogEntry, LogPage};
+use shared::frame::{self, FrameReader};
+use shared::mission::Mission;
+use shared::{CarCommand, ClientMessage, DriveMode, Melody, ProfileLock, ServerMessage, Telemetry};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, MissedTickBehavior};
+
+pub use error::{Error, Result};
+use session::Recorder;
+
+const FRAME_LEN: usize = frame::HEADER_LEN + frame::MAX_FRAME_LEN;
+
+/// Requests waiting to be written to the car
+const QUEUE_LEN: usize = 16;
+
+/// How long to wait for the car
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting, the greeting and authentication
+    pub connect: Duration,
+    /// The reply to each request
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(1),
+            request: Duration::from_secs(2),
+        }
+    }
+}
+
+/// How drive setpoints get to the car
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// In order with every other command on the TCP connection, each one acknowledged
+    #[default]
+    Tcp,
+    /// As UDP datagrams to the command port, the car applies the newest one and drops any that
+    /// arrives late. Only [`CarClient::drive`] and [`CarClient::stop`] use it, a stop is also sent
+    /// over TCP so it gets through when the datagram is lost.
+    Udp,
+}
+
+// The UDP side of a connection, for drive setpoints
+struct DriveSocket {
+    socket: UdpSocket,
+    sender: Mutex<drive::Sender>,
+}
+
+// A command and where its reply goes
+struct Request {
+    command: CarCommand,
+    reply: oneshot::Sender<Result<ServerMessage>>,
+}
+
+// Shared by the handles and the connection task, which does the recording
+type Recording = Arc<Mutex<Option<Recorder>>>;
+
+#[derive(Clone)]
+pub struct CarClient {
+    requests: mpsc::Sender<Request>,
+    timeouts: Timeouts,
+    authenticated: bool,
+    recorder: Recording,
+    drive: Option<Arc<DriveSocket>>,
+}
+
+impl CarClient {
+    /// Connect to the car with the default timeouts, authenticating with `passphrase` if the car asks for it
+    pub async fn connect(host: &str, port: u16, passphrase: Option<&str>) -> Result<Self> {
+        Self::connect_with(host, port, passphrase, Timeouts::default()).await
+    }
+
+    /// Connect to the car, authenticating with `passphrase` if the car asks for it
+    ///
+    /// Must be called within a tokio runtime, the connection is kept by a task spawned on it.
+    pub async fn connect_with(host: &str, port: u16, passphrase: Option<&str>, timeouts: Timeouts) -> Result<Self> {
+        Self::connect_over(host, port, passphrase, timeouts, Transport::Tcp).await
+    }
+
+    /// Connect to the car, sending drive setpoints over `transport`
+    ///
+    /// Must be called within a tokio runtime, the connection is kept by a task spawned on it.
+    pub async fn connect_over(
+        host: &str,
+        port: u16,
+        passphrase: Option<&str>,
+        timeouts: Timeouts,
+        transport: Transport,
+    ) -> Result<Self> {
+        let handshake = async {
+            let (mut stream, challenge) = open(host, port).await?;
+            let session = match challenge {
+                Some(server_nonce) => Some(authenticate(&mut stream, passphrase, &server_nonce).await?),
+                None => None,
+            };
+            Ok::<_, Error>((stream, session))
+        };
+        let (stream, session) = time::timeout(timeouts.connect, handshake)
+            .await
+            .map_err(|_| Error::Timeout)??;
+
+        let authenticated = session.is_some();
+        let drive = match transport {
+            Transport::Tcp => None,
+            Transport::Udp => {
+                // The car only takes setpoints from the address of the command connection
+                let car = stream.peer_addr()?;
+                let local = if car.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(car).await?;
+                let key = session.as_ref().map(ClientSession::drive_key);
+                Some(Arc::new(DriveSocket {
+                    socket,
+                    sender: Mutex::new(drive::Sender::new(key)),
+                }))
+            }
+        };
+        let (requests, queue) = mpsc::channel(QUEUE_LEN);
+        let recorder = Recording::default();
+        tokio::spawn(run_connection(stream, session, queue, recorder.clone()));
+
+        Ok(Self {
+            requests,
+            timeouts,
+            authenticated,
+            recorder,
+            drive,
+        })
+    }
+
+    /// Check whether a car answers on `host`, returning whether it requires a key
+    pub async fn probe(host: &str, port: u16, timeout: Duration) -> Result<bool> {
+        let (_, challenge) = time::timeout(timeout, open(host, port))
+            .await
+            .map_err(|_| Error::Timeout)??;
+        Ok(challenge.is_some())
+    }
+
+    /// Whether commands are signed with a session key
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Whether the connection has ended, requests then fail with [`Error::Closed`]
+    pub fn is_closed(&self) -> bool {
+        self.requests.is_closed()
+    }
+
+    /// Send a command to the car and return its reply
+    pub async fn request(&self, command: CarCommand) -> Result<ServerMessage> {
+        let (reply, response) = oneshot::channel();
+        let exchange = async {
+            self.requests
+                .send(Request { command, reply })
+                .await
+                .map_err(|_| Error::Closed)?;
+            // The connection task drops the sender when the connection ends
+            response.await.map_err(|_| Error::Closed)?
+        };
+
+        match time::timeout(self.timeouts.request, exchange).await {
+            Err(_) => Err(Error::Timeout),
+            Ok(Ok(ServerMessage::Error(code))) => Err(Error::Rejected(code)),
+            Ok(reply) => reply,
+        }
+    }
+
+    /// Send a command the car acknowledges and wait for the acknowledgment
+    pub async fn send_command(&self, command: CarCommand) -> Result<()> {
+        match self.request(command).await? {
+            ServerMessage::Ack => Ok(()),
+            other => Err(Error::Unexpected(Box::new(other))),
+        }
+    }
+
+    // Command methods
+    pub async fn go_forward(&self, speed: u8) -> Result<()> {
+        debug!("Sending forward command with speed {}", speed);
+        self.send_command(CarCommand::Forward(speed)).await
+    }
+
+    pub async fn go_backward(&self, speed: u8) -> Result<()> {
+        debug!("Sending backward command with speed {}", speed);
+        self.send_command(CarCommand::Backward(speed)).await
+    }
+
+    pub async fn turn_left(&self, speed: u8) -> Result<()> {
+        debug!("Sending turn left command with speed {}", speed);
+        self.send_command(CarCommand::TurnLeft(speed)).await
+    }
+
+    pub async fn turn_right(&self, speed: u8) -> Result<()> {
+        debug!("Sending turn right command with speed {}", speed);
+        self.send_command(CarCommand::TurnRight(speed)).await
+    }
+
+    /// Drive with `throttle` and `steer` from -100 to 100, positive steer turns right
+    ///
+    /// Over [`Transport::Udp`] this returns once the setpoint is sent, nothing is acknowledged.
+    pub async fn drive(&self, throttle: i8, steer: i8) -> Result<()> {
+        debug!("Sending drive command with throttle {} steer {}", throttle, steer);
+        if let Some(drive) = &self.drive {
+            record(&self.recorder, |recorder| recorder.command(&CarCommand::Drive { throttle, steer }));
+            return self.send_setpoint(drive, throttle, steer).await;
+        }
+        self.send_command(CarCommand::Drive { throttle, steer }).await
+    }
+
+    /// Drive forward, rightward and clockwise at `vx`, `vy` and `omega` from -100 to 100
+    ///
+    /// Cars with mecanum wheels strafe, the others drop `vy` and drive like [`CarClient::drive`].
+    pub async fn holonomic(&self, vx: i8, vy: i8, omega: i8) -> Result<()> {
+        debug!("Sending holonomic command with vx {} vy {} omega {}", vx, vy, omega);
+        self.send_command(CarCommand::Holonomic { vx, vy, omega }).await
+    }
+
+    /// Turn on the spot by `degrees` measured with the car's IMU, positive turns right
+    ///
+    /// The car stops by itself once it has turned, refused with [`ErrorCode::NoImu`] on a car
+    /// without an IMU.
+    ///
+    /// [`ErrorCode::NoImu`]: shared::ErrorCode::NoImu
+    pub async fn turn_by(&self, degrees: i16) -> Result<()> {
+        debug!("Sending turn by {} degrees command", degrees);
+        self.send_command(CarCommand::TurnBy { degrees }).await
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        debug!("Sending stop command");
+        if let Some(drive) = &self.drive {
+            // Overtakes any setpoint still on its way, the command below makes sure the car stops
+            self.send_setpoint(drive, 0, 0).await?;
+        }
+        self.send_command(CarCommand::Stop).await
+    }
+
+    // Send a setpoint over the UDP drive channel
+    async fn send_setpoint(&self, drive: &DriveSocket, throttle: i8, steer: i8) -> Result<()> {
+        // The car closes the drive channel along with the connection
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        let setpoint = drive.sender.lock().unwrap().setpoint(throttle, steer);
+        let mut buf = [0u8; MAX_DATAGRAM_LEN];
+        let n = drive::encode(&setpoint, &mut buf)?;
+        drive.socket.send(&buf[..n]).await?;
+        Ok(())
+    }
+
+    pub async fn beep(&self, freq: u16, ms: u16) -> Result<()> {
+        debug!("Sending beep command at {} Hz for {} ms", freq, ms);
+        self.send_command(CarCommand::Beep { freq, ms }).await
+    }
+
+    pub async fn play_melody(&self, melody: Melody) -> Result<()> {
+        debug!("Sending play melody command {:?}", melody);
+        self.send_command(CarCommand::PlayMelody(melody)).await
+    }
+
+    pub async fn set_mode(&self, mode: DriveMode) -> Result<()> {
+        debug!("Sending set mode command {:?}", mode);
+        self.send_command(CarCommand::SetMode(mode)).await
+    }
+
+    /// Have the car run `mission` with its own timing, its progress shows in the telemetry
+    pub async fn run_mission(&self, mission: Mission) -> Result<()> {
+        debug!("Sending mission of {} steps", mission.iter().flatten().count());
+        self.send_command(CarCommand::RunMission(mission)).await
+    }
+
+    pub async fn telemetry(&self) -> Result<Telemetry> {
+        match self.request(CarCommand::GetTelemetry).await? {
+            ServerMessage::Telemetry(telemetry) => Ok(telemetry),
+            other => Err(Error::Unexpected(Box::new(other))),
+        }
+    }
+
+    /// Telemetry sampled every `period` on this connection
+    ///
+    /// A sample that times out is reported and sampling carries on, the stream ends after any
+    /// other error.
+    pub fn subscribe(&self, period: Duration) -> impl Stream<Item = Result<Telemetry>> + use<> {
+        let mut ticker = time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        futures_util::stream::unfold(Some((self.clone(), ticker)), |state| async move {
+            let (client, mut ticker) = state?;
+            ticker.tick().await;
+            let sample = client.telemetry().await;
+            let next = match &sample {
+                Ok(_) | Err(Error::Timeout) => Some((client, ticker)),
+                Err(_) => None,
+            };
+            Some((sample, next))
+        })
+    }
+
+    /// The car's runtime settings
+    pub async fn settings(&self) -> Result<Settings> {
+        match self.request(CarCommand::GetConfig).await? {
+            ServerMessage::Config(settings) => Ok(settings),
+            other => Err(Error::Unexpected(Box::new(other))),
+        }
+    }
+
+    /// Apply and store new runtime settings on the car
+    pub async fn set_settings(&self, settings: Settings) -> Result<()> {
+        debug!("Sending settings {:?}", settings);
+        self.send_command(CarCommand::SetConfig(settings)).await
+    }
+
+    /// The motor calibration stored on the car
+    pub async fn calibration(&self) -> Result<Calibration> {
+        match self.request(CarCommand::GetCalibration).await? {
+            ServerMessage::Calibration(calibration) => Ok(calibration),
+            other => Err(Error::Unexpected(Box::new(other))),
+        }
+    }
+
+    /// A page of the car's event log from entry `from` on
+    pub async fn read_log(&self, from: u32) -> Result<LogPage> {
+        match self.request(CarCommand::ReadLog { from }).await? {
+            ServerMessage::Log(page) => Ok(page),
+            other => Err(Error::Unexpected(Box::new(other))),
+        }
+    }
+
+    /// Every entry the car's event log still holds, oldest first
+    pub async fn event_log(&self) -> Result<Vec<LogEntry>> {
+        let mut entries = Vec::new();
+        let mut from = 0;
+        loop {
+            let page = self.read_log(from).await?;
+            let read = page.iter().flatten().count();
+            entries.extend(page.into_iter().flatten());
+            match entries.last() {
+                Some(last) if read == LOG_PAGE_LEN => from = last.seq + 1,
+                _ => return Ok(entries),
+            }
+        }
+    }
+
+    /// Correct the motors with `calibration`, storing it on the car if `store` is set
+    ///
+    /// A calibration that isn't stored lasts until the car restarts, or until the stored one is
+    /// set again. A gain over 100 is refused with [`ErrorCode::OutOfRange`].
+    ///
+    /// [`ErrorCode::OutOfRange`]: shared::ErrorCode::OutOfRange
+    pub async fn set_calibration(&self, calibration: Calibration, store: bool) -> Result<()> {
+        debug!("Sending calibration {:?}", calibration);
+        self.send_command(CarCommand::SetCalibration { calibration, store }).await
+    }
+
+    /// Switch the car to the limits of `profile`, refused with [`ErrorCode::ProfileLocked`] while
+    /// the profile is locked
+    ///
+    /// [`ErrorCode::ProfileLocked`]: shared::ErrorCode::ProfileLocked
+    pub async fn set_profile(&self, profile: Profile) -> Result<()> {
+        debug!("Sending set profile command {:?}", profile);
+        self.send_command(CarCommand::SetProfile(profile)).await
+    }
+
+    /// Lock the driving profile with the admin passphrase, or unlock it with the same passphrase
+    ///
+    /// Locking a locked profile is refused with [`ErrorCode::ProfileLocked`], and a car without a
+    /// key refuses locking like a wrong key, as the passphrase can't be sent wrapped. Unlocking
+    /// answers the car's challenge with a proof of the passphrase, and the car hangs up on a wrong
+    /// one, like on a wrong key.
+    ///
+    /// [`ErrorCode::ProfileLocked`]: shared::ErrorCode::ProfileLocked
+    pub async fn lock_profile(&self, admin_passphrase: &str, lock: bool) -> Result<()> {
+        debug!("Sending {} profile command", if lock { "lock" } else { "unlock" });
+        let admin = auth::key_from_passphrase(admin_passphrase);
+        if lock {
+            return self.send_command(CarCommand::LockProfile(ProfileLock::Lock { admin })).await;
+        }
+        let challenge = match self.request(CarCommand::GetProfileChallenge).await? {
+            ServerMessage::ProfileChallenge(challenge) => challenge,
+            other => return Err(Error::Unexpected(Box::new(other))),
+        };
+        let proof = auth::unlock_proof(&admin, &challenge);
+        self.send_command(CarCommand::LockProfile(ProfileLock::Unlock { proof })).await
+    }
+
+    /// Store a new key on the car, an empty passphrase disables authentication
+    ///
+    /// On an authenticated connection the key is wrapped with the session key. A car without a key
+    /// gets its first one in the clear, so only set it over a network you trust.
+    pub async fn set_key(&self, passphrase: &str) -> Result<()> {
+        debug!("Sending new pre-shared key");
+        let key = (!passphrase.is_empty()).then(|| auth::key_from_passphrase(passphrase));
+        self.send_command(CarCommand::SetAuthKey(key)).await
+    }
+}
+
+// Connect and read the car's greeting, with a challenge if it has a pre-shared key
+async fn open(host: &str, port: u16) -> Result<(TcpStream, Option<auth::Nonce>)> {
+    debug!("Attempting to connect to {}:{}", host, port);
+    let mut stream = TcpStream::connect((host, port)).await?;
+    match read_message(&mut stream).await? {
+        ServerMessage::Hello { challenge } => Ok((stream, challenge)),
+        other => Err(Error::Unexpected(Box::new(other))),
+    }
+}
+
+async fn authenticate(
+    stream: &mut TcpStream,
+    passphrase: Option<&str>,
+    server_nonce: &auth::Nonce,
+) -> Result<ClientSession> {
+    let passphrase = passphrase.ok_or(Error::KeyRequired)?;
+    let key = auth::key_from_passphrase(passphrase);
+    let (session, response) = ClientSession::respond(&key, server_nonce, rand::random());
+
+    write_message(stream, &ClientMessage::Auth(response)).await?;
+    match read_message(stream).await? {
+        ServerMessage::Authenticated => {
+            debug!("Authenticated with car");
+            Ok(session)
+        }
+        ServerMessage::Error(code) => Err(Error::Rejected(code)),
+        other => Err(Error::Unexpected(Box::new(other))),
+    }
+}
+
+async fn write_message(stream: &mut TcpStream, message: &ClientMessage) -> Result<()> {
+    let mut buf = [0u8; FRAME_LEN];
+    let n = frame::encode(message, &mut buf)?;
+    stream.write_all(&buf[..n]).await?;
+    Ok(())
+}
+
+async fn read_message(stream: &mut TcpStream) -> Result<ServerMessage> {
+    let mut header = [0u8; frame::HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+
+    let mut payload = vec![0u8; frame::payload_len(header)];
+    stream.read_exact(&mut payload).await?;
+
+    Ok(frame::decode(&payload)?)
+}
+
+/// Write requests as they come and hand each reply to the oldest request still waiting
+async fn run_connection(
+    mut stream: TcpStream,
+    mut session: Option<ClientSession>,
+    mut queue: mpsc::Receiver<Request>,
+    recorder: Recording,
+) {
+    let mut waiting: VecDeque<oneshot::Sender<Result<ServerMessage>>> = VecDeque::new();
+    let mut reader = FrameReader::<FRAME_LEN>::new();
+
+    loop {
+        tokio::select! {
+            request = queue.recv() => {
+                // Every handle is gone
+                let Some(Request { command, reply }) = request else {
+                    break;
+                };
+
+                record(&recorder, |recorder| recorder.command(&command));
+                // Sign in the order the commands go out, the car rejects sequence numbers going back
+                let message = match &mut session {
+                    Some(session) => ClientMessage::Signed(session.sign(command)),
+                    None => ClientMessage::Command(command),
+                };
+                match write_message(&mut stream, &message).await {
+                    Ok(()) => waiting.push_back(reply),
+                    Err(err) => {
+                        let _ = reply.send(Err(err));
+                        break;
+                    }
+                }
+            }
+            read = stream.read(reader.space()) => {
+                match read {
+                    Ok(0) => {
+                        debug!("Car closed the connection");
+                        break;
+                    }
+                    Ok(n) => reader.filled(n),
+                    Err(err) => {
+                        warn!("Connection to car failed: {}", err);
+                        break;
+                    }
+                }
+
+                while let Some(message) = reader.next_message::<ServerMessage>() {
+                    let Some(reply) = waiting.pop_front() else {
+                        warn!("Dropping reply nobody asked for: {:?}", message);
+                        continue;
+                    };
+                    // A request that timed out still takes its place in the order, its reply is dropped
+                    match message {
+                        Ok(message) => {
+                            if let ServerMessage::Telemetry(telemetry) = &message {
+                                record(&recorder, |recorder| recorder.telemetry(telemetry));
+                            }
+                            let _ = reply.send(Ok(message));
+                        }
+                        Err(err) => {
+                            // The stream can't be resynchronised after a bad frame
+                            let _ = reply.send(Err(err.into()));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // Requests still waiting see the connection close when their senders are dropped
+}
+
+// Add to the session being recorded, a recording that fails to write is stopped
+fn record(recorder: &Recording, write: impl FnOnce(&mut Recorder) -> Result<()>) {
+    let mut recorder = recorder.lock().unwrap();
+    if let Some(active) = recorder.as_mut()
+        && let Err(err) = write(active)
+    {
+        warn!("Recording stopped: {}", err);
+        *recorder = None;
+    }
+}
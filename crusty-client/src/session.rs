@@ -0,0 +1,231 @@
+//! Recording and replaying driving sessions
+//!
+//! A [`Recorder`] given to [`CarClient::start_recording`] logs every command the connection
+//! sends and every telemetry sample it receives, with the milliseconds since recording started.
+//! Session files use the car's own framing: each [`Record`] is a length-prefixed bincode frame,
+//! so they stay compact and are read back with [`read`] or [`load`].
+//!
+//! [`CarClient::replay`] sends the driving commands of a session to the car again with their
+//! original timing, and [`write_telemetry_csv`] exports the samples for a spreadsheet.
+
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bincode::{Decode, Encode};
+use log::debug;
+use shared::config::Profile;
+use shared::frame;
+use shared::mission::MissionStatus;
+use shared::{CarCommand, DriveMode, LightTarget, Telemetry};
+use tokio::time;
+
+use crate::{CarClient, FRAME_LEN, Result};
+
+/// One entry of a session, stamped with the milliseconds since recording started
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub enum Record {
+    Command { at_ms: u32, command: CarCommand },
+    Telemetry { at_ms: u32, telemetry: Telemetry },
+}
+
+impl Record {
+    pub fn at_ms(&self) -> u32 {
+        match self {
+            Record::Command { at_ms, .. } | Record::Telemetry { at_ms, .. } => *at_ms,
+        }
+    }
+}
+
+/// Writes the records of a session as they happen
+pub struct Recorder {
+    out: Box<dyn Write + Send>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Record into a new session file at `path`
+    ///
+    /// Records are written as they happen, a session cut short keeps everything up to then.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Box::new(out),
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn command(&mut self, command: &CarCommand) -> Result<()> {
+        self.write(&Record::Command {
+            at_ms: self.elapsed_ms(),
+            command: command.clone(),
+        })
+    }
+
+    pub(crate) fn telemetry(&mut self, telemetry: &Telemetry) -> Result<()> {
+        self.write(&Record::Telemetry {
+            at_ms: self.elapsed_ms(),
+            telemetry: telemetry.clone(),
+        })
+    }
+
+    /// Write out everything recorded so far
+    pub fn finish(mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+
+    fn elapsed_ms(&self) -> u32 {
+        self.started.elapsed().as_millis().try_into().unwrap_or(u32::MAX)
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let mut buf = [0u8; FRAME_LEN];
+        let n = frame::encode(record, &mut buf)?;
+        Ok(self.out.write_all(&buf[..n])?)
+    }
+}
+
+/// Read the records of a session
+pub fn read(mut input: impl Read) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    loop {
+        let mut header = [0u8; frame::HEADER_LEN];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(records),
+            Err(err) => return Err(err.into()),
+        }
+        let mut payload = vec![0u8; frame::payload_len(header)];
+        input.read_exact(&mut payload)?;
+        records.push(frame::decode(&payload)?);
+    }
+}
+
+/// Read the records of the session file at `path`
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Record>> {
+    read(BufReader::new(File::open(path)?))
+}
+
+/// Whether replaying `command` drives the car
+///
+/// Queries are left out, and so are the settings and keys the car stores, a replay shouldn't
+/// change the car's configuration. Missions are replayed, the car times their steps itself.
+fn is_replayed(command: &CarCommand) -> bool {
+    matches!(
+        command,
+        CarCommand::Forward(_)
+            | CarCommand::Backward(_)
+            | CarCommand::TurnLeft(_)
+            | CarCommand::TurnRight(_)
+            | CarCommand::Stop
+            | CarCommand::Beep { .. }
+            | CarCommand::PlayMelody(_)
+            | CarCommand::SetMode(_)
+            | CarCommand::Drive { .. }
+            | CarCommand::Holonomic { .. }
+            | CarCommand::RunMission(_)
+    )
+}
+
+impl CarClient {
+    /// Record the commands sent on this connection and the telemetry received, replacing any recording
+    pub fn start_recording(&self, recorder: Recorder) {
+        debug!("Recording session");
+        *self.recorder.lock().unwrap() = Some(recorder);
+    }
+
+    /// Stop recording, returning the recorder to [`Recorder::finish`] or hand to another connection
+    pub fn stop_recording(&self) -> Option<Recorder> {
+        self.recorder.lock().unwrap().take()
+    }
+
+    /// Send the driving commands of `records` to the car with their original timing
+    ///
+    /// `speed` scales the timing, 2 replays twice as fast. The car is stopped at the end, and
+    /// when a command fails.
+    pub async fn replay(&self, records: &[Record], speed: f32) -> Result<()> {
+        let speed = speed.max(0.01);
+        let commands: Vec<_> = records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Command { at_ms, command } if is_replayed(command) => Some((*at_ms, command)),
+                _ => None,
+            })
+            .collect();
+        debug!("Replaying {} commands at {}x", commands.len(), speed);
+
+        let Some(&(first_ms, _)) = commands.first() else {
+            return Ok(());
+        };
+        let started = time::Instant::now();
+        for (at_ms, command) in commands {
+            let offset = Duration::from_millis((at_ms - first_ms) as u64).div_f32(speed);
+            time::sleep_until(started + offset).await;
+            if let Err(err) = self.send_command(command.clone()).await {
+                let _ = self.stop().await;
+                return Err(err);
+            }
+        }
+        self.stop().await
+    }
+}
+
+/// Write the telemetry samples of `records` as CSV with a header row
+pub fn write_telemetry_csv(records: &[Record], mut out: impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "at_ms,mode,light_left,light_right,distance_cm,loop_latency_us,motor_left,motor_right,mission,profile,\
+         profile_locked,heading_deg,yaw_rate_dps,accel_x_g,accel_y_g,accel_z_g"
+    )?;
+    for record in records {
+        let Record::Telemetry { at_ms, telemetry } = record else {
+            continue;
+        };
+        let mode = match telemetry.mode {
+            DriveMode::Manual => "manual",
+            DriveMode::LightFollow(LightTarget::Brighter) => "follow_light",
+            DriveMode::LightFollow(LightTarget::Darker) => "avoid_light",
+        };
+        let distance = telemetry.distance_cm.map(|cm| cm.to_string()).unwrap_or_default();
+        let mission = match telemetry.mission {
+            MissionStatus::Idle => "idle".into(),
+            MissionStatus::Running { step } => format!("step {}", step + 1),
+            MissionStatus::Done => "done".into(),
+            MissionStatus::Aborted => "aborted".into(),
+        };
+        let profile = match telemetry.profile {
+            Profile::Kid => "kid",
+            Profile::Indoor => "indoor",
+            Profile::Race => "race",
+        };
+        // Left empty without an IMU, like the distance without an echo
+        let imu = match telemetry.imu {
+            Some(imu) => format!(
+                "{},{},{},{},{}",
+                imu.heading_deg, imu.yaw_rate_dps, imu.accel_g[0], imu.accel_g[1], imu.accel_g[2]
+            ),
+            None => ",,,,".into(),
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            at_ms,
+            mode,
+            telemetry.light_left,
+            telemetry.light_right,
+            distance,
+            telemetry.loop_latency_us,
+            telemetry.motor_left,
+            telemetry.motor_right,
+            mission,
+            profile,
+            telemetry.profile_locked,
+            imu
+        )?;
+    }
+    Ok(())
+}
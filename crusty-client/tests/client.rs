@@ -0,0 +1,518 @@
+//! Runs the client against the car's own command handling, served over local TCP sockets
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crusty_client::event_log;
+use crusty_client::session::{self, Record, Recorder};
+use crusty_client::{CarClient, Error, Timeouts, Transport};
+use crusty_core::drive::DriveChannel;
+use crusty_core::motion::{self, Motion, MotionRequest, Source};
+use crusty_core::server::{self, Platform};
+use embedded_io_adapters::tokio_1::FromTokio;
+use futures_util::StreamExt;
+use shared::config::{Calibration, CarConfig, Profile, WheelCalibration};
+use shared::drive::MAX_DATAGRAM_LEN;
+use shared::event_log::{Event, LogEntry, LogPage, LOG_PAGE_LEN};
+use shared::frame;
+use shared::{auth, CarCommand, ClientMessage, DriveMode, ErrorCode, ImuReading, Melody, ServerMessage, Telemetry};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Records what the commands and setpoints do and numbers the telemetry samples in `light_left`
+#[derive(Clone, Default)]
+struct FakeCar {
+    motions: Arc<Mutex<Vec<MotionRequest>>>,
+    samples: Arc<AtomicU16>,
+    drive: Arc<Mutex<DriveChannel<IpAddr>>>,
+    calibration: Arc<Mutex<Calibration>>,
+    events: Arc<Mutex<Vec<LogEntry>>>,
+    imu: Arc<Mutex<Option<ImuReading>>>,
+}
+
+impl Platform for FakeCar {
+    async fn motion(&mut self, request: MotionRequest) {
+        self.motions.lock().unwrap().push(request);
+    }
+
+    fn set_mode(&mut self, _mode: DriveMode) {}
+
+    fn telemetry(&self) -> Telemetry {
+        Telemetry {
+            light_left: self.samples.fetch_add(1, Ordering::Relaxed),
+            imu: *self.imu.lock().unwrap(),
+            ..Telemetry::new()
+        }
+    }
+
+    fn beep(&mut self, _freq: u16, _ms: u16) {}
+
+    fn play(&mut self, _melody: Melody) {}
+
+    fn store_config(&mut self, _config: &CarConfig) -> bool {
+        true
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        *self.calibration.lock().unwrap() = calibration;
+    }
+
+    fn open_drive_channel(&mut self, key: Option<auth::Key>) {
+        self.drive.lock().unwrap().open(Ipv4Addr::LOCALHOST.into(), key);
+    }
+
+    fn record(&mut self, event: Event) {
+        let mut events = self.events.lock().unwrap();
+        let seq = events.len() as u32;
+        events.push(LogEntry { seq, boot: 0, uptime_ms: 0, event });
+    }
+
+    async fn read_log(&mut self, from: u32) -> LogPage {
+        let mut page = [None; LOG_PAGE_LEN];
+        let events = self.events.lock().unwrap();
+        for (slot, entry) in page.iter_mut().zip(events.iter().skip(from as usize)) {
+            *slot = Some(*entry);
+        }
+        page
+    }
+}
+
+/// Serve the command port on a free local port, returning the port
+async fn start_car(car: FakeCar, key: Option<&str>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let socket = UdpSocket::bind(("127.0.0.1", port)).await.unwrap();
+    let mut config = CarConfig {
+        auth_key: key.map(auth::key_from_passphrase),
+        ..CarConfig::default()
+    };
+
+    let mut served = car.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut socket = FromTokio::new(stream);
+            server::serve(&mut socket, rand::random(), &mut config, &mut served).await;
+            served.drive.lock().unwrap().close();
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut datagram = [0; MAX_DATAGRAM_LEN];
+        while let Ok((n, from)) = socket.recv_from(&mut datagram).await {
+            let motion = car.drive.lock().unwrap().receive(&from.ip(), &datagram[..n]);
+            if let Some(motion) = motion {
+                car.motions.lock().unwrap().push(MotionRequest::Set { source: Source::Network, motion });
+            }
+        }
+    });
+    port
+}
+
+/// A port that greets like the car and then never answers, or doesn't even greet
+async fn start_silent_car(greet: bool) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        if greet {
+            let mut buf = [0; frame::HEADER_LEN + frame::MAX_FRAME_LEN];
+            let n = frame::encode(&ServerMessage::Hello { challenge: None }, &mut buf).unwrap();
+            stream.write_all(&buf[..n]).await.unwrap();
+        }
+        let mut sink = [0; 64];
+        while stream.read(&mut sink).await.is_ok_and(|n| n > 0) {}
+    });
+    port
+}
+
+const SHORT: Timeouts = Timeouts {
+    connect: Duration::from_millis(200),
+    request: Duration::from_millis(200),
+};
+
+#[tokio::test]
+async fn pipelined_requests_get_their_own_replies() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+
+    let other = client.clone();
+    let (first, settings, stopped, second) = tokio::join!(
+        client.telemetry(),
+        other.settings(),
+        client.stop(),
+        other.telemetry(),
+    );
+
+    assert_eq!(first.unwrap().light_left, 0);
+    assert_eq!(settings.unwrap(), CarConfig::default().settings());
+    stopped.unwrap();
+    assert_eq!(second.unwrap().light_left, 1);
+    assert_eq!(
+        car.motions.lock().unwrap()[..],
+        [MotionRequest::Set {
+            source: Source::Network,
+            motion: Motion::Stop
+        }]
+    );
+}
+
+#[tokio::test]
+async fn subscription_streams_telemetry() {
+    let port = start_car(FakeCar::default(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+
+    let samples: Vec<_> = client.subscribe(Duration::from_millis(10)).take(3).collect().await;
+    let counters: Vec<_> = samples.into_iter().map(|sample| sample.unwrap().light_left).collect();
+    assert_eq!(counters, [0, 1, 2]);
+}
+
+#[tokio::test]
+async fn key_is_required_and_checked() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), Some("open sesame")).await;
+
+    let result = CarClient::connect("127.0.0.1", port, None).await;
+    assert!(matches!(result, Err(Error::KeyRequired)));
+
+    let result = CarClient::connect("127.0.0.1", port, Some("wrong")).await;
+    assert!(matches!(result, Err(Error::Rejected(ErrorCode::AuthFailed))));
+
+    let client = CarClient::connect("127.0.0.1", port, Some("open sesame")).await.unwrap();
+    assert!(client.is_authenticated());
+    client.drive(40, 0).await.unwrap();
+    client.drive(40, 0).await.unwrap();
+    let drives = car.motions.lock().unwrap().iter().filter(|request| matches!(request, MotionRequest::Set { .. })).count();
+    assert_eq!(drives, 2);
+
+    // The new key arrives wrapped and still works
+    client.set_key("new key").await.unwrap();
+    drop(client);
+    let result = CarClient::connect("127.0.0.1", port, Some("open sesame")).await;
+    assert!(matches!(result, Err(Error::Rejected(ErrorCode::AuthFailed))));
+    CarClient::connect("127.0.0.1", port, Some("new key")).await.unwrap();
+}
+
+#[tokio::test]
+async fn leaving_stops_the_car() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+    client.drive(40, 0).await.unwrap();
+    drop(client);
+
+    // No deadman is set, so only the connection ending stops it
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(car.motions.lock().unwrap().last(), Some(&MotionRequest::Release(Source::Network)));
+}
+
+#[tokio::test]
+async fn udp_setpoints_skip_the_command_stream() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), Some("open sesame")).await;
+    let client = CarClient::connect_over("127.0.0.1", port, Some("open sesame"), Timeouts::default(), Transport::Udp)
+        .await
+        .unwrap();
+
+    client.drive(30, 0).await.unwrap();
+    client.drive(30, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let set = |motion| MotionRequest::Set { source: Source::Network, motion };
+    assert_eq!(
+        car.motions.lock().unwrap()[..],
+        [set(motion::arcade(30, 0)), set(motion::arcade(30, 10))]
+    );
+
+    // The stop goes both ways, and stops the car either way
+    client.stop().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(car.motions.lock().unwrap()[2..], [set(Motion::Stop), set(Motion::Stop)]);
+}
+
+#[tokio::test]
+async fn calibrations_can_be_tried_before_storing() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+
+    let trimmed = Calibration {
+        rear_right: WheelCalibration {
+            gain: 90,
+            ..WheelCalibration::new()
+        },
+        ..Calibration::new()
+    };
+    client.set_calibration(trimmed, false).await.unwrap();
+    assert_eq!(*car.calibration.lock().unwrap(), trimmed);
+    assert_eq!(client.calibration().await.unwrap(), Calibration::new());
+
+    client.set_calibration(trimmed, true).await.unwrap();
+    assert_eq!(client.calibration().await.unwrap(), trimmed);
+
+    // A gain over 100 would drive the wheel faster than the profile allows
+    let boosted = Calibration {
+        front_left: WheelCalibration {
+            gain: 150,
+            ..WheelCalibration::new()
+        },
+        ..Calibration::new()
+    };
+    assert!(matches!(client.set_calibration(boosted, false).await, Err(Error::Rejected(ErrorCode::OutOfRange))));
+    assert_eq!(*car.calibration.lock().unwrap(), trimmed);
+}
+
+#[tokio::test]
+async fn locked_profiles_need_the_admin_key() {
+    // The admin key would cross the network in the clear to a car without a key
+    let port = start_car(FakeCar::default(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+    assert!(matches!(client.lock_profile("grown-up", true).await, Err(Error::Rejected(ErrorCode::AuthFailed))));
+
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), Some("secret")).await;
+    let client = CarClient::connect("127.0.0.1", port, Some("secret")).await.unwrap();
+
+    client.set_profile(Profile::Kid).await.unwrap();
+    assert_eq!(car.motions.lock().unwrap()[..], [MotionRequest::Limits(Profile::Kid.limits())]);
+    client.lock_profile("grown-up", true).await.unwrap();
+    let telemetry = client.telemetry().await.unwrap();
+    assert_eq!((telemetry.profile, telemetry.profile_locked), (Profile::Kid, true));
+    assert!(matches!(client.lock_profile("kid", true).await, Err(Error::Rejected(ErrorCode::ProfileLocked))));
+    assert!(matches!(client.set_profile(Profile::Race).await, Err(Error::Rejected(ErrorCode::ProfileLocked))));
+    // A calibration could drive the motors faster than the profile allows
+    let flat_out = WheelCalibration {
+        min_duty: 100,
+        ..WheelCalibration::new()
+    };
+    let calibration = Calibration {
+        front_left: flat_out,
+        ..Calibration::new()
+    };
+    let result = client.set_calibration(calibration, false).await;
+    assert!(matches!(result, Err(Error::Rejected(ErrorCode::ProfileLocked))));
+    assert_eq!(*car.calibration.lock().unwrap(), Calibration::new());
+
+    // A wrong admin key ends the connection, like a wrong car key
+    assert!(matches!(client.lock_profile("kid", false).await, Err(Error::Rejected(ErrorCode::AuthFailed))));
+    let client = CarClient::connect("127.0.0.1", port, Some("secret")).await.unwrap();
+    assert!(client.telemetry().await.unwrap().profile_locked);
+
+    client.lock_profile("grown-up", false).await.unwrap();
+    client.set_profile(Profile::Race).await.unwrap();
+    assert_eq!(client.telemetry().await.unwrap().profile, Profile::Race);
+    client.set_calibration(calibration, false).await.unwrap();
+}
+
+#[tokio::test]
+async fn turning_by_an_angle_needs_an_imu() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+
+    assert!(matches!(client.turn_by(90).await, Err(Error::Rejected(ErrorCode::NoImu))));
+    let reading = ImuReading {
+        heading_deg: 12.5,
+        yaw_rate_dps: 0.0,
+        accel_g: [0.0, 0.0, 1.0],
+    };
+    *car.imu.lock().unwrap() = Some(reading);
+    assert_eq!(client.telemetry().await.unwrap().imu, Some(reading));
+    client.turn_by(-90).await.unwrap();
+    assert_eq!(car.motions.lock().unwrap()[..], [MotionRequest::TurnBy(-90)]);
+}
+
+#[tokio::test]
+async fn event_log_is_read_page_by_page() {
+    let mut car = FakeCar::default();
+    for _ in 0..LOG_PAGE_LEN + 8 {
+        car.record(Event::Failsafe);
+    }
+    let port = start_car(car.clone(), Some("secret")).await;
+    assert!(matches!(CarClient::connect("127.0.0.1", port, Some("wrong")).await, Err(Error::Rejected(ErrorCode::AuthFailed))));
+    let client = CarClient::connect("127.0.0.1", port, Some("secret")).await.unwrap();
+
+    let entries = client.event_log().await.unwrap();
+    assert_eq!(entries.len(), LOG_PAGE_LEN + 12);
+    assert!(entries.iter().enumerate().all(|(i, entry)| entry.seq == i as u32));
+    let events: Vec<_> = entries[LOG_PAGE_LEN + 8..].iter().map(|entry| entry.event).collect();
+    assert_eq!(
+        events,
+        [
+            Event::Connected,
+            Event::Rejected {
+                code: ErrorCode::AuthFailed,
+                count: 1
+            },
+            Event::Disconnected,
+            Event::Connected
+        ]
+    );
+
+    let mut csv = Vec::new();
+    event_log::write_csv(&entries[LOG_PAGE_LEN + 9..LOG_PAGE_LEN + 10], &mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        format!("seq,boot,uptime_ms,event\n{},0,0,rejected a message: AuthFailed\n", LOG_PAGE_LEN + 9)
+    );
+}
+
+#[tokio::test]
+async fn rejections_are_counted_per_connection() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), Some("secret")).await;
+
+    // Plain commands to a car with a key are refused without hanging up
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0; frame::HEADER_LEN + frame::MAX_FRAME_LEN];
+    let n = frame::encode(&ClientMessage::Command(CarCommand::Stop), &mut buf).unwrap();
+    for _ in 0..20 {
+        stream.write_all(&buf[..n]).await.unwrap();
+    }
+    let mut reader = frame::FrameReader::<{ frame::HEADER_LEN + frame::MAX_FRAME_LEN }>::new();
+    let mut replies = 0;
+    while replies < 21 {
+        let n = stream.read(reader.space()).await.unwrap();
+        reader.filled(n);
+        while reader.next_message::<ServerMessage>().is_some() {
+            replies += 1;
+        }
+    }
+    drop(stream);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let events: Vec<_> = car.events.lock().unwrap().iter().map(|entry| entry.event).collect();
+    assert_eq!(
+        events,
+        [
+            Event::Connected,
+            Event::Rejected {
+                code: ErrorCode::Unauthorized,
+                count: 20
+            },
+            Event::Disconnected
+        ]
+    );
+}
+
+#[tokio::test]
+async fn silent_cars_time_out() {
+    let port = start_silent_car(false).await;
+    let result = CarClient::connect_with("127.0.0.1", port, None, SHORT).await;
+    assert!(matches!(result, Err(Error::Timeout)));
+
+    let port = start_silent_car(true).await;
+    let client = CarClient::connect_with("127.0.0.1", port, None, SHORT).await.unwrap();
+    assert!(matches!(client.stop().await, Err(Error::Timeout)));
+}
+
+#[tokio::test]
+async fn requests_fail_once_the_car_hangs_up() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; frame::HEADER_LEN + frame::MAX_FRAME_LEN];
+        let n = frame::encode(&ServerMessage::Hello { challenge: None }, &mut buf).unwrap();
+        stream.write_all(&buf[..n]).await.unwrap();
+    });
+
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(matches!(client.stop().await, Err(Error::Closed | Error::Io(_))));
+    assert!(matches!(client.stop().await, Err(Error::Closed)));
+    assert!(client.is_closed());
+}
+
+#[tokio::test]
+async fn sessions_are_recorded_and_replayed() {
+    let car = FakeCar::default();
+    let port = start_car(car.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+    let path = std::env::temp_dir().join(format!("crusty-session-{port}.bin"));
+
+    client.start_recording(Recorder::create(&path).unwrap());
+    client.drive(50, 0).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.telemetry().await.unwrap();
+    client.drive(50, -20).await.unwrap();
+    client.stop().await.unwrap();
+    client.stop_recording().unwrap().finish().unwrap();
+    client.stop().await.unwrap();
+
+    let records = session::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let commands: Vec<_> = records
+        .iter()
+        .filter_map(|record| match record {
+            Record::Command { command, .. } => Some(command.clone()),
+            Record::Telemetry { .. } => None,
+        })
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            CarCommand::Drive { throttle: 50, steer: 0 },
+            CarCommand::GetTelemetry,
+            CarCommand::Drive { throttle: 50, steer: -20 },
+            CarCommand::Stop
+        ]
+    );
+    assert!(records[1].at_ms() >= 100);
+    assert!(records.windows(2).all(|pair| pair[0].at_ms() <= pair[1].at_ms()));
+
+    // A sample from a locked car with an IMU fills in the rest
+    let with_imu = Record::Telemetry {
+        at_ms: 200,
+        telemetry: Telemetry {
+            profile: Profile::Kid,
+            profile_locked: true,
+            imu: Some(ImuReading {
+                heading_deg: -12.5,
+                yaw_rate_dps: 3.0,
+                accel_g: [0.0, 0.25, 1.0],
+            }),
+            ..Telemetry::new()
+        },
+    };
+    let mut csv = Vec::new();
+    session::write_telemetry_csv(&[&records[..], &[with_imu]].concat(), &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("at_ms,mode,"));
+    assert!(lines[0].ends_with(",profile,profile_locked,heading_deg,yaw_rate_dps,accel_x_g,accel_y_g,accel_z_g"));
+    assert!(lines[1].ends_with(",manual,0,0,,0,0,0,idle,race,false,,,,,"));
+    assert_eq!(lines[2], "200,manual,0,0,,0,0,0,idle,kid,true,-12.5,3,0,0.25,1");
+
+    // Twice as fast, the queries are left out and the car stops at the end
+    let replayed = FakeCar::default();
+    let port = start_car(replayed.clone(), None).await;
+    let client = CarClient::connect("127.0.0.1", port, None).await.unwrap();
+    let started = std::time::Instant::now();
+    client.replay(&records, 2.0).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert_eq!(replayed.motions.lock().unwrap().len(), 4);
+    assert_eq!(replayed.samples.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn blocking_client_works_without_a_runtime() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let car = FakeCar::default();
+    let port = runtime.block_on(start_car(car.clone(), None));
+
+    let client = crusty_client::blocking::CarClient::connect("127.0.0.1", port, None).unwrap();
+    client.go_forward(50).unwrap();
+    assert_eq!(client.telemetry().unwrap().light_left, 0);
+
+    let counters: Vec<_> = client
+        .subscribe(Duration::from_millis(10))
+        .take(2)
+        .map(|sample| sample.unwrap().light_left)
+        .collect();
+    assert_eq!(counters, [1, 2]);
+    assert_eq!(car.motions.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,16 @@
+//! Gzips the controller page so the car can serve it as is
+
+use std::io::Write;
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+fn main() {
+    println!("cargo::rerun-if-changed=web/index.html");
+    let page = std::fs::read("web/index.html").expect("reading web/index.html");
+    let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+    gz.write_all(&page).unwrap();
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("index.html.gz");
+    std::fs::write(out, gz.finish().unwrap()).unwrap();
+}
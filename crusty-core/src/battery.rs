@@ -0,0 +1,73 @@
+//! Low battery warning from voltage readings
+//!
+//! The motors pull the voltage down for a moment when they start, so the battery only counts as
+//! low after a few readings in a row below the threshold, and it has to come back up clear of the
+//! threshold before it can warn again.
+
+/// Readings in a row below the threshold before the battery counts as low
+pub const LOW_READINGS: u8 = 3;
+
+/// How far above the threshold the voltage has to recover before the next warning, in mV
+pub const RECOVERY_MV: u16 = 200;
+
+/// Decides when the battery is low from a reading every few seconds
+pub struct BatteryMonitor {
+    low_mv: u16,
+    low_readings: u8,
+    warned: bool,
+}
+
+impl BatteryMonitor {
+    /// A monitor warning about a battery below `low_mv`
+    pub const fn new(low_mv: u16) -> Self {
+        Self {
+            low_mv,
+            low_readings: 0,
+            warned: false,
+        }
+    }
+
+    /// Take a reading of `mv`, `true` when the battery has just become low
+    pub fn update(&mut self, mv: u16) -> bool {
+        if mv >= self.low_mv {
+            self.low_readings = 0;
+            if mv >= self.low_mv.saturating_add(RECOVERY_MV) {
+                self.warned = false;
+            }
+            return false;
+        }
+        self.low_readings = self.low_readings.saturating_add(1);
+        if self.low_readings < LOW_READINGS || self.warned {
+            return false;
+        }
+        self.warned = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_once_per_discharge() {
+        let mut monitor = BatteryMonitor::new(4500);
+        // A dip while the motors start isn't a low battery
+        assert!(!monitor.update(4400));
+        assert!(!monitor.update(4400));
+        assert!(!monitor.update(4800));
+
+        assert!(!monitor.update(4400));
+        assert!(!monitor.update(4400));
+        assert!(monitor.update(4400));
+        assert!(!monitor.update(4300));
+
+        // Creeping back over the threshold once the motors stop doesn't warn again
+        assert!(!monitor.update(4600));
+        assert!((0..LOW_READINGS).all(|_| !monitor.update(4400)));
+
+        // A charged battery does
+        assert!(!monitor.update(5000));
+        assert!((0..LOW_READINGS).any(|_| monitor.update(4400)));
+    }
+}
@@ -0,0 +1,302 @@
+//! Four wheel car built from any motor driver
+//!
+//! The car only needs to set the direction and speed of each motor, [`Motor`] abstracts that.
+//! [`PwmPair`] drives H-bridges with both inputs on PWM, like the Freenove board, and [`DirPwm`]
+//! drivers with a direction pin and a PWM enable, like the TB6612 or an L298 with its jumpers off.
+//! The car corrects every speed with each wheel's [`WheelCalibration`](shared::config::WheelCalibration) before it reaches the motor.
+//! Tank style motion drives both wheels of a side alike, mecanum wheels are each driven on their
+//! own with [`Car::drive_wheels`].
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::pwm::SetDutyCycle;
+use shared::config::Calibration;
+
+use crate::motion::Wheels;
+
+/// One motor and its driver
+pub trait Motor {
+    type Error: core::fmt::Debug;
+
+    /// Signed speed from -100 (full backward) to 100 (full forward), 0 stops the motor
+    fn drive(&mut self, speed: i8) -> Result<(), Self::Error>;
+}
+
+/// H-bridge with a PWM output on each input, the motor turns towards the input being driven
+pub struct PwmPair<A, B> {
+    in1: A,
+    in2: B,
+}
+
+impl<A, B> PwmPair<A, B> {
+    /// Driving `in1` turns the motor forward, `in2` backward
+    pub fn new(in1: A, in2: B) -> Self {
+        Self { in1, in2 }
+    }
+}
+
+impl<A, B> Motor for PwmPair<A, B>
+where
+    A: SetDutyCycle,
+    B: SetDutyCycle<Error = A::Error>,
+{
+    type Error = A::Error;
+
+    fn drive(&mut self, speed: i8) -> Result<(), Self::Error> {
+        let duty = speed.unsigned_abs().min(100);
+        if speed > 0 {
+            self.in2.set_duty_cycle_fully_off()?;
+            self.in1.set_duty_cycle_percent(duty)
+        } else if speed < 0 {
+            self.in1.set_duty_cycle_fully_off()?;
+            self.in2.set_duty_cycle_percent(duty)
+        } else {
+            self.in1.set_duty_cycle_fully_off()?;
+            self.in2.set_duty_cycle_fully_off()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DirPwmError<P, D> {
+    Pwm(P),
+    Direction(D),
+}
+
+/// Driver with a direction pin and a PWM speed input
+pub struct DirPwm<P, D> {
+    pwm: P,
+    dir: D,
+}
+
+impl<P, D> DirPwm<P, D> {
+    /// `dir` is driven high to turn the motor forward
+    pub fn new(pwm: P, dir: D) -> Self {
+        Self { pwm, dir }
+    }
+}
+
+impl<P: SetDutyCycle, D: OutputPin> Motor for DirPwm<P, D> {
+    type Error = DirPwmError<P::Error, D::Error>;
+
+    fn drive(&mut self, speed: i8) -> Result<(), Self::Error> {
+        // Stop before flipping the direction so the motor never briefly runs the wrong way
+        self.pwm.set_duty_cycle_fully_off().map_err(DirPwmError::Pwm)?;
+        if speed == 0 {
+            return Ok(());
+        }
+
+        if speed > 0 {
+            self.dir.set_high()
+        } else {
+            self.dir.set_low()
+        }
+        .map_err(DirPwmError::Direction)?;
+
+        self.pwm
+            .set_duty_cycle_percent(speed.unsigned_abs().min(100))
+            .map_err(DirPwmError::Pwm)
+    }
+}
+
+/// The entire car with four wheels
+pub struct Car<M> {
+    front_left: M,
+    front_right: M,
+    rear_left: M,
+    rear_right: M,
+    calibration: Calibration,
+}
+
+impl<M: Motor> Car<M> {
+    pub fn new(front_left: M, front_right: M, rear_left: M, rear_right: M) -> Self {
+        Self {
+            front_left,
+            front_right,
+            rear_left,
+            rear_right,
+            calibration: Calibration::new(),
+        }
+    }
+
+    /// Correct the wheels with `calibration` from the next speed set on
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Move the car forward
+    pub fn forward(&mut self, speed: u8) -> Result<(), M::Error> {
+        info!("Moving car forward at {}% speed", speed);
+        let speed = percent(speed);
+        self.sides(speed, speed)
+    }
+
+    /// Move the car backward
+    pub fn backward(&mut self, speed: u8) -> Result<(), M::Error> {
+        info!("Moving car backward at {}% speed", speed);
+        let speed = percent(speed);
+        self.sides(-speed, -speed)
+    }
+
+    /// Turn the car left on the spot
+    pub fn turn_left(&mut self, speed: u8) -> Result<(), M::Error> {
+        info!("Turning car left at {}% speed", speed);
+        let speed = percent(speed);
+        self.sides(-speed, speed)
+    }
+
+    /// Turn the car right on the spot
+    pub fn turn_right(&mut self, speed: u8) -> Result<(), M::Error> {
+        info!("Turning car right at {}% speed", speed);
+        let speed = percent(speed);
+        self.sides(speed, -speed)
+    }
+
+    /// Drive each side at its own signed speed (-100 to 100), like a tank
+    pub fn drive(&mut self, left: i8, right: i8) -> Result<(), M::Error> {
+        debug!("Driving car with left {}% right {}%", left, right);
+        self.sides(left, right)
+    }
+
+    /// Drive each wheel at its own signed speed (-100 to 100), like mecanum wheels need
+    pub fn drive_wheels(&mut self, wheels: Wheels) -> Result<(), M::Error> {
+        debug!("Driving car with wheels {:?}", wheels);
+        self.wheels(wheels)
+    }
+
+    /// Stop all wheels
+    pub fn stop(&mut self) -> Result<(), M::Error> {
+        info!("Stopping car");
+        self.sides(0, 0)
+    }
+
+    fn sides(&mut self, left: i8, right: i8) -> Result<(), M::Error> {
+        self.wheels(Wheels::sides(left, right))
+    }
+
+    fn wheels(&mut self, wheels: Wheels) -> Result<(), M::Error> {
+        let calibration = &self.calibration;
+        self.front_left.drive(calibration.front_left.apply(wheels.front_left))?;
+        self.rear_left.drive(calibration.rear_left.apply(wheels.rear_left))?;
+        self.front_right.drive(calibration.front_right.apply(wheels.front_right))?;
+        self.rear_right.drive(calibration.rear_right.apply(wheels.rear_right))
+    }
+}
+
+fn percent(speed: u8) -> i8 {
+    speed.min(100) as i8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockPin, MockPwm, MAX_DUTY};
+    use shared::config::WheelCalibration;
+
+    /// The in1 and in2 outputs of each wheel: front left, front right, rear left, rear right
+    type Pins = [(MockPwm, MockPwm); 4];
+
+    /// A car of `PwmPair` motors
+    fn pwm_car() -> (Car<PwmPair<MockPwm, MockPwm>>, Pins) {
+        let pins = Pins::default();
+        let motor = |(in1, in2): &(MockPwm, MockPwm)| PwmPair::new(in1.clone(), in2.clone());
+        let car = Car::new(motor(&pins[0]), motor(&pins[1]), motor(&pins[2]), motor(&pins[3]));
+        (car, pins)
+    }
+
+    fn duties(pins: &Pins) -> [(Option<u16>, Option<u16>); 4] {
+        pins.each_ref().map(|(in1, in2)| (in1.duty(), in2.duty()))
+    }
+
+    #[test]
+    fn forward_and_backward_drive_every_wheel() {
+        let (mut car, pins) = pwm_car();
+        let half = Some(MAX_DUTY / 2);
+
+        car.forward(50).unwrap();
+        assert_eq!(duties(&pins), [(half, Some(0)); 4]);
+
+        car.backward(50).unwrap();
+        assert_eq!(duties(&pins), [(Some(0), half); 4]);
+
+        car.stop().unwrap();
+        assert_eq!(duties(&pins), [(Some(0), Some(0)); 4]);
+    }
+
+    #[test]
+    fn turns_run_the_sides_apart() {
+        let (mut car, pins) = pwm_car();
+        let full = Some(MAX_DUTY);
+
+        car.turn_left(100).unwrap();
+        assert_eq!(
+            duties(&pins),
+            [(Some(0), full), (full, Some(0)), (Some(0), full), (full, Some(0))]
+        );
+    }
+
+    #[test]
+    fn speeds_are_clamped() {
+        let (mut car, pins) = pwm_car();
+        car.forward(200).unwrap();
+        car.drive(-128, 127).unwrap();
+        assert_eq!(pins[0].0.history(), [MAX_DUTY, 0]);
+        assert_eq!(pins[0].1.history(), [0, MAX_DUTY]);
+        assert_eq!(pins[1].0.history(), [MAX_DUTY, MAX_DUTY]);
+    }
+
+    #[test]
+    fn calibration_corrects_each_wheel() {
+        let (mut car, pins) = pwm_car();
+        car.set_calibration(Calibration {
+            front_right: WheelCalibration {
+                invert: true,
+                ..WheelCalibration::new()
+            },
+            rear_left: WheelCalibration {
+                gain: 80,
+                ..WheelCalibration::new()
+            },
+            ..Calibration::new()
+        });
+
+        car.forward(50).unwrap();
+        let duty = |percent: u16| Some(MAX_DUTY * percent / 100);
+        assert_eq!(
+            duties(&pins),
+            [(duty(50), Some(0)), (Some(0), duty(50)), (duty(40), Some(0)), (duty(50), Some(0))]
+        );
+    }
+
+    #[test]
+    fn wheels_can_be_driven_apart() {
+        let (mut car, pins) = pwm_car();
+        let full = Some(MAX_DUTY);
+
+        // Strafing right with mecanum wheels
+        car.drive_wheels(Wheels {
+            front_left: 100,
+            front_right: -100,
+            rear_left: -100,
+            rear_right: 100,
+        })
+        .unwrap();
+        assert_eq!(
+            duties(&pins),
+            [(full, Some(0)), (Some(0), full), (Some(0), full), (full, Some(0))]
+        );
+    }
+
+    #[test]
+    fn dir_pwm_stops_before_reversing() {
+        let (pwm, dir) = (MockPwm::default(), MockPin::default());
+        let mut motor = DirPwm::new(pwm.clone(), dir.clone());
+
+        motor.drive(30).unwrap();
+        motor.drive(-30).unwrap();
+        motor.drive(0).unwrap();
+
+        let thirty = MAX_DUTY * 30 / 100;
+        assert_eq!(pwm.history(), [0, thirty, 0, thirty, 0]);
+        assert_eq!(dir.history(), [true, false]);
+    }
+}
@@ -0,0 +1,197 @@
+//! The car's [`CarConfig`] kept in two flash sectors
+//!
+//! Every store goes to the sector not holding the newest record, with a sequence number one past
+//! it and a CRC over the whole record. A reset halfway through erasing or writing leaves the other
+//! sector's record alone, so loading always finds the newest record that was written completely.
+
+use embedded_storage::nor_flash::NorFlash;
+use shared::config::{CONFIG_VERSION, CarConfig, Restored};
+
+/// Sectors the store alternates between
+pub const CONFIG_SECTORS: u32 = 2;
+
+/// Longest record, header and CRC included
+const RECORD_LEN: usize = 1024;
+
+const MAGIC: [u8; 4] = *b"CRCV";
+/// Magic, layout version, u32 sequence number and u16 payload length
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 2;
+const CRC_LEN: usize = 4;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StoreError<E> {
+    Flash(E),
+    Encode,
+}
+
+/// What [`ConfigStore::load`] found
+#[derive(Debug, PartialEq)]
+pub enum Loaded {
+    Blank,                       // Nothing was ever stored completely
+    Config(CarConfig, Restored), // The newest complete record
+    Unreadable,                  // A complete record whose key can't be read, or the flash failed
+}
+
+/// The config record kept in `F`, which spans [`CONFIG_SECTORS`] whole sectors
+pub struct ConfigStore<F> {
+    flash: F,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    pub fn new(flash: F) -> Self {
+        Self { flash }
+    }
+
+    /// The newest record that was written completely
+    pub fn load(&mut self) -> Loaded {
+        let mut record = [0; RECORD_LEN];
+        let newest = match self.newest(&mut record) {
+            Ok(newest) => newest,
+            Err(_) => return Loaded::Unreadable,
+        };
+        let Some((_, _, payload_len)) = newest else {
+            return Loaded::Blank;
+        };
+        match shared::config::decode_stored(record[MAGIC.len()], &record[HEADER_LEN..HEADER_LEN + payload_len]) {
+            Ok((config, restored)) => Loaded::Config(config, restored),
+            Err(_) => Loaded::Unreadable,
+        }
+    }
+
+    /// Write `config` over the older of the two records, the newest one stays until it is done
+    pub fn store(&mut self, config: &CarConfig) -> Result<(), StoreError<F::Error>> {
+        let mut record = [0xFF; RECORD_LEN];
+        let (sector, seq) = match self.newest(&mut record).map_err(StoreError::Flash)? {
+            Some((sector, seq, _)) => ((sector + 1) % CONFIG_SECTORS, seq.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        record.fill(0xFF);
+        let len = bincode::encode_into_slice(config, &mut record[HEADER_LEN..RECORD_LEN - CRC_LEN], shared::frame::config())
+            .map_err(|_| StoreError::Encode)?;
+        record[..MAGIC.len()].copy_from_slice(&MAGIC);
+        record[MAGIC.len()] = CONFIG_VERSION;
+        record[MAGIC.len() + 1..MAGIC.len() + 5].copy_from_slice(&seq.to_le_bytes());
+        record[MAGIC.len() + 5..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+        let end = HEADER_LEN + len;
+        let crc = crc32(&record[..end]);
+        record[end..end + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        let offset = sector * F::ERASE_SIZE as u32;
+        self.flash
+            .erase(offset, offset + F::ERASE_SIZE as u32)
+            .map_err(StoreError::Flash)?;
+        self.flash
+            .write(offset, &record[..end + CRC_LEN])
+            .map_err(StoreError::Flash)
+    }
+
+    /// Sector, sequence number and payload length of the newest complete record, which is left
+    /// in `record`
+    fn newest(&mut self, record: &mut [u8; RECORD_LEN]) -> Result<Option<(u32, u32, usize)>, F::Error> {
+        let mut newest: Option<(u32, u32, usize)> = None;
+        for sector in 0..CONFIG_SECTORS {
+            let mut candidate = [0; RECORD_LEN];
+            self.flash.read(sector * F::ERASE_SIZE as u32, &mut candidate)?;
+            let Some((seq, len)) = complete(&candidate) else {
+                continue;
+            };
+            // Sequence numbers are compared as if on a circle, so they may wrap
+            if newest.is_none_or(|(_, newest_seq, _)| (seq.wrapping_sub(newest_seq) as i32) > 0) {
+                newest = Some((sector, seq, len));
+                *record = candidate;
+            }
+        }
+        Ok(newest)
+    }
+}
+
+/// Sequence number and payload length of `record`, `None` unless it was written completely
+fn complete(record: &[u8; RECORD_LEN]) -> Option<(u32, usize)> {
+    if record[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    let seq = u32::from_le_bytes(record[MAGIC.len() + 1..MAGIC.len() + 5].try_into().unwrap());
+    let len = u16::from_le_bytes(record[MAGIC.len() + 5..HEADER_LEN].try_into().unwrap()) as usize;
+    let end = HEADER_LEN + len;
+    let crc = record.get(end..end + CRC_LEN)?;
+    (crc == crc32(&record[..end]).to_le_bytes()).then_some((seq, len))
+}
+
+/// CRC-32 as used by zip and Ethernet
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFlash, SECTOR_LEN};
+    use shared::config::Profile;
+
+    fn keyed(key: u8) -> CarConfig {
+        CarConfig {
+            auth_key: Some([key; shared::auth::KEY_LEN]),
+            ..CarConfig::default()
+        }
+    }
+
+    #[test]
+    fn crc_matches_the_standard() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn stores_alternate_between_the_sectors() {
+        let flash = MockFlash::new(CONFIG_SECTORS as usize);
+        assert_eq!(ConfigStore::new(flash.clone()).load(), Loaded::Blank);
+
+        let mut store = ConfigStore::new(flash.clone());
+        store.store(&keyed(1)).unwrap();
+        store.store(&keyed(2)).unwrap();
+        store.store(&keyed(3)).unwrap();
+        assert_eq!(flash.erases(), [2, 1]);
+        assert_eq!(ConfigStore::new(flash).load(), Loaded::Config(keyed(3), Restored::All));
+    }
+
+    #[test]
+    fn a_torn_store_keeps_the_previous_config() {
+        let flash = MockFlash::new(CONFIG_SECTORS as usize);
+        let mut store = ConfigStore::new(flash.clone());
+        store.store(&keyed(1)).unwrap();
+        store.store(&CarConfig {
+            profile: Profile::Kid,
+            ..keyed(1)
+        })
+        .unwrap();
+
+        // A reset cut the write to the second sector short
+        flash.tear(SECTOR_LEN + HEADER_LEN, 8);
+        assert_eq!(ConfigStore::new(flash.clone()).load(), Loaded::Config(keyed(1), Restored::All));
+
+        // Or came right after erasing it
+        flash.tear(SECTOR_LEN, SECTOR_LEN);
+        assert_eq!(ConfigStore::new(flash.clone()).load(), Loaded::Config(keyed(1), Restored::All));
+
+        // The next store goes over the torn record again
+        store.store(&keyed(2)).unwrap();
+        assert_eq!(flash.erases(), [1, 2]);
+        assert_eq!(ConfigStore::new(flash).load(), Loaded::Config(keyed(2), Restored::All));
+    }
+
+    #[test]
+    fn a_torn_first_store_leaves_the_flash_blank() {
+        let flash = MockFlash::new(CONFIG_SECTORS as usize);
+        ConfigStore::new(flash.clone()).store(&keyed(1)).unwrap();
+        flash.tear(HEADER_LEN + 4, 8);
+        assert_eq!(ConfigStore::new(flash).load(), Loaded::Blank);
+    }
+}
@@ -0,0 +1,95 @@
+//! The UDP drive channel of the command connection being served
+//!
+//! See [`shared::drive`] for the protocol. [`server::serve`](crate::server::serve) opens the
+//! channel through [`Platform::open_drive_channel`](crate::server::Platform::open_drive_channel)
+//! once the client may drive, the caller knows the client's address and closes the channel when
+//! the connection ends. The address type is the transport's, so the firmware and the simulator
+//! each keep one of these next to their UDP socket.
+
+use shared::auth::Key;
+use shared::drive::{self, Receiver};
+
+use crate::motion::{self, Motion};
+
+pub struct DriveChannel<A> {
+    open: Option<(A, Receiver)>,
+}
+
+impl<A: PartialEq> DriveChannel<A> {
+    pub const fn new() -> Self {
+        Self { open: None }
+    }
+
+    /// Take setpoints from `peer`, checked against the session's drive key when the car has a key
+    pub fn open(&mut self, peer: A, key: Option<Key>) {
+        self.open = Some((peer, Receiver::new(key)));
+    }
+
+    pub fn close(&mut self) {
+        self.open = None;
+    }
+
+    /// The motion a datagram from `from` asks for, `None` if it is dropped. A setpoint of zero
+    /// throttle and steer stops the car, like [`CarCommand::Stop`](shared::CarCommand::Stop)
+    pub fn receive(&mut self, from: &A, datagram: &[u8]) -> Option<Motion> {
+        let (peer, receiver) = self.open.as_mut()?;
+        if peer != from {
+            debug!("Dropping setpoint from another client");
+            return None;
+        }
+        let setpoint = match drive::decode(datagram) {
+            Ok(setpoint) => setpoint,
+            Err(_) => {
+                warn!("Failed to parse setpoint");
+                return None;
+            }
+        };
+        match receiver.accept(&setpoint) {
+            Ok((0, 0)) => Some(Motion::Stop),
+            Ok((throttle, steer)) => Some(motion::arcade(throttle, steer)),
+            Err(_) => {
+                debug!("Dropping setpoint {}", setpoint.seq);
+                None
+            }
+        }
+    }
+}
+
+impl<A: PartialEq> Default for DriveChannel<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::drive::{Sender, MAX_DATAGRAM_LEN};
+
+    fn datagram(sender: &mut Sender, throttle: i8, steer: i8) -> ([u8; MAX_DATAGRAM_LEN], usize) {
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let n = drive::encode(&sender.setpoint(throttle, steer), &mut buf).unwrap();
+        (buf, n)
+    }
+
+    #[test]
+    fn only_the_connected_client_drives() {
+        let mut channel = DriveChannel::new();
+        let mut sender = Sender::new(None);
+
+        let (buf, n) = datagram(&mut sender, 50, 0);
+        assert_eq!(channel.receive(&1, &buf[..n]), None);
+
+        channel.open(1, None);
+        let (buf, n) = datagram(&mut sender, 50, 0);
+        assert_eq!(channel.receive(&2, &buf[..n]), None);
+        assert_eq!(channel.receive(&1, &buf[..n]), Some(motion::arcade(50, 0)));
+        assert_eq!(channel.receive(&1, &buf[..n - 1]), None);
+        let (buf, n) = datagram(&mut sender, 0, 0);
+        assert_eq!(channel.receive(&1, &buf[..n]), Some(Motion::Stop));
+
+        channel.close();
+        let (buf, n) = datagram(&mut sender, 0, 0);
+        assert_eq!(channel.receive(&1, &buf[..n]), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Ring of event log entries in a few flash sectors
+//!
+//! Each sector starts with a header holding the sector's number, which counts up every time the
+//! ring moves on to a sector, followed by fixed size slots holding one entry each. Once the newest
+//! sector is full the oldest one is erased and takes the next number, so every sector is erased
+//! once per turn of the ring and they all wear alike. An entry's sequence number follows from its
+//! sector's number and its slot, so reading from any entry on needs no scan.
+
+use embedded_storage::nor_flash::NorFlash;
+use shared::event_log::{Event, LOG_PAGE_LEN, LogEntry, LogPage};
+
+/// Bytes taken by each entry, and by the header of each sector
+pub const SLOT_LEN: usize = 16;
+
+const MAGIC: [u8; 4] = *b"CRLG";
+const ERASED: u8 = 0xFF;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogError<E> {
+    Flash(E),
+    Encode,
+}
+
+/// The event log kept in `F`, which spans one or more whole sectors
+pub struct EventLog<F> {
+    flash: F,
+    sectors: u32,
+    next: u32,
+    boot: u16,
+}
+
+impl<F: NorFlash> EventLog<F> {
+    /// Entries that fit in a sector after its header
+    const PER_SECTOR: u32 = (F::ERASE_SIZE / SLOT_LEN) as u32 - 1;
+
+    /// Pick up the log kept in `flash` and start logging a new boot, a blank flash starts an
+    /// empty log
+    pub fn open(flash: F) -> Self {
+        let sectors = (flash.capacity() / F::ERASE_SIZE) as u32;
+        let mut log = Self {
+            flash,
+            sectors,
+            next: 0,
+            boot: 0,
+        };
+        let newest = (0..sectors).filter_map(|sector| log.header(sector)).max();
+        if let Some(number) = newest {
+            let first = number * Self::PER_SECTOR;
+            let used = (first..first + Self::PER_SECTOR).take_while(|&seq| !log.erased(seq)).count();
+            log.next = first + used as u32;
+            // A reset may have torn the last entries, the newest readable one has the boot number
+            let kept = sectors * Self::PER_SECTOR;
+            let newest = (log.next.saturating_sub(kept)..log.next).rev().find_map(|seq| log.entry(seq));
+            if let Some(last) = newest {
+                log.boot = last.boot.wrapping_add(1);
+            }
+        }
+        log
+    }
+
+    /// Number of the boot being logged
+    pub fn boot(&self) -> u16 {
+        self.boot
+    }
+
+    /// Sequence number the next entry gets
+    pub fn next_seq(&self) -> u32 {
+        self.next
+    }
+
+    /// Whether the next [`EventLog::append`] starts a sector, and so erases it
+    pub fn append_erases(&self) -> bool {
+        self.next / Self::PER_SECTOR * Self::PER_SECTOR == self.next
+    }
+
+    /// Add `event`, which happened `uptime_ms` after boot, erasing the oldest sector when the
+    /// newest one is full
+    ///
+    /// A failed write loses the entry, not the ones after it.
+    pub fn append(&mut self, uptime_ms: u32, event: Event) -> Result<LogEntry, LogError<F::Error>> {
+        let entry = LogEntry {
+            seq: self.next,
+            boot: self.boot,
+            uptime_ms,
+            event,
+        };
+        let mut slot = [ERASED; SLOT_LEN];
+        let len = bincode::encode_into_slice((entry.boot, entry.uptime_ms, entry.event), &mut slot[1..], shared::frame::config())
+            .map_err(|_| LogError::Encode)?;
+        slot[0] = len as u8;
+        self.next += 1;
+
+        // The first entry of a sector starts it
+        let number = entry.seq / Self::PER_SECTOR;
+        if entry.seq == number * Self::PER_SECTOR {
+            let sector = self.sector_offset(number);
+            let mut header = [ERASED; SLOT_LEN];
+            header[..MAGIC.len()].copy_from_slice(&MAGIC);
+            header[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&number.to_le_bytes());
+            self.flash
+                .erase(sector, sector + F::ERASE_SIZE as u32)
+                .map_err(LogError::Flash)?;
+            self.flash.write(sector, &header).map_err(LogError::Flash)?;
+        }
+        self.flash
+            .write(self.slot_offset(entry.seq), &slot)
+            .map_err(LogError::Flash)?;
+        Ok(entry)
+    }
+
+    /// Entries from `from` on, oldest first, starting at the oldest one still kept if `from` was
+    /// overwritten already
+    pub fn read(&mut self, from: u32) -> LogPage {
+        let mut page = [None; LOG_PAGE_LEN];
+        let mut filled = 0;
+        let kept = self.sectors * Self::PER_SECTOR;
+        let mut seq = from.max(self.next.saturating_sub(kept));
+        while seq < self.next && filled < LOG_PAGE_LEN {
+            let number = seq / Self::PER_SECTOR;
+            if self.header(number % self.sectors) != Some(number) {
+                // Overwritten or never started, carry on with the next sector
+                seq = (number + 1) * Self::PER_SECTOR;
+                continue;
+            }
+            if let Some(entry) = self.slot(seq) {
+                page[filled] = Some(entry);
+                filled += 1;
+            }
+            seq += 1;
+        }
+        page
+    }
+
+    fn sector_offset(&self, number: u32) -> u32 {
+        (number % self.sectors) * F::ERASE_SIZE as u32
+    }
+
+    fn slot_offset(&self, seq: u32) -> u32 {
+        let number = seq / Self::PER_SECTOR;
+        self.sector_offset(number) + (seq % Self::PER_SECTOR + 1) * SLOT_LEN as u32
+    }
+
+    /// Number of the sector at index `sector`, `None` if it was never started
+    fn header(&mut self, sector: u32) -> Option<u32> {
+        let mut header = [0; SLOT_LEN];
+        self.flash.read(sector * F::ERASE_SIZE as u32, &mut header).ok()?;
+        if header[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let number = u32::from_le_bytes(header[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+        (number % self.sectors == sector).then_some(number)
+    }
+
+    /// Whether the slot of `seq` was never written, an unreadable one counts as erased
+    fn erased(&mut self, seq: u32) -> bool {
+        let mut len = [ERASED];
+        let _ = self.flash.read(self.slot_offset(seq), &mut len);
+        len[0] == ERASED
+    }
+
+    /// The entry `seq` if its sector still holds it
+    fn entry(&mut self, seq: u32) -> Option<LogEntry> {
+        let number = seq / Self::PER_SECTOR;
+        if self.header(number % self.sectors) != Some(number) {
+            return None;
+        }
+        self.slot(seq)
+    }
+
+    /// The entry in the slot of `seq`, `None` if it is blank or was torn by a reset
+    fn slot(&mut self, seq: u32) -> Option<LogEntry> {
+        let mut slot = [0; SLOT_LEN];
+        self.flash.read(self.slot_offset(seq), &mut slot).ok()?;
+        let payload = slot[1..].get(..slot[0] as usize)?;
+        let ((boot, uptime_ms, event), _) = bincode::decode_from_slice(payload, shared::frame::config()).ok()?;
+        Some(LogEntry {
+            seq,
+            boot,
+            uptime_ms,
+            event,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::{MockFlash, SECTOR_LEN};
+    use std::vec::Vec;
+    use shared::ErrorCode;
+    use shared::event_log::ResetReason;
+
+    const PER_SECTOR: u32 = (SECTOR_LEN / SLOT_LEN) as u32 - 1;
+
+    fn entries(page: LogPage) -> impl Iterator<Item = LogEntry> {
+        page.into_iter().flatten()
+    }
+
+    #[test]
+    fn entries_survive_a_reboot() {
+        let flash = MockFlash::new(2);
+        let mut log = EventLog::open(flash.clone());
+        log.append(0, Event::Boot(ResetReason::PowerOn)).unwrap();
+        log.append(1500, Event::Connected).unwrap();
+
+        let mut log = EventLog::open(flash);
+        assert_eq!(log.boot(), 1);
+        assert_eq!(log.next_seq(), 2);
+        log.append(20, Event::Boot(ResetReason::Watchdog)).unwrap();
+
+        let read: Vec<_> = entries(log.read(0)).map(|entry| (entry.seq, entry.boot, entry.uptime_ms, entry.event)).collect();
+        assert_eq!(
+            read,
+            [
+                (0, 0, 0, Event::Boot(ResetReason::PowerOn)),
+                (1, 0, 1500, Event::Connected),
+                (2, 1, 20, Event::Boot(ResetReason::Watchdog)),
+            ]
+        );
+        assert_eq!(entries(log.read(2)).count(), 1);
+        assert_eq!(entries(log.read(3)).count(), 0);
+    }
+
+    #[test]
+    fn a_full_ring_overwrites_the_oldest_sector() {
+        let flash = MockFlash::new(2);
+        let mut log = EventLog::open(flash.clone());
+        for i in 0..2 * PER_SECTOR + 3 {
+            assert_eq!(log.append_erases(), i % PER_SECTOR == 0);
+            let rejected = Event::Rejected {
+                code: ErrorCode::Malformed,
+                count: 1,
+            };
+            log.append(i, rejected).unwrap();
+        }
+        // Every sector was erased once per turn of the ring, the first one twice
+        assert_eq!(flash.erases(), [2, 1]);
+
+        // The first sector's entries are gone, the log starts with the second one
+        let first = log.read(0)[0].unwrap();
+        assert_eq!(first.seq, PER_SECTOR);
+        assert_eq!(first.uptime_ms, PER_SECTOR);
+
+        let newest = EventLog::open(flash).read(2 * PER_SECTOR);
+        assert_eq!(entries(newest).map(|entry| entry.seq).collect::<Vec<_>>(), [2 * PER_SECTOR, 2 * PER_SECTOR + 1, 2 * PER_SECTOR + 2]);
+    }
+
+    #[test]
+    fn pages_are_bounded() {
+        let mut log = EventLog::open(MockFlash::new(2));
+        for i in 0..LOG_PAGE_LEN as u32 + 5 {
+            log.append(i, Event::Failsafe).unwrap();
+        }
+        assert_eq!(entries(log.read(0)).count(), LOG_PAGE_LEN);
+        assert_eq!(entries(log.read(LOG_PAGE_LEN as u32)).count(), 5);
+    }
+
+    #[test]
+    fn torn_entries_are_skipped() {
+        let flash = MockFlash::new(2);
+        let mut log = EventLog::open(flash.clone());
+        log.append(0, Event::Connected).unwrap();
+        log.append(10, Event::Disconnected).unwrap();
+        // A reset while writing the first entry left only its length behind
+        flash.tear(SLOT_LEN + 1, SLOT_LEN - 1);
+
+        let mut log = EventLog::open(flash);
+        assert_eq!(log.next_seq(), 2);
+        assert_eq!(entries(log.read(0)).map(|entry| entry.event).collect::<Vec<_>>(), [Event::Disconnected]);
+    }
+
+    #[test]
+    fn a_torn_last_entry_keeps_the_boot_count() {
+        let flash = MockFlash::new(2);
+        EventLog::open(flash.clone()).append(0, Event::Boot(ResetReason::PowerOn)).unwrap();
+        let mut log = EventLog::open(flash.clone());
+        log.append(0, Event::Boot(ResetReason::PowerOn)).unwrap();
+        log.append(10, Event::Connected).unwrap();
+        // The reset cut the last entry short
+        flash.tear(3 * SLOT_LEN + 1, SLOT_LEN - 1);
+
+        let log = EventLog::open(flash);
+        assert_eq!(log.boot(), 2);
+        assert_eq!(log.next_seq(), 3);
+    }
+}
@@ -0,0 +1,249 @@
+//! MPU6050 IMU and the heading worked out from its gyro
+//!
+//! Only the yaw rate, the rotation about the vertical axis, matters for driving. Its bias is
+//! measured while the car stands still at startup and taken off every sample, the heading is
+//! what is left integrated over time. That drifts by a degree or so a minute, plenty for holding
+//! a course or turning by an angle, but it isn't a compass.
+//!
+//! The IMU is mounted flat with the chip facing up, so its z axis points up and a clockwise
+//! rotation seen from above reads as a negative z rate.
+
+use embassy_time::Instant;
+use embedded_hal_async::i2c::I2c;
+use shared::ImuReading;
+
+/// I2C address with AD0 pulled low, as on most breakout boards
+pub const ADDRESS: u8 = 0x68;
+
+/// Samples averaged to measure the gyro's bias
+pub const CALIBRATION_SAMPLES: u32 = 200;
+
+const WHO_AM_I: u8 = 0x75;
+const PWR_MGMT_1: u8 = 0x6B;
+const CONFIG: u8 = 0x1A;
+const GYRO_CONFIG: u8 = 0x1B;
+const ACCEL_CONFIG: u8 = 0x1C;
+const ACCEL_XOUT_H: u8 = 0x3B;
+
+/// Scale at the ±250 °/s range
+const GYRO_LSB_PER_DPS: f32 = 131.0;
+
+/// Scale at the ±2 g range
+const ACCEL_LSB_PER_G: f32 = 16384.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImuError<E> {
+    I2c(E),
+    /// Something else answered, with this WHO_AM_I
+    NotFound(u8),
+}
+
+/// One sample, rotation rates in °/s and accelerations in g along the sensor's axes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    pub gyro_dps: [f32; 3],
+    pub accel_g: [f32; 3],
+}
+
+impl Sample {
+    /// Rotation rate clockwise seen from above
+    pub fn yaw_rate_dps(&self) -> f32 {
+        -self.gyro_dps[2]
+    }
+}
+
+pub struct Mpu6050<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Mpu6050<I> {
+    /// Check that an MPU6050 answers at `address`, wake it up and set its ranges
+    pub async fn new(mut i2c: I, address: u8) -> Result<Self, ImuError<I::Error>> {
+        let mut id = [0];
+        i2c.write_read(address, &[WHO_AM_I], &mut id).await.map_err(ImuError::I2c)?;
+        if id[0] != ADDRESS {
+            return Err(ImuError::NotFound(id[0]));
+        }
+        let mut imu = Self { i2c, address };
+        // Clocked from the gyro's PLL, which is steadier than the internal oscillator
+        imu.write(PWR_MGMT_1, 0x01).await.map_err(ImuError::I2c)?;
+        // 44 Hz low pass, filters out most of the motor vibration
+        imu.write(CONFIG, 0x03).await.map_err(ImuError::I2c)?;
+        imu.write(GYRO_CONFIG, 0x00).await.map_err(ImuError::I2c)?;
+        imu.write(ACCEL_CONFIG, 0x00).await.map_err(ImuError::I2c)?;
+        Ok(imu)
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), I::Error> {
+        self.i2c.write(self.address, &[register, value]).await
+    }
+
+    /// Read the accelerometer and gyro in one go, so both are from the same moment
+    pub async fn read(&mut self) -> Result<Sample, I::Error> {
+        let mut raw = [0; 14];
+        self.i2c.write_read(self.address, &[ACCEL_XOUT_H], &mut raw).await?;
+        let value = |index: usize| i16::from_be_bytes([raw[2 * index], raw[2 * index + 1]]) as f32;
+        // The temperature sits between the accelerometer and the gyro
+        Ok(Sample {
+            accel_g: [0, 1, 2].map(|axis| value(axis) / ACCEL_LSB_PER_G),
+            gyro_dps: [4, 5, 6].map(|axis| value(axis) / GYRO_LSB_PER_DPS),
+        })
+    }
+}
+
+/// Mean yaw rate while the car stands still, the gyro's bias
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bias {
+    sum: f32,
+    count: u32,
+}
+
+impl Bias {
+    pub fn add(&mut self, yaw_rate_dps: f32) {
+        self.sum += yaw_rate_dps;
+        self.count += 1;
+    }
+
+    /// Whether enough samples were taken
+    pub fn done(&self) -> bool {
+        self.count >= CALIBRATION_SAMPLES
+    }
+
+    pub fn bias_dps(&self) -> f32 {
+        if self.count == 0 { 0.0 } else { self.sum / self.count as f32 }
+    }
+}
+
+/// Heading integrated from the yaw rate, starting at 0
+pub struct Heading {
+    bias_dps: f32,
+    reading: ImuReading,
+    last: Option<Instant>,
+}
+
+impl Heading {
+    pub fn new(bias_dps: f32) -> Self {
+        Self {
+            bias_dps,
+            reading: ImuReading {
+                heading_deg: 0.0,
+                yaw_rate_dps: 0.0,
+                accel_g: [0.0; 3],
+            },
+            last: None,
+        }
+    }
+
+    /// Take a sample read at `now`
+    ///
+    /// The rate is integrated over the time since the previous sample, the first sample only
+    /// starts the clock.
+    pub fn update(&mut self, sample: &Sample, now: Instant) -> ImuReading {
+        let rate = sample.yaw_rate_dps() - self.bias_dps;
+        if let Some(last) = self.last {
+            let dt = (now - last).as_micros() as f32 / 1_000_000.0;
+            // Trapezoidal, the mean of this rate and the previous one
+            let turned = (rate + self.reading.yaw_rate_dps) / 2.0 * dt;
+            self.reading.heading_deg = wrap_degrees(self.reading.heading_deg + turned);
+        }
+        self.last = Some(now);
+        self.reading.yaw_rate_dps = rate;
+        self.reading.accel_g = sample.accel_g;
+        self.reading
+    }
+
+    pub fn reading(&self) -> ImuReading {
+        self.reading
+    }
+}
+
+/// An angle in degrees brought into -180 to 180
+pub fn wrap_degrees(degrees: f32) -> f32 {
+    if !degrees.is_finite() {
+        return 0.0;
+    }
+    let mut degrees = degrees;
+    while degrees > 180.0 {
+        degrees -= 360.0;
+    }
+    while degrees <= -180.0 {
+        degrees += 360.0;
+    }
+    degrees
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future waited"),
+        }
+    }
+
+    #[test]
+    fn reads_scaled_samples() {
+        let bus = MockI2c::new(ADDRESS);
+        bus.set(WHO_AM_I, &[0x68]);
+        // 1 g down the z axis and turning clockwise at 10 °/s
+        bus.set(ACCEL_XOUT_H, &[0x00, 0x00, 0xC0, 0x00, 0x40, 0x00, 0, 0, 0x00, 0x83, 0x00, 0x00, 0xFA, 0xE2]);
+        let mut imu = block_on(Mpu6050::new(bus.clone(), ADDRESS)).unwrap();
+        assert_eq!(bus.register(PWR_MGMT_1), 0x01);
+        assert_eq!(bus.register(CONFIG), 0x03);
+
+        let sample = block_on(imu.read()).unwrap();
+        assert_eq!(sample.accel_g, [0.0, -1.0, 1.0]);
+        assert_eq!(sample.gyro_dps, [1.0, 0.0, -10.0]);
+        assert_eq!(sample.yaw_rate_dps(), 10.0);
+
+        bus.set(WHO_AM_I, &[0x70]);
+        assert!(matches!(block_on(Mpu6050::new(bus, ADDRESS)), Err(ImuError::NotFound(0x70))));
+        assert!(matches!(block_on(Mpu6050::new(MockI2c::new(0x69), ADDRESS)), Err(ImuError::I2c(_))));
+    }
+
+    #[test]
+    fn integrates_the_rate_without_the_bias() {
+        let mut bias = Bias::default();
+        for i in 0..CALIBRATION_SAMPLES {
+            assert!(!bias.done());
+            bias.add(if i % 2 == 0 { 0.4 } else { 0.6 });
+        }
+        assert!(bias.done());
+        assert!((bias.bias_dps() - 0.5).abs() < 1e-4);
+
+        let mut heading = Heading::new(0.5);
+        let turning = |rate: f32| Sample {
+            gyro_dps: [0.0, 0.0, -rate],
+            accel_g: [0.0, 0.0, 1.0],
+        };
+        heading.update(&turning(0.5), Instant::from_millis(0));
+        assert_eq!(heading.reading().heading_deg, 0.0);
+        // A second at 90 °/s, then on past 180
+        for ms in (10..=1000).step_by(10) {
+            heading.update(&turning(90.5), Instant::from_millis(ms));
+        }
+        assert!((heading.reading().heading_deg - 89.55).abs() < 0.01);
+        assert_eq!(heading.reading().yaw_rate_dps, 90.0);
+        for ms in (1010..=2500).step_by(10) {
+            heading.update(&turning(90.5), Instant::from_millis(ms));
+        }
+        assert!((heading.reading().heading_deg + 135.45).abs() < 0.01);
+    }
+
+    #[test]
+    fn wraps_angles() {
+        assert_eq!(wrap_degrees(190.0), -170.0);
+        assert_eq!(wrap_degrees(-180.0), 180.0);
+        assert_eq!(wrap_degrees(720.0), 0.0);
+        assert_eq!(wrap_degrees(f32::NAN), 0.0);
+    }
+}
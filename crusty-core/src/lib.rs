@@ -0,0 +1,19 @@
+//! Car logic that doesn't touch the hardware, shared by the firmware and the simulator
+
+#![no_std]
+
+// This must go FIRST so that all the other modules see its macros.
+mod fmt;
+
+pub mod battery;
+pub mod car;
+pub mod config_store;
+pub mod drive;
+pub mod event_log;
+pub mod imu;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod motion;
+pub mod pwm;
+pub mod server;
+pub mod web;
@@ -0,0 +1,231 @@
+//! Motor outputs that record what they were set to, and flash and I2C devices kept in memory,
+//! for testing on the host
+//!
+//! Clones share their history, so a test can keep a clone of each output it hands to a
+//! [`Car`](crate::car::Car) and inspect it afterwards.
+
+extern crate std;
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use embedded_hal::digital::{self, OutputPin};
+use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource, Operation};
+use embedded_hal::pwm::{self, SetDutyCycle};
+use embedded_storage::nor_flash::{self, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+/// Duty cycle of a [`MockPwm`] at 100%
+pub const MAX_DUTY: u16 = 1000;
+
+/// PWM output recording every duty cycle it is set to
+#[derive(Clone, Default)]
+pub struct MockPwm {
+    history: Rc<RefCell<Vec<u16>>>,
+}
+
+impl MockPwm {
+    /// Every duty cycle set so far, oldest first
+    pub fn history(&self) -> Vec<u16> {
+        self.history.borrow().clone()
+    }
+
+    /// The current duty cycle, `None` if it was never set
+    pub fn duty(&self) -> Option<u16> {
+        self.history.borrow().last().copied()
+    }
+}
+
+impl pwm::ErrorType for MockPwm {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for MockPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        MAX_DUTY
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.history.borrow_mut().push(duty);
+        Ok(())
+    }
+}
+
+/// Output pin recording every level it is set to, `true` is high
+#[derive(Clone, Default)]
+pub struct MockPin {
+    history: Rc<RefCell<Vec<bool>>>,
+}
+
+impl MockPin {
+    /// Every level set so far, oldest first
+    pub fn history(&self) -> Vec<bool> {
+        self.history.borrow().clone()
+    }
+}
+
+impl digital::ErrorType for MockPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.history.borrow_mut().push(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.history.borrow_mut().push(true);
+        Ok(())
+    }
+}
+
+/// Sector size of a [`MockFlash`]
+pub const SECTOR_LEN: usize = 4096;
+
+/// Flash kept in memory that only clears bits on writes like NOR flash, and counts how often each
+/// sector is erased
+#[derive(Clone)]
+pub struct MockFlash {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    erases: Rc<RefCell<Vec<u32>>>,
+}
+
+impl MockFlash {
+    /// Blank flash of `sectors` sectors
+    pub fn new(sectors: usize) -> Self {
+        Self {
+            bytes: Rc::new(RefCell::new(std::vec![0xFF; sectors * SECTOR_LEN])),
+            erases: Rc::new(RefCell::new(std::vec![0; sectors])),
+        }
+    }
+
+    /// How often each sector was erased
+    pub fn erases(&self) -> Vec<u32> {
+        self.erases.borrow().clone()
+    }
+
+    /// Put `len` bytes from `offset` on back to erased, like a write cut short by a reset
+    pub fn tear(&self, offset: usize, len: usize) {
+        self.bytes.borrow_mut()[offset..offset + len].fill(0xFF);
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, NorFlashErrorKind> {
+        let start = offset as usize;
+        let end = start + len;
+        if end > self.bytes.borrow().len() {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        Ok(start..end)
+    }
+}
+
+impl nor_flash::ErrorType for MockFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.check(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.bytes.borrow()[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.bytes.borrow().len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_LEN;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !(from as usize).is_multiple_of(SECTOR_LEN) || !(to as usize).is_multiple_of(SECTOR_LEN) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let range = self.check(from, (to - from) as usize)?;
+        for sector in range.start / SECTOR_LEN..range.end / SECTOR_LEN {
+            self.erases.borrow_mut()[sector] += 1;
+        }
+        self.bytes.borrow_mut()[range].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.check(offset, bytes.len())?;
+        for (cell, byte) in self.bytes.borrow_mut()[range].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+/// I2C device kept in memory, 256 registers at one address that auto-increment like most sensors
+#[derive(Clone)]
+pub struct MockI2c {
+    address: u8,
+    registers: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MockI2c {
+    /// A device answering at `address` with every register 0
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            registers: Rc::new(RefCell::new(std::vec![0; 256])),
+        }
+    }
+
+    /// Set the registers from `register` on to `values`
+    pub fn set(&self, register: u8, values: &[u8]) {
+        let start = register as usize;
+        self.registers.borrow_mut()[start..start + values.len()].copy_from_slice(values);
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers.borrow()[register as usize]
+    }
+}
+
+impl i2c::ErrorType for MockI2c {
+    type Error = ErrorKind;
+}
+
+impl embedded_hal_async::i2c::I2c for MockI2c {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if address != self.address {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        // The first byte written picks the register, the rest are written from there on
+        let mut pointer = None;
+        let mut registers = self.registers.borrow_mut();
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    for byte in bytes.iter() {
+                        match pointer {
+                            None => pointer = Some(*byte),
+                            Some(register) => {
+                                registers[register as usize] = *byte;
+                                pointer = Some(register.wrapping_add(1));
+                            }
+                        }
+                    }
+                }
+                Operation::Read(buffer) => {
+                    let mut register = pointer.unwrap_or(0);
+                    for byte in buffer.iter_mut() {
+                        *byte = registers[register as usize];
+                        register = register.wrapping_add(1);
+                    }
+                    pointer = Some(register);
+                }
+            }
+        }
+        Ok(())
+    }
+}
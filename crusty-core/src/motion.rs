@@ -0,0 +1,1006 @@
+//! Motion requests from every input source and the arbitration between them
+//!
+//! Input sources don't drive the motors themselves, they queue a [`MotionRequest`] for the
+//! control loop, which owns the motors. A source takes control when it sends a motion while
+//! nobody else is driving, and keeps it as long as it refreshes its lease. A stop from any source
+//! always wins, and an expired lease (the deadman) stops the car and frees control for the other
+//! sources.
+//!
+//! Autonomous modes drive through the same path as [`Source::Autonomous`], so a stop from the
+//! network or the remote also ends the autonomous mode.
+//!
+//! Missions run inside the control loop, so their timing doesn't depend on the network. They
+//! drive as [`Source::Network`] and every step refreshes the lease, a step longer than the
+//! deadman needs the client to send a [`MotionRequest::Keepalive`]. Any stop, other network
+//! driving, the deadman or an obstacle aborts the mission.
+//!
+//! Whoever drives, the controller holds the wheels to the [`DriveLimits`] of the driving
+//! profile: speeds are scaled down to its top speed, turning is capped, speed changes are ramped
+//! at its acceleration and obstacles stop the car at its distance.
+//!
+//! With a heading from the IMU the controller also keeps the car on course while it drives
+//! straight, and turns by an angle for [`MotionRequest::TurnBy`]. Like missions, a turn drives as
+//! [`Source::Network`] and any stop or other network driving aborts it.
+
+use embassy_time::{Duration, Instant};
+use shared::config::{Chassis, DriveLimits, Profile};
+use shared::mission::{Mission, MissionStatus, MissionStep};
+use shared::DriveMode;
+
+use crate::imu::wrap_degrees;
+
+/// Period of the control loop
+pub const CONTROL_PERIOD: Duration = Duration::from_millis(10);
+
+/// How long a single IR frame or repeat code keeps the car moving, remotes repeat every 108 ms
+pub const IR_LEASE: Duration = Duration::from_millis(250);
+
+/// How long a single update from an autonomous mode keeps the car moving
+pub const AUTONOMOUS_LEASE: Duration = Duration::from_millis(200);
+
+/// Longest a [`MotionRequest::TurnBy`] may take before the car gives up and stops
+pub const TURN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Heading hold correction in % of wheel speed per degree off course
+const HOLD_GAIN: f32 = 2.0;
+
+/// Most the heading hold slows one side and speeds up the other
+const MAX_HOLD: f32 = 20.0;
+
+/// How close to its angle a turn stops, in degrees
+const TURN_TOLERANCE: f32 = 2.0;
+
+/// Turning speed in % per degree still to turn, so turns slow down as they near the end
+const TURN_GAIN: f32 = 1.0;
+
+/// Slowest and fastest turning speed for [`MotionRequest::TurnBy`]
+const MIN_TURN_SPEED: f32 = 25.0;
+const MAX_TURN_SPEED: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    Network,
+    Ir,
+    Autonomous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Motion {
+    Stop,
+    Forward(u8),
+    Backward(u8),
+    TurnLeft(u8),
+    TurnRight(u8),
+    Drive { left: i8, right: i8 }, // Signed speed of each side, -100 to 100
+    Wheels(Wheels),                // Signed speed of each wheel, for mecanum wheels
+}
+
+/// Signed speed of each wheel, -100 to 100
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Wheels {
+    pub front_left: i8,
+    pub front_right: i8,
+    pub rear_left: i8,
+    pub rear_right: i8,
+}
+
+impl Wheels {
+    pub const STOP: Self = Self::sides(0, 0);
+
+    /// Both wheels of a side at the same speed, like a tank
+    pub const fn sides(left: i8, right: i8) -> Self {
+        Self {
+            front_left: left,
+            front_right: right,
+            rear_left: left,
+            rear_right: right,
+        }
+    }
+
+    /// Mean speed of the left wheels
+    pub fn left(&self) -> i8 {
+        ((self.front_left as i16 + self.rear_left as i16) / 2) as i8
+    }
+
+    /// Mean speed of the right wheels
+    pub fn right(&self) -> i8 {
+        ((self.front_right as i16 + self.rear_right as i16) / 2) as i8
+    }
+
+    fn map(self, f: impl Fn(i8) -> i8) -> Self {
+        self.zip(self, |speed, _| f(speed))
+    }
+
+    fn zip(self, other: Self, f: impl Fn(i8, i8) -> i8) -> Self {
+        Self {
+            front_left: f(self.front_left, other.front_left),
+            front_right: f(self.front_right, other.front_right),
+            rear_left: f(self.rear_left, other.rear_left),
+            rear_right: f(self.rear_right, other.rear_right),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionRequest {
+    /// Drive the car, or refresh the lease if the motion is unchanged
+    Set { source: Source, motion: Motion },
+    /// The source no longer wants control, stops the car if it was driving
+    Release(Source),
+    /// Change how long network motion lasts without a refresh, see [`deadman_lease`]
+    NetworkLease(Option<Duration>),
+    /// Run the steps of a mission, replacing any mission already running
+    Mission(Mission),
+    /// Refresh the lease of the source if it is driving, without changing its motion
+    Keepalive(Source),
+    /// Hold every source to new limits from the next tick on
+    Limits(DriveLimits),
+    /// Turn on the spot by this many degrees from the network, positive turns right, needs a
+    /// heading from [`Controller::set_heading`]
+    TurnBy(i16),
+}
+
+/// Network lease for a configured deadman time, 0 disables the deadman
+pub fn deadman_lease(deadman_ms: u16) -> Option<Duration> {
+    (deadman_ms > 0).then(|| Duration::from_millis(deadman_ms as u64))
+}
+
+/// Mix arcade style throttle and steering (-100 to 100) into side speeds, positive steer turns
+/// right
+pub fn arcade(throttle: i8, steer: i8) -> Motion {
+    let mix = |speed: i16| speed.clamp(-100, 100) as i8;
+    Motion::Drive {
+        left: mix(throttle as i16 + steer as i16),
+        right: mix(throttle as i16 - steer as i16),
+    }
+}
+
+/// Mix forward, rightward and clockwise speeds (-100 to 100) for the wheels of `chassis`
+///
+/// Plain wheels can't move the car sideways, so on a [`Chassis::Tank`] `vy` is dropped and the
+/// rest drives like [`arcade`].
+pub fn holonomic(vx: i8, vy: i8, omega: i8, chassis: Chassis) -> Motion {
+    match chassis {
+        Chassis::Tank => arcade(vx, omega),
+        Chassis::Mecanum => Motion::Wheels(mecanum(vx, vy, omega)),
+    }
+}
+
+/// Wheel speeds of a car with mecanum wheels, their rollers forming an X seen from above
+///
+/// When a wheel would have to go faster than 100 all of them are scaled down together, so the car
+/// still heads the same way.
+pub fn mecanum(vx: i8, vy: i8, omega: i8) -> Wheels {
+    let [vx, vy, omega] = [vx, vy, omega].map(|speed| (speed as i16).clamp(-100, 100));
+    let speeds = [vx + vy + omega, vx - vy - omega, vx - vy + omega, vx + vy - omega];
+    let peak = speeds.iter().map(|speed| speed.abs()).max().unwrap_or(0).max(100);
+    let [front_left, front_right, rear_left, rear_right] = speeds.map(|speed| (speed * 100 / peak) as i8);
+    Wheels {
+        front_left,
+        front_right,
+        rear_left,
+        rear_right,
+    }
+}
+
+/// Decides which source is in control and when its lease runs out
+pub struct Arbiter {
+    owner: Option<Source>,
+    deadline: Option<Instant>,
+    network_lease: Option<Duration>,
+}
+
+impl Arbiter {
+    /// `network_lease` of `None` keeps network motion going until it is replaced or stopped
+    pub fn new(network_lease: Option<Duration>) -> Self {
+        Self {
+            owner: None,
+            deadline: None,
+            network_lease,
+        }
+    }
+
+    fn lease(&self, source: Source) -> Option<Duration> {
+        match source {
+            Source::Network => self.network_lease,
+            Source::Ir => Some(IR_LEASE),
+            Source::Autonomous => Some(AUTONOMOUS_LEASE),
+        }
+    }
+
+    /// The source currently driving the car
+    pub fn owner(&self) -> Option<Source> {
+        self.owner
+    }
+
+    /// When the car has to be stopped unless the owner refreshes its lease
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Handle a request, returning the motion to apply or `None` if the request was ignored
+    pub fn handle(&mut self, request: MotionRequest, now: Instant) -> Option<Motion> {
+        match request {
+            MotionRequest::Set {
+                motion: Motion::Stop, ..
+            } => {
+                self.owner = None;
+                self.deadline = None;
+                Some(Motion::Stop)
+            }
+            MotionRequest::Set { source, motion } => {
+                if self.owner.is_some_and(|owner| owner != source) {
+                    return None;
+                }
+                self.owner = Some(source);
+                self.deadline = self.lease(source).map(|lease| now + lease);
+                Some(motion)
+            }
+            MotionRequest::Release(source) if self.owner == Some(source) => {
+                self.owner = None;
+                self.deadline = None;
+                Some(Motion::Stop)
+            }
+            MotionRequest::Release(_) => None,
+            MotionRequest::NetworkLease(lease) => {
+                self.network_lease = lease;
+                if self.owner == Some(Source::Network) {
+                    self.deadline = lease.map(|lease| now + lease);
+                }
+                None
+            }
+            MotionRequest::Keepalive(source) => {
+                if self.owner == Some(source) {
+                    self.deadline = self.lease(source).map(|lease| now + lease);
+                }
+                None
+            }
+            // Missions, limits and turns are applied by the controller
+            MotionRequest::Mission(_) | MotionRequest::Limits(_) | MotionRequest::TurnBy(_) => None,
+        }
+    }
+
+    /// Stop the car if the owner's lease ran out
+    pub fn expire(&mut self, now: Instant) -> Option<Motion> {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.owner = None;
+                self.deadline = None;
+                Some(Motion::Stop)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Signed speed of each wheel for a motion
+pub fn wheel_speeds(motion: Motion) -> Wheels {
+    let signed = |speed: u8| speed.min(100) as i8;
+    let clamp = |speed: i8| speed.clamp(-100, 100);
+    match motion {
+        Motion::Stop => Wheels::STOP,
+        Motion::Forward(speed) => Wheels::sides(signed(speed), signed(speed)),
+        Motion::Backward(speed) => Wheels::sides(-signed(speed), -signed(speed)),
+        Motion::TurnLeft(speed) => Wheels::sides(-signed(speed), signed(speed)),
+        Motion::TurnRight(speed) => Wheels::sides(signed(speed), -signed(speed)),
+        Motion::Drive { left, right } => Wheels::sides(clamp(left), clamp(right)),
+        Motion::Wheels(wheels) => wheels.map(clamp),
+    }
+}
+
+/// Wheel speeds held to `limits`, scaled down to the top speed with the turning part capped
+///
+/// Turning is how much faster one side runs than the mean of both, capping it slows the faster
+/// side and speeds up the slower one alike, so the car keeps its speed along its heading.
+pub fn limit(wheels: Wheels, limits: &DriveLimits) -> Wheels {
+    let max_speed = limits.max_speed.min(100) as i16;
+    let scaled = wheels.map(|speed| (speed as i16 * max_speed / 100) as i8);
+    let max_turn = limits.max_turn.min(100) as i16;
+    let turn = (scaled.left() as i16 - scaled.right() as i16) / 2;
+    let excess = turn - turn.clamp(-max_turn, max_turn);
+    steer(scaled, -excess)
+}
+
+/// Speed up the left wheels and slow down the right ones by `by`, turning the car right
+fn steer(wheels: Wheels, by: i16) -> Wheels {
+    let shift = |speed: i8, by: i16| (speed as i16 + by).clamp(-100, 100) as i8;
+    Wheels {
+        front_left: shift(wheels.front_left, by),
+        front_right: shift(wheels.front_right, -by),
+        rear_left: shift(wheels.rear_left, by),
+        rear_right: shift(wheels.rear_right, -by),
+    }
+}
+
+/// Largest speed change per control period `limits` allow, at least 1
+fn ramp_step(limits: &DriveLimits) -> i16 {
+    (limits.accel_per_s as u64 * CONTROL_PERIOD.as_millis() / 1000).max(1) as i16
+}
+
+/// Move one wheel's speed towards its target by at most `step`
+fn ramp(current: i8, target: i8, step: i16) -> i8 {
+    let change = (target as i16 - current as i16).clamp(-step, step);
+    (current as i16 + change) as i8
+}
+
+/// Whether the car would move towards an obstacle in front of it, strafing sideways doesn't
+fn heading_forward(wheels: Wheels) -> bool {
+    wheels.front_left as i16 + wheels.front_right as i16 + wheels.rear_left as i16 + wheels.rear_right as i16 > 0
+}
+
+/// What happened during one control loop tick
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tick {
+    /// New speed of each wheel, `None` if the motors don't need updating
+    pub wheels: Option<Wheels>,
+    /// The source whose lease ran out, the car was stopped
+    pub deadman: Option<Source>,
+    /// An obstacle showed up in front of the car and forward motion was stopped
+    pub obstacle: bool,
+    /// A mission step asks for a tone of this frequency and length in ms
+    pub beep: Option<(u16, u16)>,
+    /// A mission step sets the LEDs to this colour
+    pub lights: Option<(u8, u8, u8)>,
+}
+
+/// The mission being run and when its current step ends
+struct MissionRun {
+    steps: Mission,
+    next: usize,
+    until: Instant,
+}
+
+/// The turn being made for [`MotionRequest::TurnBy`]
+struct TurnRun {
+    remaining: f32, // Degrees still to turn, positive right
+    last: f32,      // Heading at the previous tick
+    until: Instant,
+}
+
+/// The control loop without the timing: applies requests, ramps the motors, enforces the
+/// deadman and stops in front of obstacles
+pub struct Controller {
+    arbiter: Arbiter,
+    target: Wheels,
+    output: Wheels,
+    blocked: bool,
+    mission: Option<MissionRun>,
+    mission_status: MissionStatus,
+    limits: DriveLimits,
+    heading: Option<f32>,
+    hold: Option<f32>,
+    turn: Option<TurnRun>,
+}
+
+impl Controller {
+    /// A controller holding the car to the limits of [`Profile::Race`] until told otherwise
+    pub fn new(arbiter: Arbiter) -> Self {
+        Self {
+            arbiter,
+            target: Wheels::STOP,
+            output: Wheels::STOP,
+            blocked: false,
+            mission: None,
+            mission_status: MissionStatus::Idle,
+            limits: Profile::Race.limits(),
+            heading: None,
+            hold: None,
+            turn: None,
+        }
+    }
+
+    /// Heading from the IMU in degrees clockwise, `None` without one
+    ///
+    /// Set before every tick, the heading hold and turns by an angle stop as soon as it is gone.
+    pub fn set_heading(&mut self, heading: Option<f32>) {
+        self.heading = heading;
+    }
+
+    /// Hold every source to `limits` from the next tick on
+    pub fn set_limits(&mut self, limits: DriveLimits) {
+        self.limits = limits;
+    }
+
+    /// Whether an obstacle `distance_cm` ahead is closer than the limits allow
+    pub fn too_close(&self, distance_cm: Option<u16>) -> bool {
+        distance_cm.is_some_and(|cm| cm < self.limits.stop_distance_cm)
+    }
+
+    /// Speed the motors are currently set to
+    pub fn output(&self) -> Wheels {
+        self.output
+    }
+
+    /// How far the last mission got
+    pub fn mission(&self) -> MissionStatus {
+        self.mission_status
+    }
+
+    /// Take a request from the queue while `mode` is active
+    ///
+    /// Returns the mode to switch to when the request ends an autonomous mode.
+    pub fn request(&mut self, request: MotionRequest, mode: DriveMode, now: Instant) -> Option<DriveMode> {
+        // Stopping by hand takes the car out of any autonomous mode
+        let manual_stop = matches!(
+            request,
+            MotionRequest::Set {
+                source: Source::Network | Source::Ir,
+                motion: Motion::Stop,
+            }
+        );
+        let mut switch = None;
+        if manual_stop && mode != DriveMode::Manual {
+            info!("manual stop, leaving {:?}", mode);
+            switch = Some(DriveMode::Manual);
+        }
+
+        // Stopping or driving from the network takes over from a mission
+        let takes_over = matches!(
+            request,
+            MotionRequest::Set {
+                motion: Motion::Stop,
+                ..
+            } | MotionRequest::Set {
+                source: Source::Network,
+                ..
+            } | MotionRequest::Release(Source::Network)
+        );
+        if takes_over || matches!(request, MotionRequest::TurnBy(_)) {
+            self.abort_mission();
+            self.abort_turn();
+        }
+        if let MotionRequest::Mission(steps) = request {
+            self.start_mission(steps, mode, now);
+            return switch;
+        }
+        if let MotionRequest::TurnBy(degrees) = request {
+            self.start_turn(degrees, mode, now);
+            return switch;
+        }
+        if let MotionRequest::Limits(limits) = request {
+            info!("holding the car to {:?}", limits);
+            self.set_limits(limits);
+            return switch;
+        }
+
+        // Drop updates the autonomous task sent before it noticed the mode change
+        let stale = matches!(request, MotionRequest::Set { source: Source::Autonomous, .. }) && mode == DriveMode::Manual;
+
+        match if stale { None } else { self.arbiter.handle(request, now) } {
+            Some(motion) => self.target = wheel_speeds(motion),
+            None if matches!(request, MotionRequest::NetworkLease(_)) => {}
+            None => debug!("ignoring {:?}, {:?} is in control", request, self.arbiter.owner()),
+        }
+        switch
+    }
+
+    fn start_mission(&mut self, steps: Mission, mode: DriveMode, now: Instant) {
+        if mode != DriveMode::Manual || self.arbiter.owner().is_some_and(|owner| owner != Source::Network) {
+            warn!("refusing mission, {:?} is in control", self.arbiter.owner());
+            self.mission_status = MissionStatus::Aborted;
+            return;
+        }
+        info!("starting mission");
+        self.mission = Some(MissionRun {
+            steps,
+            next: 0,
+            until: now,
+        });
+    }
+
+    fn abort_mission(&mut self) {
+        if self.mission.take().is_some() {
+            warn!("mission aborted");
+            self.mission_status = MissionStatus::Aborted;
+        }
+    }
+
+    fn start_turn(&mut self, degrees: i16, mode: DriveMode, now: Instant) {
+        let Some(heading) = self.heading else {
+            warn!("can't turn by {} degrees without a heading", degrees);
+            return;
+        };
+        if mode != DriveMode::Manual || self.arbiter.owner().is_some_and(|owner| owner != Source::Network) {
+            warn!("refusing turn, {:?} is in control", self.arbiter.owner());
+            return;
+        }
+        info!("turning by {} degrees", degrees);
+        self.turn = Some(TurnRun {
+            remaining: degrees as f32,
+            last: heading,
+            until: now + TURN_TIMEOUT,
+        });
+    }
+
+    fn abort_turn(&mut self) {
+        if self.turn.take().is_some() {
+            warn!("turn aborted");
+        }
+    }
+
+    /// Turn towards the angle asked for, slowing down near it, and stop once it is reached
+    fn run_turn(&mut self, now: Instant) {
+        let Some(run) = self.turn.as_mut() else {
+            return;
+        };
+        let done = match self.heading {
+            Some(heading) => {
+                run.remaining -= wrap_degrees(heading - run.last);
+                run.last = heading;
+                let left = if run.remaining < 0.0 { -run.remaining } else { run.remaining };
+                if left <= TURN_TOLERANCE {
+                    debug!("turn done");
+                    true
+                } else if run.until <= now {
+                    warn!("turn timed out {} degrees short", run.remaining);
+                    true
+                } else {
+                    false
+                }
+            }
+            None => {
+                warn!("lost the heading, stopping the turn");
+                true
+            }
+        };
+
+        // Every tick refreshes the lease, the turn has its own timeout
+        let request = if done {
+            self.turn = None;
+            MotionRequest::Release(Source::Network)
+        } else {
+            let left = if run.remaining < 0.0 { -run.remaining } else { run.remaining };
+            let speed = (left * TURN_GAIN).clamp(MIN_TURN_SPEED, MAX_TURN_SPEED) as u8;
+            let motion = if run.remaining > 0.0 {
+                Motion::TurnRight(speed)
+            } else {
+                Motion::TurnLeft(speed)
+            };
+            MotionRequest::Set {
+                source: Source::Network,
+                motion,
+            }
+        };
+        if let Some(motion) = self.arbiter.handle(request, now) {
+            self.target = wheel_speeds(motion);
+        }
+    }
+
+    /// Steer `wheels` back onto the heading the car had when it started driving straight
+    fn hold_heading(&mut self, wheels: Wheels) -> Wheels {
+        let straight = wheels != Wheels::STOP && wheels.left() == wheels.right();
+        let (true, Some(heading)) = (straight, self.heading) else {
+            self.hold = None;
+            return wheels;
+        };
+        let course = *self.hold.get_or_insert(heading);
+        // Drifting right slows the left side and speeds up the right one
+        let correction = (wrap_degrees(heading - course) * HOLD_GAIN).clamp(-MAX_HOLD, MAX_HOLD);
+        steer(wheels, -correction as i16)
+    }
+
+    /// Start every mission step that is due
+    fn run_mission(&mut self, now: Instant, tick: &mut Tick) {
+        loop {
+            let Some(run) = self.mission.as_mut() else {
+                return;
+            };
+            if run.until > now {
+                return;
+            }
+            let index = run.next;
+            let Some(step) = run.steps.get(index).copied().flatten() else {
+                info!("mission done");
+                self.mission = None;
+                self.mission_status = MissionStatus::Done;
+                if let Some(motion) = self.arbiter.handle(MotionRequest::Release(Source::Network), now) {
+                    self.target = wheel_speeds(motion);
+                }
+                return;
+            };
+            // Steps follow on from when the previous one was due, so late ticks don't add up
+            run.next += 1;
+            run.until += Duration::from_millis(step.duration_ms() as u64);
+            self.mission_status = MissionStatus::Running { step: index as u8 };
+            debug!("mission step {}: {:?}", index, step);
+
+            let motion = match step {
+                MissionStep::Drive { throttle, steer, .. } => arcade(throttle, steer),
+                MissionStep::Turn { speed, .. } => Motion::Drive {
+                    left: speed,
+                    right: speed.saturating_neg(),
+                },
+                MissionStep::Wait { .. } => Motion::Drive { left: 0, right: 0 },
+                MissionStep::Beep { freq, ms } => {
+                    tick.beep = Some((freq, ms));
+                    continue;
+                }
+                MissionStep::Lights { r, g, b } => {
+                    tick.lights = Some((r, g, b));
+                    continue;
+                }
+            };
+            let request = MotionRequest::Set {
+                source: Source::Network,
+                motion,
+            };
+            if let Some(motion) = self.arbiter.handle(request, now) {
+                self.target = wheel_speeds(motion);
+            }
+        }
+    }
+
+    /// Advance the motors by one control period, `obstacle` is whether something is too close
+    pub fn tick(&mut self, now: Instant, obstacle: bool) -> Tick {
+        let mut tick = Tick::default();
+        self.run_mission(now, &mut tick);
+        self.run_turn(now);
+
+        let owner = self.arbiter.owner();
+        if self.arbiter.expire(now).is_some() {
+            warn!("deadman expired, stopping car");
+            self.target = Wheels::STOP;
+            tick.deadman = owner;
+            self.abort_mission();
+            self.abort_turn();
+        }
+
+        // Only forward motion is refused, the car can still turn or back away
+        if obstacle && heading_forward(self.target) {
+            if !self.blocked {
+                warn!("obstacle ahead, stopping car");
+                tick.obstacle = true;
+            }
+            self.target = Wheels::STOP;
+            self.abort_mission();
+        }
+        self.blocked = obstacle;
+
+        // Speed changes are ramped to spare the gearboxes, but stopping is always immediate
+        let target = limit(self.target, &self.limits);
+        // The correction may not take a wheel past the profile's top speed either
+        let max_speed = self.limits.max_speed.min(100) as i8;
+        let target = self.hold_heading(target).map(|speed| speed.clamp(-max_speed, max_speed));
+        let step = ramp_step(&self.limits);
+        let next = if target == Wheels::STOP {
+            target
+        } else {
+            self.output.zip(target, |current, target| ramp(current, target, step))
+        };
+        if next != self.output {
+            self.output = next;
+            tick.wheels = Some(next);
+        }
+        tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::mission::mission;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn set(source: Source, motion: Motion) -> MotionRequest {
+        MotionRequest::Set { source, motion }
+    }
+
+    fn sides(left: i8, right: i8) -> Option<Wheels> {
+        Some(Wheels::sides(left, right))
+    }
+
+    #[test]
+    fn ramps_up_and_stops_immediately() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(set(Source::Network, Motion::Forward(12)), DriveMode::Manual, at(0));
+
+        assert_eq!(controller.tick(at(0), false).wheels, sides(5, 5));
+        assert_eq!(controller.tick(at(10), false).wheels, sides(10, 10));
+        assert_eq!(controller.tick(at(20), false).wheels, sides(12, 12));
+        assert_eq!(controller.tick(at(30), false).wheels, None);
+
+        controller.request(set(Source::Network, Motion::Stop), DriveMode::Manual, at(40));
+        assert_eq!(controller.tick(at(40), false).wheels, sides(0, 0));
+    }
+
+    #[test]
+    fn other_sources_wait_for_the_owner() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(set(Source::Ir, Motion::Forward(5)), DriveMode::Manual, at(0));
+        controller.request(set(Source::Network, Motion::Backward(5)), DriveMode::Manual, at(0));
+        assert_eq!(controller.tick(at(0), false).wheels, sides(5, 5));
+
+        // The IR lease runs out and the network can take over
+        assert_eq!(controller.tick(at(300), false).deadman, Some(Source::Ir));
+        controller.request(set(Source::Network, Motion::Backward(5)), DriveMode::Manual, at(300));
+        assert_eq!(controller.tick(at(310), false).wheels, sides(-5, -5));
+    }
+
+    #[test]
+    fn deadman_stops_network_motion() {
+        let mut controller = Controller::new(Arbiter::new(Some(Duration::from_millis(100))));
+        controller.request(set(Source::Network, Motion::Forward(5)), DriveMode::Manual, at(0));
+        assert_eq!(controller.tick(at(0), false).wheels, sides(5, 5));
+        assert_eq!(controller.tick(at(90), false), Tick::default());
+
+        let tick = controller.tick(at(100), false);
+        assert_eq!(tick.deadman, Some(Source::Network));
+        assert_eq!(tick.wheels, sides(0, 0));
+    }
+
+    #[test]
+    fn arcade_mixes_and_clamps() {
+        assert_eq!(arcade(40, -20), Motion::Drive { left: 20, right: 60 });
+        assert_eq!(arcade(100, 50), Motion::Drive { left: 100, right: 50 });
+        assert_eq!(arcade(-128, -128), Motion::Drive { left: -100, right: 0 });
+    }
+
+    #[test]
+    fn mecanum_wheels_strafe_and_keep_their_heading_when_scaled() {
+        let strafe = mecanum(0, 50, 0);
+        assert_eq!(strafe, Wheels { front_left: 50, front_right: -50, rear_left: -50, rear_right: 50 });
+        assert_eq!((strafe.left(), strafe.right()), (0, 0));
+        assert_eq!(mecanum(40, 0, 20), Wheels::sides(60, 20));
+
+        // Full speed forward and sideways at once runs the diagonal wheels flat out
+        let diagonal = mecanum(100, 100, 0);
+        assert_eq!(diagonal, Wheels { front_left: 100, front_right: 0, rear_left: 0, rear_right: 100 });
+        assert_eq!(mecanum(-128, 0, 100), Wheels { front_left: 0, front_right: -100, rear_left: 0, rear_right: -100 });
+
+        // Plain wheels ignore the sideways part
+        assert_eq!(holonomic(40, 100, 20, Chassis::Tank), arcade(40, 20));
+        assert_eq!(holonomic(0, 50, 0, Chassis::Mecanum), Motion::Wheels(strafe));
+    }
+
+    #[test]
+    fn each_wheel_ramps_and_strafing_passes_obstacles() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(set(Source::Network, Motion::Wheels(mecanum(0, 8, 0))), DriveMode::Manual, at(0));
+
+        let tick = controller.tick(at(0), true);
+        assert!(!tick.obstacle);
+        assert_eq!(tick.wheels, Some(Wheels { front_left: 5, front_right: -5, rear_left: -5, rear_right: 5 }));
+        assert_eq!(controller.tick(at(10), true).wheels, Some(mecanum(0, 8, 0)));
+
+        controller.request(set(Source::Network, Motion::Wheels(mecanum(8, 8, 0))), DriveMode::Manual, at(20));
+        assert_eq!(controller.tick(at(20), true).wheels, Some(Wheels::STOP));
+    }
+
+    #[test]
+    fn network_lease_can_change_while_driving() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(set(Source::Network, Motion::Forward(5)), DriveMode::Manual, at(0));
+        controller.tick(at(0), false);
+
+        let lease = deadman_lease(100);
+        controller.request(MotionRequest::NetworkLease(lease), DriveMode::Manual, at(50));
+        assert_eq!(controller.tick(at(140), false).deadman, None);
+        assert_eq!(controller.tick(at(150), false).deadman, Some(Source::Network));
+    }
+
+    #[test]
+    fn obstacle_blocks_forward_but_not_reverse() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(set(Source::Network, Motion::Forward(5)), DriveMode::Manual, at(0));
+        controller.tick(at(0), false);
+
+        let tick = controller.tick(at(10), true);
+        assert!(tick.obstacle);
+        assert_eq!(tick.wheels, sides(0, 0));
+
+        // Still blocked, but only reported once
+        controller.request(set(Source::Network, Motion::Forward(5)), DriveMode::Manual, at(20));
+        assert_eq!(controller.tick(at(20), true), Tick::default());
+
+        controller.request(set(Source::Network, Motion::Backward(5)), DriveMode::Manual, at(30));
+        assert_eq!(controller.tick(at(30), true).wheels, sides(-5, -5));
+    }
+
+    #[test]
+    fn profiles_limit_every_source() {
+        let kid = Profile::Kid.limits();
+        assert_eq!(limit(Wheels::sides(100, 100), &kid), Wheels::sides(40, 40));
+        // Pivoting is capped, gentle curves aren't
+        assert_eq!(limit(Wheels::sides(-100, 100), &kid), Wheels::sides(-25, 25));
+        assert_eq!(limit(Wheels::sides(100, 0), &kid), Wheels::sides(40, 0));
+        assert_eq!(limit(Wheels::sides(100, -100), &Profile::Race.limits()), Wheels::sides(100, -100));
+
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(MotionRequest::Limits(kid), DriveMode::Manual, at(0));
+        controller.request(set(Source::Ir, Motion::Forward(100)), DriveMode::Manual, at(0));
+        assert_eq!(controller.tick(at(0), false).wheels, sides(1, 1));
+        assert_eq!(controller.tick(at(10), false).wheels, sides(2, 2));
+
+        assert!(controller.too_close(Some(35)));
+        assert!(!controller.too_close(Some(40)));
+        assert!(!controller.too_close(None));
+    }
+
+    #[test]
+    fn manual_stop_ends_autonomous_mode() {
+        let mode = DriveMode::LightFollow(shared::LightTarget::Brighter);
+        let mut controller = Controller::new(Arbiter::new(None));
+        assert_eq!(controller.request(set(Source::Autonomous, Motion::Forward(5)), mode, at(0)), None);
+        assert_eq!(
+            controller.request(set(Source::Ir, Motion::Stop), mode, at(10)),
+            Some(DriveMode::Manual)
+        );
+
+        // Late updates from the autonomous task are ignored once back in manual
+        controller.request(set(Source::Autonomous, Motion::Forward(5)), DriveMode::Manual, at(20));
+        assert_eq!(controller.tick(at(20), false).wheels, None);
+    }
+
+    #[test]
+    fn missions_run_their_steps_on_time() {
+        let steps = [
+            MissionStep::Lights { r: 255, g: 0, b: 0 },
+            MissionStep::Drive {
+                throttle: 10,
+                steer: 0,
+                ms: 100,
+            },
+            MissionStep::Beep { freq: 440, ms: 50 },
+            MissionStep::Turn { speed: 10, ms: 50 },
+        ];
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(MotionRequest::Mission(mission(&steps).unwrap()), DriveMode::Manual, at(0));
+
+        let tick = controller.tick(at(0), false);
+        assert_eq!(tick.lights, Some((255, 0, 0)));
+        assert_eq!(tick.wheels, sides(5, 5));
+        assert_eq!(controller.mission(), MissionStatus::Running { step: 1 });
+        controller.tick(at(10), false);
+        assert_eq!(controller.tick(at(90), false).wheels, None);
+
+        // A late tick beeps and starts turning on the way
+        let tick = controller.tick(at(105), false);
+        assert_eq!(tick.beep, Some((440, 50)));
+        assert_eq!(tick.wheels, sides(10, 5));
+        assert_eq!(controller.mission(), MissionStatus::Running { step: 3 });
+
+        // The turn still ends on schedule
+        assert_eq!(controller.tick(at(150), false).wheels, sides(0, 0));
+        assert_eq!(controller.mission(), MissionStatus::Done);
+    }
+
+    #[test]
+    fn stopping_or_driving_aborts_a_mission() {
+        let steps = [MissionStep::Drive {
+            throttle: 10,
+            steer: 0,
+            ms: 1000,
+        }];
+        let start = MotionRequest::Mission(mission(&steps).unwrap());
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.request(start, DriveMode::Manual, at(0));
+        controller.tick(at(0), false);
+
+        // The remote can't take over, but it can stop the car
+        controller.request(set(Source::Ir, Motion::Backward(5)), DriveMode::Manual, at(10));
+        assert_eq!(controller.tick(at(10), false).wheels, sides(10, 10));
+        controller.request(set(Source::Ir, Motion::Stop), DriveMode::Manual, at(20));
+        assert_eq!(controller.mission(), MissionStatus::Aborted);
+        assert_eq!(controller.tick(at(20), false).wheels, sides(0, 0));
+
+        controller.request(start, DriveMode::Manual, at(30));
+        controller.tick(at(30), false);
+        controller.request(set(Source::Network, Motion::Backward(5)), DriveMode::Manual, at(40));
+        assert_eq!(controller.mission(), MissionStatus::Aborted);
+
+        let mode = DriveMode::LightFollow(shared::LightTarget::Brighter);
+        controller.request(start, mode, at(50));
+        assert_eq!(controller.mission(), MissionStatus::Aborted);
+    }
+
+    #[test]
+    fn deadman_aborts_missions_unless_kept_alive() {
+        let steps = [MissionStep::Drive {
+            throttle: 10,
+            steer: 0,
+            ms: 300,
+        }];
+        let mut controller = Controller::new(Arbiter::new(Some(Duration::from_millis(100))));
+        controller.request(MotionRequest::Mission(mission(&steps).unwrap()), DriveMode::Manual, at(0));
+        controller.tick(at(0), false);
+
+        controller.request(MotionRequest::Keepalive(Source::Network), DriveMode::Manual, at(80));
+        assert_eq!(controller.tick(at(150), false).deadman, None);
+        assert_eq!(controller.tick(at(180), false).deadman, Some(Source::Network));
+        assert_eq!(controller.mission(), MissionStatus::Aborted);
+    }
+
+    #[test]
+    fn heading_hold_steers_back_on_course() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.set_heading(Some(10.0));
+        controller.request(set(Source::Network, Motion::Forward(20)), DriveMode::Manual, at(0));
+        for ms in (0..50).step_by(10) {
+            controller.tick(at(ms), false);
+        }
+        assert_eq!(controller.output(), Wheels::sides(20, 20));
+
+        // Drifted 3 degrees right, the left side slows down and the right one speeds up
+        controller.set_heading(Some(13.0));
+        assert_eq!(controller.tick(at(50), false).wheels, sides(15, 25));
+        assert_eq!(controller.tick(at(60), false).wheels, sides(14, 26));
+
+        // Turning on purpose isn't corrected, and the new course is held from where it ended
+        controller.request(set(Source::Network, Motion::Drive { left: 20, right: 10 }), DriveMode::Manual, at(70));
+        controller.set_heading(Some(40.0));
+        for ms in (70..120).step_by(10) {
+            controller.tick(at(ms), false);
+        }
+        assert_eq!(controller.output(), Wheels::sides(20, 10));
+        controller.request(set(Source::Network, Motion::Forward(20)), DriveMode::Manual, at(120));
+        for ms in (120..170).step_by(10) {
+            controller.tick(at(ms), false);
+        }
+        assert_eq!(controller.output(), Wheels::sides(20, 20));
+
+        // Without the IMU the car drives open loop
+        controller.set_heading(Some(45.0));
+        controller.tick(at(170), false);
+        controller.set_heading(None);
+        controller.tick(at(180), false);
+        assert_eq!(controller.output(), Wheels::sides(20, 20));
+    }
+
+    #[test]
+    fn heading_hold_keeps_to_the_profile() {
+        let mut controller = Controller::new(Arbiter::new(None));
+        controller.set_limits(Profile::Kid.limits());
+        controller.set_heading(Some(0.0));
+        controller.request(set(Source::Network, Motion::Forward(100)), DriveMode::Manual, at(0));
+        for ms in (0..500).step_by(10) {
+            controller.tick(at(ms), false);
+        }
+        assert_eq!(controller.output(), Wheels::sides(40, 40));
+
+        // Far off course the full correction only slows the faster side
+        controller.set_heading(Some(-30.0));
+        for ms in (500..1000).step_by(10) {
+            controller.tick(at(ms), false);
+        }
+        assert_eq!(controller.output(), Wheels::sides(40, 20));
+    }
+
+    #[test]
+    fn turns_by_an_angle_with_the_heading() {
+        let mut controller = Controller::new(Arbiter::new(Some(Duration::from_millis(100))));
+        controller.request(MotionRequest::TurnBy(90), DriveMode::Manual, at(0));
+        assert_eq!(controller.tick(at(0), false).wheels, None);
+
+        controller.set_heading(Some(170.0));
+        controller.request(MotionRequest::TurnBy(90), DriveMode::Manual, at(0));
+        assert_eq!(controller.tick(at(0), false).wheels, sides(5, -5));
+
+        // Across -180, slowing down near the end and keeping the lease as long as it takes
+        controller.set_heading(Some(-130.0));
+        for ms in (10..=200).step_by(10) {
+            assert_eq!(controller.tick(at(ms), false).deadman, None);
+        }
+        assert_eq!(controller.output(), Wheels::sides(30, -30));
+        controller.set_heading(Some(-101.0));
+        assert_eq!(controller.tick(at(210), false).wheels, sides(0, 0));
+        assert_eq!(controller.tick(at(400), false), Tick::default());
+
+        // Overshooting turns back, a stop or the timeout ends the turn
+        controller.request(MotionRequest::TurnBy(-45), DriveMode::Manual, at(400));
+        controller.set_heading(Some(-150.0));
+        assert_eq!(controller.tick(at(400), false).wheels, sides(5, -5));
+        assert_eq!(controller.tick(at(400) + TURN_TIMEOUT, false).wheels, sides(0, 0));
+        controller.request(MotionRequest::TurnBy(-45), DriveMode::Manual, at(6000));
+        controller.tick(at(6000), false);
+        controller.request(set(Source::Ir, Motion::Stop), DriveMode::Manual, at(6010));
+        assert_eq!(controller.tick(at(6010), false).wheels, sides(0, 0));
+        assert_eq!(controller.tick(at(6020), false), Tick::default());
+    }
+}
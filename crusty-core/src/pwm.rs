@@ -0,0 +1,87 @@
+//! Timing of the motor PWM for a target frequency
+//!
+//! An RP2040 PWM slice counts system clock cycles, slowed down by an integer divider, from 0 up
+//! to a top value and wraps, or counts back down in phase-correct mode. The frequency follows as
+//! `clock / (divider * (top + 1))`, halved in phase-correct mode. The smallest divider that fits
+//! the 16 bit counter is picked, so the duty cycle gets as many steps as possible.
+
+use shared::config::PwmSettings;
+
+/// Fewest counts per period, so every percent of duty cycle is a step of its own
+pub const MIN_COUNTS: u32 = 100;
+
+/// Divider and counter top of a PWM slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timing {
+    pub divider: u8,
+    pub top: u16,
+}
+
+impl Timing {
+    /// The frequency this timing runs at, which rounding makes slightly off the target
+    pub fn frequency_hz(&self, clock_hz: u32, phase_correct: bool) -> u32 {
+        let passes = if phase_correct { 2 } else { 1 };
+        clock_hz / (self.divider as u32 * (self.top as u32 + 1) * passes)
+    }
+}
+
+/// Timing for `settings` with the system clock at `clock_hz`, `None` if the frequency is out of
+/// reach
+pub fn timing(clock_hz: u32, settings: PwmSettings) -> Option<Timing> {
+    let frequency = settings.frequency_hz as u64;
+    if frequency == 0 {
+        return None;
+    }
+    // Clock cycles per period, counting down again takes as long as counting up
+    let cycles = clock_hz as u64 / if settings.phase_correct { 2 } else { 1 };
+    let divider = cycles.div_ceil(frequency * (u16::MAX as u64 + 1)).max(1);
+    let divider = u8::try_from(divider).ok()?;
+    let counts = (cycles + frequency * divider as u64 / 2) / (frequency * divider as u64);
+    if counts < MIN_COUNTS as u64 {
+        return None;
+    }
+    Some(Timing {
+        divider,
+        top: (counts - 1) as u16,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_HZ: u32 = 125_000_000;
+
+    fn settings(frequency_hz: u32, phase_correct: bool) -> PwmSettings {
+        PwmSettings {
+            frequency_hz,
+            phase_correct,
+        }
+    }
+
+    #[test]
+    fn slow_frequencies_need_a_divider() {
+        let timing = timing(CLOCK_HZ, PwmSettings::new()).unwrap();
+        assert_eq!(timing, Timing { divider: 4, top: 62_499 });
+        assert_eq!(timing.frequency_hz(CLOCK_HZ, false), 500);
+    }
+
+    #[test]
+    fn ultrasonic_frequencies_run_undivided() {
+        assert_eq!(timing(CLOCK_HZ, settings(20_000, false)), Some(Timing { divider: 1, top: 6_249 }));
+
+        let centred = timing(CLOCK_HZ, settings(20_000, true)).unwrap();
+        assert_eq!(centred, Timing { divider: 1, top: 3_124 });
+        assert_eq!(centred.frequency_hz(CLOCK_HZ, true), 20_000);
+    }
+
+    #[test]
+    fn unreachable_frequencies_are_refused() {
+        assert_eq!(timing(CLOCK_HZ, settings(0, false)), None);
+        assert_eq!(timing(CLOCK_HZ, settings(5, false)), None);
+        assert_eq!(timing(CLOCK_HZ, settings(2_000_000, false)), None);
+        assert!(timing(CLOCK_HZ, settings(1_250_000, false)).is_some());
+        assert_eq!(timing(CLOCK_HZ, settings(1_250_000, true)), None);
+    }
+}
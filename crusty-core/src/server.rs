@@ -0,0 +1,353 @@
+//! One client connection to the command port: greeting, authentication and command dispatch
+//!
+//! The transport and everything a command ends up touching are supplied by the caller, so the
+//! firmware serves TCP sockets from embassy-net with the real hardware behind [`Platform`] while
+//! the simulator serves std sockets and a model of the car.
+
+use embedded_io_async::{Error as _, Read, Write};
+use shared::auth::{self, Key, Nonce, ServerSession};
+use shared::config::{Calibration, CarConfig};
+use shared::event_log::{Event, LogPage};
+use shared::frame::{self, FrameReader};
+use shared::mission::MissionStatus;
+use shared::{CarCommand, ClientMessage, DriveMode, ErrorCode, Melody, ProfileLock, ServerMessage, Telemetry};
+
+use crate::motion::{self, Motion, MotionRequest, Source};
+
+const FRAME_LEN: usize = frame::HEADER_LEN + frame::MAX_FRAME_LEN;
+
+/// What commands act on
+#[allow(async_fn_in_trait)]
+pub trait Platform {
+    /// Queue a request for the motion control loop
+    async fn motion(&mut self, request: MotionRequest);
+
+    /// Switch the drive mode
+    fn set_mode(&mut self, mode: DriveMode);
+
+    /// Latest telemetry
+    fn telemetry(&self) -> Telemetry;
+
+    /// Play a tone on the buzzer
+    fn beep(&mut self, freq: u16, ms: u16);
+
+    /// Play a built-in melody on the buzzer
+    fn play(&mut self, melody: Melody);
+
+    /// Persist a changed configuration and apply it, `false` if it couldn't be stored
+    fn store_config(&mut self, config: &CarConfig) -> bool;
+
+    /// Correct the motors with `calibration` from now on, whether or not it is stored
+    fn set_calibration(&mut self, calibration: Calibration);
+
+    /// Take drive setpoints over UDP from this connection's client, tagged with `key` when the car
+    /// has one, until the connection ends
+    fn open_drive_channel(&mut self, key: Option<Key>);
+
+    /// Keep a record of `event` in the event log
+    fn record(&mut self, event: Event);
+
+    /// Entries of the event log from `from` on
+    async fn read_log(&mut self, from: u32) -> LogPage;
+}
+
+/// Serve one connection until the client leaves, the transport fails or authentication fails
+///
+/// `nonce` must be fresh for every connection, it challenges the client when `config` has a key.
+/// The connection and the messages rejected on it are recorded in the event log, the rejections
+/// counted in one entry, so a flood of bad frames can't wear out the log. Whatever the client was
+/// driving stops when it leaves, which hands the car back to the IR remote.
+pub async fn serve<S: Read + Write>(
+    socket: &mut S,
+    nonce: Nonce,
+    config: &mut CarConfig,
+    platform: &mut impl Platform,
+) {
+    platform.record(Event::Connected);
+    let mut rejected = None;
+    serve_connection(socket, nonce, config, platform, &mut rejected).await;
+    platform.motion(MotionRequest::Release(Source::Network)).await;
+    if let Some((code, count)) = rejected {
+        platform.record(Event::Rejected { code, count });
+    }
+    platform.record(Event::Disconnected);
+}
+
+async fn serve_connection<S: Read + Write>(
+    socket: &mut S,
+    nonce: Nonce,
+    config: &mut CarConfig,
+    platform: &mut impl Platform,
+    rejected: &mut Option<(ErrorCode, u8)>,
+) {
+    let mut tx_frame = [0; FRAME_LEN];
+
+    // Challenge the client when a pre-shared key is configured
+    let mut session = config.auth_key.map(|key| ServerSession::new(key, nonce));
+    let hello = ServerMessage::Hello {
+        challenge: session.as_ref().map(ServerSession::challenge),
+    };
+    if send_message(socket, &hello, &mut tx_frame).await.is_err() {
+        return;
+    }
+    if session.is_none() {
+        platform.open_drive_channel(None);
+    }
+
+    let mut reader = FrameReader::<FRAME_LEN>::new();
+    loop {
+        let n = match socket.read(reader.space()).await {
+            Ok(0) => {
+                warn!("read EOF");
+                return;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                warn!("read error: {:?}", e.kind());
+                return;
+            }
+        };
+        reader.filled(n);
+
+        while let Some(message) = reader.next_message::<ClientMessage>() {
+            let reply = match message {
+                Ok(message) => handle_message(message, &nonce, &mut session, config, platform).await,
+                Err(_) => {
+                    warn!("Failed to parse frame");
+                    ServerMessage::Error(ErrorCode::Malformed)
+                }
+            };
+            if let ServerMessage::Error(code) = reply {
+                let (_, count) = rejected.get_or_insert((code, 0));
+                *count = count.saturating_add(1);
+            }
+
+            if let Err(e) = send_message(socket, &reply, &mut tx_frame).await {
+                warn!("write error: {:?}", e.kind());
+                return;
+            }
+
+            // Don't let a client keep guessing the key on the same connection
+            if reply == ServerMessage::Error(ErrorCode::AuthFailed) {
+                warn!("Authentication failed, closing connection");
+                return;
+            }
+        }
+    }
+}
+
+/// Check a client message against the session and execute the command it carries
+async fn handle_message(
+    message: ClientMessage,
+    nonce: &Nonce,
+    session: &mut Option<ServerSession>,
+    config: &mut CarConfig,
+    platform: &mut impl Platform,
+) -> ServerMessage {
+    let command = match (message, session.as_mut()) {
+        (ClientMessage::Command(command), None) => command,
+        (ClientMessage::Command(_), Some(_)) => {
+            warn!("Rejecting unauthenticated command");
+            return ServerMessage::Error(ErrorCode::Unauthorized);
+        }
+        (ClientMessage::Auth(response), Some(session)) => {
+            return match session.authenticate(&response) {
+                Ok(()) => {
+                    info!("Client authenticated");
+                    platform.open_drive_channel(session.drive_key());
+                    ServerMessage::Authenticated
+                }
+                Err(e) => ServerMessage::Error(e.into()),
+            };
+        }
+        // Nothing to check without a key, let the client carry on
+        (ClientMessage::Auth(_), None) => return ServerMessage::Authenticated,
+        (ClientMessage::Signed(signed), Some(session)) => match session.open(&signed) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("Rejecting signed command");
+                return ServerMessage::Error(e.into());
+            }
+        },
+        (ClientMessage::Signed(signed), None) => signed.command,
+    };
+
+    let drive = |motion| MotionRequest::Set {
+        source: Source::Network,
+        motion,
+    };
+
+    // Execute the command on the car
+    match command {
+        CarCommand::Forward(speed) => {
+            info!("Moving forward with speed {}", speed);
+            platform.motion(drive(Motion::Forward(speed))).await;
+        }
+        CarCommand::Backward(speed) => {
+            info!("Moving backward with speed {}", speed);
+            platform.motion(drive(Motion::Backward(speed))).await;
+        }
+        CarCommand::TurnLeft(speed) => {
+            info!("Turning left with speed {}", speed);
+            platform.motion(drive(Motion::TurnLeft(speed))).await;
+        }
+        CarCommand::TurnRight(speed) => {
+            info!("Turning right with speed {}", speed);
+            platform.motion(drive(Motion::TurnRight(speed))).await;
+        }
+        CarCommand::Drive { throttle, steer } => {
+            debug!("Driving with throttle {} steer {}", throttle, steer);
+            platform.motion(drive(motion::arcade(throttle, steer))).await;
+        }
+        CarCommand::Holonomic { vx, vy, omega } => {
+            debug!("Driving with vx {} vy {} omega {} on {:?} wheels", vx, vy, omega, config.chassis);
+            platform.motion(drive(motion::holonomic(vx, vy, omega, config.chassis))).await;
+        }
+        CarCommand::Stop => {
+            info!("Stopping car");
+            platform.motion(drive(Motion::Stop)).await;
+        }
+        CarCommand::SetAuthKey(key) => {
+            info!("Updating pre-shared key, authentication {}", if key.is_some() { "enabled" } else { "disabled" });
+            config.auth_key = key;
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::Beep { freq, ms } => {
+            info!("Beeping at {} Hz for {} ms", freq, ms);
+            platform.beep(freq, ms);
+        }
+        CarCommand::PlayMelody(melody) => {
+            info!("Playing melody {:?}", melody);
+            platform.play(melody);
+        }
+        CarCommand::SetMode(mode) => {
+            info!("Switching to {:?} mode", mode);
+            platform.set_mode(mode);
+            // Hand control over to the new mode right away instead of waiting for a lease to run out
+            let previous = if mode == DriveMode::Manual {
+                Source::Autonomous
+            } else {
+                Source::Network
+            };
+            platform.motion(MotionRequest::Release(previous)).await;
+        }
+        CarCommand::GetTelemetry => {
+            let mut telemetry = platform.telemetry();
+            telemetry.profile = config.profile;
+            telemetry.profile_locked = config.profile_lock.is_some();
+            // Following a mission's progress keeps it going while a deadman is set
+            if matches!(telemetry.mission, MissionStatus::Running { .. }) {
+                platform.motion(MotionRequest::Keepalive(Source::Network)).await;
+            }
+            return ServerMessage::Telemetry(telemetry);
+        }
+        CarCommand::GetConfig => return ServerMessage::Config(config.settings()),
+        CarCommand::SetConfig(settings) => {
+            info!("Updating settings, deadman {} ms, {:?} wheels", settings.deadman_ms, settings.chassis);
+            config.apply(settings);
+            platform
+                .motion(MotionRequest::NetworkLease(motion::deadman_lease(config.deadman_ms)))
+                .await;
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::RunMission(mission) => {
+            info!("Running mission of {} steps", mission.iter().flatten().count());
+            platform.motion(MotionRequest::Mission(mission)).await;
+        }
+        CarCommand::SetCalibration { calibration, store } => {
+            if !calibration.is_valid() {
+                warn!("Refusing a motor calibration with a gain over 100");
+                return ServerMessage::Error(ErrorCode::OutOfRange);
+            }
+            // The minimum duty would drive the motors past the profile's limits
+            if config.profile_lock.is_some() {
+                warn!("Refusing to change the motor calibration, the profile is locked");
+                return ServerMessage::Error(ErrorCode::ProfileLocked);
+            }
+            info!("Applying motor calibration{}", if store { " and storing it" } else { " to try it out" });
+            platform.set_calibration(calibration);
+            if store {
+                config.calibration = calibration;
+                if !platform.store_config(config) {
+                    return ServerMessage::Error(ErrorCode::StorageFailed);
+                }
+            }
+        }
+        CarCommand::GetCalibration => return ServerMessage::Calibration(config.calibration),
+        CarCommand::ReadLog { from } => return ServerMessage::Log(platform.read_log(from).await),
+        CarCommand::SetProfile(profile) => {
+            if config.profile_lock.is_some() {
+                warn!("Refusing to switch to the {:?} profile, it is locked", profile);
+                return ServerMessage::Error(ErrorCode::ProfileLocked);
+            }
+            info!("Switching to the {:?} profile", profile);
+            config.profile = profile;
+            platform.motion(MotionRequest::Limits(profile.limits())).await;
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::LockProfile(ProfileLock::Lock { admin }) => {
+            // Without a session the admin key came in the clear and anyone could lock the car
+            if session.is_none() {
+                warn!("Refusing to lock the profile without authentication");
+                return ServerMessage::Error(ErrorCode::AuthFailed);
+            }
+            // Relocking with another key would take the lock from whoever holds it
+            if config.profile_lock.is_some() {
+                warn!("The profile is already locked");
+                return ServerMessage::Error(ErrorCode::ProfileLocked);
+            }
+            info!("Locking the {:?} profile", config.profile);
+            config.profile_lock = Some(admin);
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::LockProfile(ProfileLock::Unlock { proof }) => {
+            let Some(admin) = config.profile_lock else {
+                return ServerMessage::Ack;
+            };
+            // The proof is only good for this connection's challenge, so it can't be replayed
+            if !auth::verify_unlock(&admin, nonce, &proof) {
+                warn!("Wrong admin key for the profile lock");
+                return ServerMessage::Error(ErrorCode::AuthFailed);
+            }
+            info!("Unlocking the {:?} profile", config.profile);
+            config.profile_lock = None;
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::GetProfileChallenge => return ServerMessage::ProfileChallenge(*nonce),
+        CarCommand::SetIrKeymap(keymap) => {
+            info!("Updating IR key bindings");
+            config.ir_keymap = keymap;
+            if !platform.store_config(config) {
+                return ServerMessage::Error(ErrorCode::StorageFailed);
+            }
+        }
+        CarCommand::TurnBy { degrees } => {
+            if platform.telemetry().imu.is_none() {
+                warn!("Can't turn by {} degrees without an IMU", degrees);
+                return ServerMessage::Error(ErrorCode::NoImu);
+            }
+            info!("Turning by {} degrees", degrees);
+            platform.motion(MotionRequest::TurnBy(degrees)).await;
+        }
+    }
+
+    ServerMessage::Ack
+}
+
+async fn send_message<S: Write>(socket: &mut S, message: &ServerMessage, buf: &mut [u8]) -> Result<(), S::Error> {
+    let Ok(n) = frame::encode(message, buf) else {
+        warn!("Failed to encode reply");
+        return Ok(());
+    };
+    socket.write_all(&buf[..n]).await
+}
@@ -0,0 +1,441 @@
+//! The controller page the car serves to browsers, and the WebSocket it drives the car through
+//!
+//! Browsers can't open the command port, so the page connects back to the car with a WebSocket
+//! on the HTTP port instead. Every binary message carries bytes of the command stream, framed as
+//! on the command port, so [`WebSocket`] turns the upgraded connection into a stream that
+//! [`server::serve`](crate::server::serve) handles like any other client. The page itself is
+//! gzipped at build time from `web/index.html` and served as is.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use embedded_io_async::{ErrorKind, ErrorType, Read, ReadExactError, Write};
+use sha1::{Digest, Sha1};
+
+/// Port the page and the WebSocket are served on
+pub const HTTP_PORT: u16 = 80;
+
+/// The controller page, gzipped
+pub static PAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/index.html.gz"));
+
+/// Longest request head accepted, browsers send well under this
+const HEAD_LEN: usize = 2048;
+
+const WEBSOCKET_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Length of a `Sec-WebSocket-Accept` value
+const ACCEPT_LEN: usize = 28;
+
+// WebSocket opcodes
+const CONTINUATION: u8 = 0x0;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Answer one HTTP request, returning the connection as a WebSocket if the browser asked for one
+///
+/// The page is sent for `/`, the WebSocket lives at `/ws` and anything else gets a 404.
+pub async fn respond<S: Read + Write>(mut socket: S) -> Result<Option<WebSocket<S>>, S::Error> {
+    let mut head = [0; HEAD_LEN];
+    let Some(len) = read_head(&mut socket, &mut head).await? else {
+        return Ok(None);
+    };
+
+    let mut headers = [httparse::EMPTY_HEADER; 24];
+    let mut request = httparse::Request::new(&mut headers);
+    if !matches!(request.parse(&head[..len]), Ok(httparse::Status::Complete(_))) {
+        warn!("Malformed HTTP request");
+        socket.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+        socket.flush().await?;
+        return Ok(None);
+    }
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    };
+
+    // The caller closes the connection as soon as this returns, so everything is flushed first
+    let websocket = match (request.method, request.path) {
+        (Some("GET"), Some("/" | "/index.html")) => {
+            info!("Serving the controller page");
+            let mut length = [0; 10];
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Encoding: gzip\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Length: ")
+                .await?;
+            socket.write_all(decimal(PAGE.len(), &mut length)).await?;
+            socket.write_all(b"\r\n\r\n").await?;
+            socket.write_all(PAGE).await?;
+            false
+        }
+        (Some("GET"), Some("/ws")) => {
+            let upgrade = header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case(b"websocket"));
+            match header("Sec-WebSocket-Key").filter(|_| upgrade) {
+                Some(key) => {
+                    info!("Browser connected over WebSocket");
+                    socket
+                        .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: ")
+                        .await?;
+                    socket.write_all(&accept_key(key)).await?;
+                    socket.write_all(b"\r\n\r\n").await?;
+                    true
+                }
+                None => {
+                    socket.write_all(b"HTTP/1.1 426 Upgrade Required\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+                    false
+                }
+            }
+        }
+        _ => {
+            socket.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+            false
+        }
+    };
+    socket.flush().await?;
+    Ok(websocket.then(|| WebSocket::new(socket)))
+}
+
+/// Read up to the blank line ending the request head, `None` if the browser left or sent too much
+async fn read_head<S: Read>(socket: &mut S, head: &mut [u8]) -> Result<Option<usize>, S::Error> {
+    let mut len = 0;
+    while !head[..len].ends_with(b"\r\n\r\n") {
+        if len == head.len() {
+            warn!("HTTP request head too long");
+            return Ok(None);
+        }
+        // One byte at a time so nothing past the head is taken from the socket
+        match socket.read(&mut head[len..len + 1]).await? {
+            0 => return Ok(None),
+            n => len += n,
+        }
+    }
+    Ok(Some(len))
+}
+
+/// The `Sec-WebSocket-Accept` answer to a `Sec-WebSocket-Key`
+fn accept_key(key: &[u8]) -> [u8; ACCEPT_LEN] {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(WEBSOCKET_GUID);
+    let mut accept = [0; ACCEPT_LEN];
+    // A SHA-1 digest always encodes to ACCEPT_LEN bytes
+    let _ = BASE64.encode_slice(sha1.finalize(), &mut accept);
+    accept
+}
+
+fn decimal(mut n: usize, buf: &mut [u8; 10]) -> &[u8] {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[start..];
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSocketError<E> {
+    Io(E),
+    /// The browser broke the WebSocket protocol or sent a text message
+    Protocol,
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for WebSocketError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            WebSocketError::Io(e) => e.kind(),
+            WebSocketError::Protocol => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl<E> From<E> for WebSocketError<E> {
+    fn from(e: E) -> Self {
+        WebSocketError::Io(e)
+    }
+}
+
+/// The payload of the binary messages of an upgraded connection, as a stream
+///
+/// Reads return the payload of the browser's messages, pings are answered and a close ends the
+/// stream. Every write goes out as one binary message.
+pub struct WebSocket<S> {
+    socket: S,
+    remaining: u64, // Payload left in the message being read
+    mask: [u8; 4],
+    masked: usize, // Where in the mask the next payload byte is
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    pub fn new(socket: S) -> Self {
+        Self {
+            socket,
+            remaining: 0,
+            mask: [0; 4],
+            masked: 0,
+        }
+    }
+
+    /// Read the next frame header, `false` once the browser closed the connection
+    async fn next_frame(&mut self) -> Result<bool, WebSocketError<S::Error>> {
+        loop {
+            let mut header = [0; 2];
+            if !read_exact(&mut self.socket, &mut header).await? {
+                return Ok(false);
+            }
+            let opcode = header[0] & 0x0f;
+            // Frames from browsers are always masked
+            if header[1] & 0x80 == 0 {
+                return Err(WebSocketError::Protocol);
+            }
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0; 2];
+                    read_exact(&mut self.socket, &mut len).await?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0; 8];
+                    read_exact(&mut self.socket, &mut len).await?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            let mut mask = [0; 4];
+            read_exact(&mut self.socket, &mut mask).await?;
+
+            match opcode {
+                BINARY | CONTINUATION => {
+                    self.remaining = len;
+                    self.mask = mask;
+                    self.masked = 0;
+                    if len > 0 {
+                        return Ok(true);
+                    }
+                }
+                PING | PONG | CLOSE if len <= 125 => {
+                    let mut payload = [0; 125];
+                    let payload = &mut payload[..len as usize];
+                    read_exact(&mut self.socket, payload).await?;
+                    for (i, byte) in payload.iter_mut().enumerate() {
+                        *byte ^= mask[i % 4];
+                    }
+                    match opcode {
+                        PING => self.send(PONG, payload).await?,
+                        CLOSE => {
+                            // Echo the close, the browser then hangs up
+                            self.send(CLOSE, payload).await?;
+                            return Ok(false);
+                        }
+                        _ => {}
+                    }
+                }
+                _ => return Err(WebSocketError::Protocol),
+            }
+        }
+    }
+
+    /// Send one unfragmented frame, servers don't mask
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<(), S::Error> {
+        let mut header = [0; 10];
+        header[0] = 0x80 | opcode;
+        let header = match payload.len() {
+            len @ 0..=125 => {
+                header[1] = len as u8;
+                &header[..2]
+            }
+            len @ 126..=0xffff => {
+                header[1] = 126;
+                header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+                &header[..4]
+            }
+            len => {
+                header[1] = 127;
+                header[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+                &header[..10]
+            }
+        };
+        self.socket.write_all(header).await?;
+        self.socket.write_all(payload).await
+    }
+}
+
+/// Fill `buf`, `false` if the connection ended first
+async fn read_exact<S: Read>(socket: &mut S, buf: &mut [u8]) -> Result<bool, WebSocketError<S::Error>> {
+    match socket.read_exact(buf).await {
+        Ok(()) => Ok(true),
+        Err(ReadExactError::UnexpectedEof) => Ok(false),
+        Err(ReadExactError::Other(e)) => Err(WebSocketError::Io(e)),
+    }
+}
+
+impl<S: Read + Write> ErrorType for WebSocket<S> {
+    type Error = WebSocketError<S::Error>;
+}
+
+impl<S: Read + Write> Read for WebSocket<S> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 && !self.next_frame().await? {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.socket.read(&mut buf[..len]).await?;
+        if n == 0 {
+            return Ok(0);
+        }
+        for byte in &mut buf[..n] {
+            *byte ^= self.mask[self.masked];
+            self.masked = (self.masked + 1) % 4;
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for WebSocket<S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.send(BINARY, buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(self.socket.flush().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::convert::Infallible;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// A connection that reads `input` and records what is written to it
+    struct Stream<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl ErrorType for Stream<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Stream<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let n = buf.len().min(self.input.len());
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Stream<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Nothing here ever waits
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future waited"),
+        }
+    }
+
+    fn masked_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = std::vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        assert_eq!(&accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="), b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn page_is_served_gzipped() {
+        let mut stream = Stream {
+            input: b"GET / HTTP/1.1\r\nHost: car\r\n\r\n",
+            output: Vec::new(),
+        };
+        assert!(block_on(respond(&mut stream)).unwrap().is_none());
+        let head_len = stream.output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = core::str::from_utf8(&stream.output[..head_len]).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains(&std::format!("Content-Length: {}\r\n", PAGE.len())));
+        assert_eq!(&stream.output[head_len..], PAGE);
+        assert_eq!(&PAGE[..2], [0x1f, 0x8b]);
+
+        let mut stream = Stream {
+            input: b"GET /favicon.ico HTTP/1.1\r\n\r\n",
+            output: Vec::new(),
+        };
+        assert!(block_on(respond(&mut stream)).unwrap().is_none());
+        assert!(stream.output.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn websocket_carries_the_command_stream() {
+        let mut input = b"GET /ws HTTP/1.1\r\nHost: car\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n".to_vec();
+        input.extend(masked_frame(BINARY, b"hello"));
+        input.extend(masked_frame(PING, b"hi"));
+        input.extend(masked_frame(CONTINUATION, b" car"));
+        input.extend(masked_frame(CLOSE, &[]));
+        let mut stream = Stream {
+            input: &input,
+            output: Vec::new(),
+        };
+
+        block_on(async {
+            let mut ws = respond(&mut stream).await.unwrap().unwrap();
+            let mut buf = [0; 16];
+            // Split reads continue the mask where they left off
+            ws.read_exact(&mut buf[..3]).await.unwrap();
+            ws.read_exact(&mut buf[3..9]).await.unwrap();
+            assert_eq!(&buf[..9], b"hello car");
+            ws.write_all(b"ack").await.unwrap();
+            assert_eq!(ws.read(&mut buf).await.unwrap(), 0);
+        });
+
+        let output = &stream.output;
+        let head_len = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = core::str::from_utf8(&output[..head_len]).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(&output[head_len..], b"\x8a\x02hi\x82\x03ack\x88\x00");
+    }
+
+    #[test]
+    fn unmasked_and_text_frames_are_refused() {
+        let mut ws = WebSocket::new(Stream {
+            input: b"\x82\x01x",
+            output: Vec::new(),
+        });
+        assert!(matches!(block_on(ws.read(&mut [0; 4])), Err(WebSocketError::Protocol)));
+
+        let text = masked_frame(0x1, b"x");
+        let mut ws = WebSocket::new(Stream {
+            input: &text,
+            output: Vec::new(),
+        });
+        assert!(matches!(block_on(ws.read(&mut [0; 4])), Err(WebSocketError::Protocol)));
+    }
+}
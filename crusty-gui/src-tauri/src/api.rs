@@ -0,0 +1,521 @@
+//! The commands and events the frontend uses, as a taurpc router
+//!
+//! The TypeScript bindings in `src/lib/bindings.ts` are generated from this trait and the types
+//! it uses, `shared`'s protocol types included, whenever a debug build starts. Commands for a car
+//! name it with a [`Target`], state lives in tauri's managed state.
+
+use crusty_client::event_log;
+use crusty_client::session::{self, Recorder};
+use shared::config::{Calibration, Profile, Settings};
+use shared::event_log::LogEntry;
+use shared::{CarCommand, DriveMode, Melody, Telemetry};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::calibration::{self, Drift, Wheel};
+use crate::connection::{Connection, Target};
+use crate::drive::Direction;
+use crate::error::ApiError;
+use crate::fleet::{self, Broadcast, CarFailure, CarStatus, Fleet, FleetCar};
+use crate::gamepad::{Gamepad, GamepadSettings, GamepadStatus};
+use crate::keyboard::{DriveState, Keyboard};
+use crate::mission::{self, Step};
+use crate::script::{ScriptEvent, Scripts};
+
+#[taurpc::procedures(export_to = "../src/lib/bindings.ts", event_trigger = ApiEventTrigger)]
+pub trait Api {
+    async fn stop<R: Runtime>(app_handle: AppHandle<R>, car: Target) -> Result<(), ApiError>;
+
+    async fn horn<R: Runtime>(app_handle: AppHandle<R>, car: Target) -> Result<(), ApiError>;
+
+    async fn set_mode<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        mode: DriveMode,
+    ) -> Result<(), ApiError>;
+
+    async fn telemetry<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Telemetry, ApiError>;
+
+    // Send any command the car acknowledges
+    async fn send<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        command: CarCommand,
+    ) -> Result<(), ApiError>;
+
+    async fn settings<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Settings, ApiError>;
+
+    async fn set_settings<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        settings: Settings,
+    ) -> Result<(), ApiError>;
+
+    async fn calibration<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Calibration, ApiError>;
+
+    // Correct the car's motors with `calibration`, only trying it out unless `store` is set
+    async fn set_calibration<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        store: bool,
+    ) -> Result<(), ApiError>;
+
+    // Spin one wheel forward on its own for the guided calibration
+    async fn calibration_spin<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        wheel: Wheel,
+        duty: i8,
+    ) -> Result<(), ApiError>;
+
+    // Drive straight ahead with `calibration` to see which way the car pulls
+    async fn calibration_straight<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        speed: i8,
+    ) -> Result<(), ApiError>;
+
+    // `calibration` trimmed for the car pulling towards `drift`
+    async fn calibration_trim(calibration: Calibration, drift: Drift) -> Calibration;
+
+    // Store a new pre-shared key on the car, an empty key disables authentication
+    async fn set_key<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        new_key: String,
+    ) -> Result<(), ApiError>;
+
+    // Switch the driving profile the car holds every driver to
+    async fn set_profile<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        profile: Profile,
+    ) -> Result<(), ApiError>;
+
+    // Lock the driving profile with the admin passphrase, or unlock it with the same one
+    async fn lock_profile<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        admin: String,
+        lock: bool,
+    ) -> Result<(), ApiError>;
+
+    // Turn on the spot by `degrees` measured with the car's IMU, positive turns right
+    async fn turn_by<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        degrees: i16,
+    ) -> Result<(), ApiError>;
+
+    async fn run_mission<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        steps: Vec<Step>,
+    ) -> Result<(), ApiError>;
+
+    // Run a Rhai script driving the car, its output comes as `script` events
+    async fn run_script<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        source: String,
+    ) -> Result<(), ApiError>;
+
+    async fn stop_script<R: Runtime>(app_handle: AppHandle<R>);
+
+    // Record everything sent to and received from the car to the session file at `path`
+    async fn start_recording<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        path: String,
+    ) -> Result<(), ApiError>;
+
+    async fn stop_recording<R: Runtime>(app_handle: AppHandle<R>) -> Result<(), ApiError>;
+
+    // Drive the car with the commands of a recorded session, `speed` times as fast
+    async fn replay_session<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        path: String,
+        speed: f32,
+    ) -> Result<(), ApiError>;
+
+    // Write the telemetry of a recorded session to a CSV file
+    async fn export_telemetry(path: String, csv_path: String) -> Result<(), ApiError>;
+
+    // Every entry the car's event log still holds, oldest first
+    async fn event_log<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Vec<LogEntry>, ApiError>;
+
+    // Write event log entries to a CSV file
+    async fn export_event_log(entries: Vec<LogEntry>, csv_path: String) -> Result<(), ApiError>;
+
+    async fn fleet_cars<R: Runtime>(app_handle: AppHandle<R>) -> Vec<FleetCar>;
+
+    // Add a car to the fleet, or change the address or key of the car with the same name
+    async fn fleet_add<R: Runtime>(app_handle: AppHandle<R>, car: FleetCar)
+        -> Result<(), ApiError>;
+
+    async fn fleet_remove<R: Runtime>(
+        app_handle: AppHandle<R>,
+        name: String,
+    ) -> Result<(), ApiError>;
+
+    async fn fleet_status<R: Runtime>(app_handle: AppHandle<R>) -> Vec<CarStatus>;
+
+    // Send a command to every car of the fleet, returning the cars it failed on
+    async fn fleet_broadcast<R: Runtime>(
+        app_handle: AppHandle<R>,
+        command: Broadcast,
+    ) -> Vec<CarFailure>;
+
+    async fn gamepad_settings<R: Runtime>(app_handle: AppHandle<R>) -> GamepadSettings;
+
+    async fn set_gamepad_settings<R: Runtime>(app_handle: AppHandle<R>, settings: GamepadSettings);
+
+    // Stream the gamepad to `car`, or stop using the gamepad with `None`
+    async fn gamepad_drive<R: Runtime>(app_handle: AppHandle<R>, car: Option<Target>);
+
+    // Hold or release a direction key, driving the car while any is held
+    async fn drive_key<R: Runtime>(
+        app_handle: AppHandle<R>,
+        car: Target,
+        direction: Direction,
+        pressed: bool,
+        speed: u8,
+    );
+
+    // Let go of every direction key, when the window loses focus
+    async fn release_keys<R: Runtime>(app_handle: AppHandle<R>);
+
+    #[taurpc(event)]
+    async fn drive(update: DriveState);
+
+    #[taurpc(event)]
+    async fn gamepad(status: GamepadStatus);
+
+    #[taurpc(event)]
+    async fn script(event: ScriptEvent);
+}
+
+#[derive(Clone)]
+pub struct ApiImpl;
+
+#[taurpc::resolvers]
+impl Api for ApiImpl {
+    async fn stop<R: Runtime>(self, app_handle: AppHandle<R>, car: Target) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.stop().await?)
+    }
+
+    async fn horn<R: Runtime>(self, app_handle: AppHandle<R>, car: Target) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.play_melody(Melody::Horn).await?)
+    }
+
+    async fn set_mode<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        mode: DriveMode,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.set_mode(mode).await?)
+    }
+
+    async fn telemetry<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Telemetry, ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.telemetry().await?)
+    }
+
+    async fn send<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        command: CarCommand,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.send_command(command).await?)
+    }
+
+    async fn settings<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Settings, ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.settings().await?)
+    }
+
+    async fn set_settings<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        settings: Settings,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.set_settings(settings).await?)
+    }
+
+    async fn calibration<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Calibration, ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.calibration().await?)
+    }
+
+    async fn set_calibration<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        store: bool,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.set_calibration(calibration, store).await?)
+    }
+
+    async fn calibration_spin<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        wheel: Wheel,
+        duty: i8,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(calibration::spin(&car_client, calibration, wheel, duty).await?)
+    }
+
+    async fn calibration_straight<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        calibration: Calibration,
+        speed: i8,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(calibration::straight(&car_client, calibration, speed).await?)
+    }
+
+    async fn calibration_trim(self, calibration: Calibration, drift: Drift) -> Calibration {
+        calibration::trim(calibration, drift)
+    }
+
+    async fn set_key<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        new_key: String,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.set_key(&new_key).await?)
+    }
+
+    async fn set_profile<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        profile: Profile,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.set_profile(profile).await?)
+    }
+
+    async fn lock_profile<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        admin: String,
+        lock: bool,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.lock_profile(&admin, lock).await?)
+    }
+
+    async fn turn_by<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        degrees: i16,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.turn_by(degrees).await?)
+    }
+
+    async fn run_mission<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        steps: Vec<Step>,
+    ) -> Result<(), ApiError> {
+        let mission = mission::build(&steps)?;
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.run_mission(mission).await?)
+    }
+
+    async fn run_script<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        source: String,
+    ) -> Result<(), ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        let scripts = app_handle.state::<Scripts>();
+        scripts.start(app_handle.clone(), source, car_client)
+    }
+
+    async fn stop_script<R: Runtime>(self, app_handle: AppHandle<R>) {
+        app_handle.state::<Scripts>().stop();
+    }
+
+    async fn start_recording<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        path: String,
+    ) -> Result<(), ApiError> {
+        let recorder = Recorder::create(&path).map_err(ApiError::file)?;
+        let car_client = client(&app_handle, &car).await?;
+        car_client.start_recording(recorder);
+        Ok(())
+    }
+
+    async fn stop_recording<R: Runtime>(self, app_handle: AppHandle<R>) -> Result<(), ApiError> {
+        app_handle.state::<Connection>().stop_recording().await
+    }
+
+    async fn replay_session<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        path: String,
+        speed: f32,
+    ) -> Result<(), ApiError> {
+        let records = session::load(&path).map_err(ApiError::file)?;
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.replay(&records, speed).await?)
+    }
+
+    async fn export_telemetry(self, path: String, csv_path: String) -> Result<(), ApiError> {
+        let records = session::load(&path).map_err(ApiError::file)?;
+        let mut csv = Vec::new();
+        session::write_telemetry_csv(&records, &mut csv).map_err(ApiError::file)?;
+        std::fs::write(&csv_path, csv).map_err(ApiError::file)
+    }
+
+    async fn event_log<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+    ) -> Result<Vec<LogEntry>, ApiError> {
+        let car_client = client(&app_handle, &car).await?;
+        Ok(car_client.event_log().await?)
+    }
+
+    async fn export_event_log(
+        self,
+        entries: Vec<LogEntry>,
+        csv_path: String,
+    ) -> Result<(), ApiError> {
+        let mut csv = Vec::new();
+        event_log::write_csv(&entries, &mut csv).map_err(ApiError::file)?;
+        std::fs::write(&csv_path, csv).map_err(ApiError::file)
+    }
+
+    async fn fleet_cars<R: Runtime>(self, app_handle: AppHandle<R>) -> Vec<FleetCar> {
+        app_handle.state::<Fleet>().cars()
+    }
+
+    async fn fleet_add<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: FleetCar,
+    ) -> Result<(), ApiError> {
+        app_handle.state::<Fleet>().add(car)
+    }
+
+    async fn fleet_remove<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        name: String,
+    ) -> Result<(), ApiError> {
+        match app_handle.state::<Fleet>().remove(&name) {
+            Some(car) => app_handle.state::<Connection>().close(&car.ip).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn fleet_status<R: Runtime>(self, app_handle: AppHandle<R>) -> Vec<CarStatus> {
+        let cars = app_handle.state::<Fleet>().cars();
+        fleet::status(&app_handle.state::<Connection>(), &cars).await
+    }
+
+    async fn fleet_broadcast<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        command: Broadcast,
+    ) -> Vec<CarFailure> {
+        let cars = app_handle.state::<Fleet>().cars();
+        fleet::broadcast(&app_handle.state::<Connection>(), &cars, command).await
+    }
+
+    async fn gamepad_settings<R: Runtime>(self, app_handle: AppHandle<R>) -> GamepadSettings {
+        app_handle.state::<Gamepad>().settings()
+    }
+
+    async fn set_gamepad_settings<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        settings: GamepadSettings,
+    ) {
+        app_handle.state::<Gamepad>().set_settings(settings);
+    }
+
+    async fn gamepad_drive<R: Runtime>(self, app_handle: AppHandle<R>, car: Option<Target>) {
+        app_handle.state::<Gamepad>().set_target(car);
+    }
+
+    async fn drive_key<R: Runtime>(
+        self,
+        app_handle: AppHandle<R>,
+        car: Target,
+        direction: Direction,
+        pressed: bool,
+        speed: u8,
+    ) {
+        app_handle
+            .state::<Keyboard>()
+            .press(direction, pressed, speed, car);
+    }
+
+    async fn release_keys<R: Runtime>(self, app_handle: AppHandle<R>) {
+        app_handle.state::<Keyboard>().release_all();
+    }
+}
+
+/// A client connected to `car` on the app's connections
+async fn client<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    car: &Target,
+) -> Result<crusty_client::CarClient, ApiError> {
+    app_handle.state::<Connection>().client_for(car).await
+}
@@ -0,0 +1,180 @@
+//! The guided motor calibration: each wheel is spun on its own to check which way it turns and
+//! find the duty it starts moving at, then the car drives straight and the faster side is
+//! trimmed down
+//!
+//! Every step only tries the calibration being worked on out on the car, the frontend stores it
+//! once the routine is done.
+
+use std::time::Duration;
+
+use crusty_client::CarClient;
+use serde::{Deserialize, Serialize};
+use shared::config::{Calibration, WheelCalibration};
+use tokio::time;
+
+/// How long a wheel is spun for
+const SPIN_TIME: Duration = Duration::from_millis(1000);
+
+/// How long the car drives to show its drift
+const STRAIGHT_TIME: Duration = Duration::from_millis(2000);
+
+/// How often driving is repeated, so a deadman doesn't stop the car early
+const REFRESH: Duration = Duration::from_millis(200);
+
+/// Gain taken off the faster side, or given back to the slower one, for each drift reported
+const TRIM_STEP: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Wheel {
+    FrontLeft,
+    FrontRight,
+    RearLeft,
+    RearRight,
+}
+
+/// Which way the car pulled while driving straight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Drift {
+    Left,
+    Right,
+}
+
+fn wheel_mut(calibration: &mut Calibration, wheel: Wheel) -> &mut WheelCalibration {
+    match wheel {
+        Wheel::FrontLeft => &mut calibration.front_left,
+        Wheel::FrontRight => &mut calibration.front_right,
+        Wheel::RearLeft => &mut calibration.rear_left,
+        Wheel::RearRight => &mut calibration.rear_right,
+    }
+}
+
+/// `calibration` with every other wheel held still and `wheel` driven at the raw duty, only
+/// corrected for its direction
+pub fn only(mut calibration: Calibration, wheel: Wheel) -> Calibration {
+    let still = WheelCalibration {
+        gain: 0,
+        ..WheelCalibration::new()
+    };
+    let mut only = Calibration {
+        front_left: still,
+        front_right: still,
+        rear_left: still,
+        rear_right: still,
+    };
+    let original = *wheel_mut(&mut calibration, wheel);
+    *wheel_mut(&mut only, wheel) = WheelCalibration {
+        invert: original.invert,
+        ..WheelCalibration::new()
+    };
+    only
+}
+
+/// `calibration` corrected for the car pulling towards `drift`
+///
+/// The side that is too fast has its gain lowered, unless the other side was trimmed before,
+/// then that side gets its gain back first.
+pub fn trim(mut calibration: Calibration, drift: Drift) -> Calibration {
+    let (slow, fast) = match drift {
+        Drift::Left => (
+            [Wheel::FrontLeft, Wheel::RearLeft],
+            [Wheel::FrontRight, Wheel::RearRight],
+        ),
+        Drift::Right => (
+            [Wheel::FrontRight, Wheel::RearRight],
+            [Wheel::FrontLeft, Wheel::RearLeft],
+        ),
+    };
+    let trimmed = slow
+        .iter()
+        .any(|&wheel| wheel_mut(&mut calibration, wheel).gain < 100);
+    for wheel in if trimmed { slow } else { fast } {
+        let wheel = wheel_mut(&mut calibration, wheel);
+        wheel.gain = if trimmed {
+            wheel.gain.saturating_add(TRIM_STEP).min(100)
+        } else {
+            wheel.gain.saturating_sub(TRIM_STEP)
+        };
+    }
+    calibration
+}
+
+/// Spin `wheel` forward at `duty` percent for a second, the others held still, then go back to
+/// trying `calibration`
+pub async fn spin(
+    client: &CarClient,
+    calibration: Calibration,
+    wheel: Wheel,
+    duty: i8,
+) -> crusty_client::Result<()> {
+    client
+        .set_calibration(only(calibration, wheel), false)
+        .await?;
+    let spun = drive_for(client, duty, SPIN_TIME).await;
+    // Put the calibration back even when spinning failed, the car may still be listening
+    client.set_calibration(calibration, false).await?;
+    spun
+}
+
+/// Drive straight ahead at `speed` percent for two seconds with `calibration`
+pub async fn straight(
+    client: &CarClient,
+    calibration: Calibration,
+    speed: i8,
+) -> crusty_client::Result<()> {
+    client.set_calibration(calibration, false).await?;
+    drive_for(client, speed, STRAIGHT_TIME).await
+}
+
+async fn drive_for(
+    client: &CarClient,
+    throttle: i8,
+    duration: Duration,
+) -> crusty_client::Result<()> {
+    let start = time::Instant::now();
+    while start.elapsed() < duration {
+        if let Err(e) = client.drive(throttle, 0).await {
+            let _ = client.stop().await;
+            return Err(e);
+        }
+        time::sleep(REFRESH).await;
+    }
+    client.stop().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_spun_wheel_moves_at_the_raw_duty() {
+        let mut calibration = Calibration::new();
+        calibration.rear_left = WheelCalibration {
+            invert: true,
+            gain: 80,
+            min_duty: 30,
+            deadband: 10,
+        };
+
+        let spun = only(calibration, Wheel::RearLeft);
+        assert_eq!(spun.rear_left.apply(20), -20);
+        assert_eq!(spun.front_left.apply(20), 0);
+        assert_eq!(spun.front_right.apply(100), 0);
+        assert_eq!(spun.rear_right.apply(-100), 0);
+    }
+
+    #[test]
+    fn drift_trims_the_faster_side_then_gives_gain_back() {
+        let pulled_left = trim(Calibration::new(), Drift::Left);
+        assert_eq!(pulled_left.front_right.gain, 95);
+        assert_eq!(pulled_left.rear_right.gain, 95);
+        assert_eq!(pulled_left.front_left.gain, 100);
+
+        // Overshooting the other way undoes the trim rather than slowing the left side
+        let overshot = trim(pulled_left, Drift::Right);
+        assert_eq!(overshot, Calibration::new());
+        let pulled_right = trim(overshot, Drift::Right);
+        assert_eq!(pulled_right.front_left.gain, 95);
+    }
+}
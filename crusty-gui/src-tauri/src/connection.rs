@@ -0,0 +1,109 @@
+//! The connections to the cars, kept open between commands
+//!
+//! Commands name the car they are for, each car's connection is reused while its key stays the
+//! same and made again when the key changes or the car hung up. A session being recorded carries
+//! on over the new connection.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crusty_client::CarClient;
+use serde::{Deserialize, Serialize};
+use shared::COMMAND_PORT;
+use tokio::sync::Mutex;
+
+use crate::error::ApiError;
+
+/// The car at `ip`, unlocked with `key`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct Target {
+    pub ip: String,
+    pub key: Option<String>,
+}
+
+struct Open {
+    key: Option<String>,
+    client: CarClient,
+}
+
+/// The connection to one car, locked while connecting so other cars don't wait for it
+type Slot = Arc<Mutex<Option<Open>>>;
+
+/// The open connections by the address of their car
+#[derive(Default)]
+pub struct Connection {
+    cars: std::sync::Mutex<HashMap<String, Slot>>,
+}
+
+impl Connection {
+    /// A client connected to the car at `ip`, connecting if needed
+    pub async fn client(&self, ip: &str, key: Option<&str>) -> Result<CarClient, ApiError> {
+        let slot = self.slot(ip);
+        let mut current = slot.lock().await;
+        if let Some(open) = current.as_ref() {
+            if open.key.as_deref() == key && !open.client.is_closed() {
+                return Ok(open.client.clone());
+            }
+        }
+
+        let client = CarClient::connect(ip, COMMAND_PORT, key).await?;
+        if let Some(recorder) = current
+            .as_ref()
+            .and_then(|open| open.client.stop_recording())
+        {
+            client.start_recording(recorder);
+        }
+        *current = Some(Open {
+            key: key.map(str::to_string),
+            client: client.clone(),
+        });
+        Ok(client)
+    }
+
+    /// Whether the connection to the car at `ip` is open
+    pub async fn is_open(&self, ip: &str) -> bool {
+        let slot = self.slot(ip);
+        let current = slot.lock().await;
+        current
+            .as_ref()
+            .is_some_and(|open| !open.client.is_closed())
+    }
+
+    /// Forget the connection to the car at `ip`, it closes once nothing else is using it
+    pub async fn close(&self, ip: &str) -> Result<(), ApiError> {
+        let Some(slot) = self.cars.lock().unwrap().remove(ip) else {
+            return Ok(());
+        };
+        let closed = slot.lock().await.take();
+        match closed.and_then(|open| open.client.stop_recording()) {
+            Some(recorder) => recorder.finish().map_err(ApiError::file),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop recording the sessions and write out the rest of them
+    pub async fn stop_recording(&self) -> Result<(), ApiError> {
+        let slots: Vec<Slot> = self.cars.lock().unwrap().values().cloned().collect();
+        for slot in slots {
+            let recorder = slot
+                .lock()
+                .await
+                .as_ref()
+                .and_then(|open| open.client.stop_recording());
+            if let Some(recorder) = recorder {
+                recorder.finish().map_err(ApiError::file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A client connected to `target`, connecting if needed
+    pub async fn client_for(&self, target: &Target) -> Result<CarClient, ApiError> {
+        self.client(&target.ip, target.key.as_deref()).await
+    }
+
+    fn slot(&self, ip: &str) -> Slot {
+        let mut cars = self.cars.lock().unwrap();
+        cars.entry(ip.to_string()).or_default().clone()
+    }
+}
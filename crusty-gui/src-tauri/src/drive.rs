@@ -0,0 +1,257 @@
+//! Turns controller and keyboard input into the throttle, strafe and steering streamed to the car
+
+use crusty_client::CarClient;
+use serde::{Deserialize, Serialize};
+
+/// Top speed of each gear, in percent
+pub const GEARS: [u8; 3] = [40, 70, 100];
+
+/// Analog controls of a gamepad, sticks from -1 to 1 with up and right positive, triggers from 0 to 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PadInput {
+    pub left_x: f32,
+    pub left_y: f32,
+    pub right_x: f32,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+/// How stick travel maps to speed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct Shaping {
+    /// Travel around the centre that is ignored, from 0 to 1
+    pub deadzone: f32,
+    /// 0 is linear, 1 is cubic for finer control around the centre
+    pub expo: f32,
+}
+
+impl Default for Shaping {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.1,
+            expo: 0.3,
+        }
+    }
+}
+
+/// Apply the deadzone and expo curve to one axis
+pub fn shape(value: f32, shaping: Shaping) -> f32 {
+    let deadzone = shaping.deadzone.clamp(0.0, 0.95);
+    let magnitude = value.abs().min(1.0);
+    if magnitude <= deadzone {
+        return 0.0;
+    }
+
+    // Rescale so the output still starts at zero right outside the deadzone
+    let x = (magnitude - deadzone) / (1.0 - deadzone);
+    let expo = shaping.expo.clamp(0.0, 1.0);
+    value.signum() * ((1.0 - expo) * x + expo * x * x * x)
+}
+
+/// Throttle, strafe and steering in percent for the pad's input in `gear`
+///
+/// The left stick drives and steers, the right stick steers too and the triggers add forward
+/// and backward throttle, like racing games. With `strafe` the left stick moves the car sideways
+/// instead and only the right stick steers, for cars with mecanum wheels.
+pub fn pad_drive(input: &PadInput, shaping: Shaping, strafe: bool, gear: usize) -> (i8, i8, i8) {
+    let throttle = shape(input.left_y, shaping) + shape(input.right_trigger, shaping)
+        - shape(input.left_trigger, shaping);
+    let (sideways, steer) = if strafe {
+        (shape(input.left_x, shaping), shape(input.right_x, shaping))
+    } else {
+        let steer = [input.left_x, input.right_x]
+            .map(|axis| shape(axis, shaping))
+            .into_iter()
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0);
+        (0.0, steer)
+    };
+
+    let top = GEARS[gear.min(GEARS.len() - 1)] as f32;
+    let percent = |value: f32| (value.clamp(-1.0, 1.0) * top).round() as i8;
+    (percent(throttle), percent(sideways), percent(steer))
+}
+
+/// A direction key of the keyboard or the on-screen pad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    StrafeLeft,
+    StrafeRight,
+}
+
+/// The direction keys held down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, specta::Type)]
+pub struct Held {
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    pub strafe_left: bool,
+    pub strafe_right: bool,
+}
+
+impl Held {
+    pub fn set(&mut self, direction: Direction, pressed: bool) {
+        let key = match direction {
+            Direction::Forward => &mut self.forward,
+            Direction::Backward => &mut self.backward,
+            Direction::Left => &mut self.left,
+            Direction::Right => &mut self.right,
+            Direction::StrafeLeft => &mut self.strafe_left,
+            Direction::StrafeRight => &mut self.strafe_right,
+        };
+        *key = pressed;
+    }
+}
+
+/// Throttle, strafe and steering in percent for the keys held at `speed`
+///
+/// Opposite keys cancel out. Turning while driving arcs with half the speed on the steering,
+/// turning alone spins on the spot. Strafing while driving moves diagonally.
+pub fn key_drive(held: Held, speed: u8) -> (i8, i8, i8) {
+    let speed = speed.min(100) as i8;
+    let axis = |positive: bool, negative: bool| positive as i8 - negative as i8;
+    let throttle = axis(held.forward, held.backward) * speed;
+    let strafe = axis(held.strafe_right, held.strafe_left) * speed;
+    let turn = axis(held.right, held.left);
+    let steer = if throttle == 0 && strafe == 0 {
+        turn * speed
+    } else {
+        turn * (speed / 2)
+    };
+    (throttle, strafe, steer)
+}
+
+/// Send throttle, strafe and steering to the car
+///
+/// Only strafing needs the holonomic command, everything else goes as a drive, which can take the
+/// UDP channel.
+pub async fn send(
+    car: &CarClient,
+    (throttle, strafe, steer): (i8, i8, i8),
+) -> crusty_client::Result<()> {
+    if strafe == 0 {
+        car.drive(throttle, steer).await
+    } else {
+        car.holonomic(throttle, strafe, steer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINEAR: Shaping = Shaping {
+        deadzone: 0.0,
+        expo: 0.0,
+    };
+
+    #[test]
+    fn deadzone_is_ignored_and_rescaled() {
+        let shaping = Shaping {
+            deadzone: 0.2,
+            expo: 0.0,
+        };
+        assert_eq!(shape(0.15, shaping), 0.0);
+        assert_eq!(shape(-0.2, shaping), 0.0);
+        assert!((shape(0.6, shaping) - 0.5).abs() < 1e-6);
+        assert_eq!(shape(-1.0, shaping), -1.0);
+    }
+
+    #[test]
+    fn expo_softens_the_centre_but_keeps_full_travel() {
+        let shaping = Shaping {
+            deadzone: 0.0,
+            expo: 1.0,
+        };
+        assert!((shape(0.5, shaping) - 0.125).abs() < 1e-6);
+        assert_eq!(shape(1.0, shaping), 1.0);
+        assert_eq!(shape(-1.0, shaping), -1.0);
+    }
+
+    #[test]
+    fn gears_limit_the_speed() {
+        let input = PadInput {
+            left_y: 1.0,
+            left_x: -0.5,
+            ..PadInput::default()
+        };
+        assert_eq!(pad_drive(&input, LINEAR, false, 0), (40, 0, -20));
+        assert_eq!(pad_drive(&input, LINEAR, false, 2), (100, 0, -50));
+        assert_eq!(pad_drive(&input, LINEAR, false, 9), (100, 0, -50));
+    }
+
+    #[test]
+    fn triggers_add_throttle_and_the_larger_stick_steers() {
+        let input = PadInput {
+            right_trigger: 0.5,
+            left_trigger: 0.25,
+            left_x: 0.2,
+            right_x: -0.6,
+            ..PadInput::default()
+        };
+        assert_eq!(pad_drive(&input, LINEAR, false, 2), (25, 0, -60));
+    }
+
+    #[test]
+    fn strafing_takes_the_left_stick_sideways() {
+        let input = PadInput {
+            left_y: 0.5,
+            left_x: -1.0,
+            right_x: 0.25,
+            ..PadInput::default()
+        };
+        assert_eq!(pad_drive(&input, LINEAR, true, 2), (50, -100, 25));
+    }
+
+    #[test]
+    fn held_keys_drive_arcs_and_spins() {
+        let mut held = Held::default();
+        assert_eq!(key_drive(held, 60), (0, 0, 0));
+
+        held.set(Direction::Forward, true);
+        assert_eq!(key_drive(held, 60), (60, 0, 0));
+        held.set(Direction::Left, true);
+        assert_eq!(key_drive(held, 60), (60, 0, -30));
+        held.set(Direction::Forward, false);
+        assert_eq!(key_drive(held, 60), (0, 0, -60));
+        held.set(Direction::Backward, true);
+        held.set(Direction::Left, false);
+        held.set(Direction::Right, true);
+        assert_eq!(key_drive(held, 60), (-60, 0, 30));
+    }
+
+    #[test]
+    fn strafe_keys_move_sideways_and_diagonally() {
+        let mut held = Held::default();
+        held.set(Direction::StrafeLeft, true);
+        assert_eq!(key_drive(held, 60), (0, -60, 0));
+        held.set(Direction::Forward, true);
+        assert_eq!(key_drive(held, 60), (60, -60, 0));
+        held.set(Direction::Right, true);
+        assert_eq!(key_drive(held, 60), (60, -60, 30));
+    }
+
+    #[test]
+    fn opposite_keys_cancel_and_speed_is_capped() {
+        let held = Held {
+            forward: true,
+            backward: true,
+            left: true,
+            right: true,
+            strafe_left: true,
+            strafe_right: true,
+        };
+        assert_eq!(key_drive(held, 100), (0, 0, 0));
+        let held = Held {
+            backward: false,
+            ..held
+        };
+        assert_eq!(key_drive(held, 200), (100, 0, 0));
+    }
+}
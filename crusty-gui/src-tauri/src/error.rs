@@ -0,0 +1,58 @@
+//! Why a command failed, typed for the frontend
+
+use std::fmt;
+
+use serde::Serialize;
+use shared::ErrorCode;
+
+#[derive(Debug, Clone, PartialEq, Serialize, specta::Type)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum ApiError {
+    /// The car didn't connect or answer in time
+    Timeout,
+    /// The connection couldn't be made or broke down
+    Connection(String),
+    /// The car asked for a key and none was given
+    KeyRequired,
+    /// The car refused the command
+    Rejected(ErrorCode),
+    /// The car answered with something that doesn't make sense
+    Protocol(String),
+    /// The request can't be carried out as given
+    Invalid(String),
+    /// A session or export file couldn't be read or written
+    File(String),
+}
+
+impl ApiError {
+    pub fn file(err: impl fmt::Display) -> Self {
+        ApiError::File(err.to_string())
+    }
+}
+
+impl From<crusty_client::Error> for ApiError {
+    fn from(err: crusty_client::Error) -> Self {
+        use crusty_client::Error;
+        match err {
+            Error::Timeout => ApiError::Timeout,
+            Error::Io(_) | Error::Closed => ApiError::Connection(err.to_string()),
+            Error::KeyRequired => ApiError::KeyRequired,
+            Error::Rejected(code) => ApiError::Rejected(code),
+            Error::Frame(_) | Error::Unexpected(_) => ApiError::Protocol(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Timeout => write!(f, "Timed out waiting for the car"),
+            ApiError::Connection(err) | ApiError::Protocol(err) | ApiError::Invalid(err) => {
+                write!(f, "{err}")
+            }
+            ApiError::KeyRequired => write!(f, "The car requires a key, please enter it"),
+            ApiError::Rejected(code) => write!(f, "Car rejected command: {code:?}"),
+            ApiError::File(err) => write!(f, "File error: {err}"),
+        }
+    }
+}
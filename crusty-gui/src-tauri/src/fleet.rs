@@ -0,0 +1,178 @@
+//! The fleet: named cars the GUI keeps connections to
+//!
+//! The frontend picks which car of the fleet the controls, keyboard and gamepad drive by
+//! addressing their commands to it. The fleet panel shows every car's status and telemetry and
+//! can send a command to all of them at once, like stopping them all.
+
+use std::sync::Mutex;
+
+use futures_util::future;
+use serde::{Deserialize, Serialize};
+use shared::{DriveMode, LightTarget, Melody, Telemetry};
+
+use crate::connection::Connection;
+use crate::error::ApiError;
+
+/// A car of the fleet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct FleetCar {
+    pub name: String,
+    pub ip: String,
+    pub key: Option<String>,
+}
+
+/// How a car of the fleet is doing
+#[derive(Debug, Clone, Serialize, specta::Type)]
+pub struct CarStatus {
+    name: String,
+    ip: String,
+    connected: bool,
+    telemetry: Option<Telemetry>,
+    error: Option<ApiError>,
+}
+
+/// A car a broadcast command failed on
+#[derive(Debug, Clone, Serialize, specta::Type)]
+pub struct CarFailure {
+    name: String,
+    error: ApiError,
+}
+
+/// A command sent to every car of the fleet
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum Broadcast {
+    Stop,
+    Horn,
+    Manual,
+    FollowLight,
+    AvoidLight,
+}
+
+#[derive(Default)]
+pub struct Fleet {
+    cars: Mutex<Vec<FleetCar>>,
+}
+
+impl Fleet {
+    /// Add `car`, replacing the car with the same name
+    pub fn add(&self, car: FleetCar) -> Result<(), ApiError> {
+        if car.name.trim().is_empty() {
+            return Err(ApiError::Invalid("A car needs a name".to_string()));
+        }
+        let mut cars = self.cars.lock().unwrap();
+        if cars
+            .iter()
+            .any(|other| other.ip == car.ip && other.name != car.name)
+        {
+            return Err(ApiError::Invalid(format!(
+                "{} is already in the fleet",
+                car.ip
+            )));
+        }
+        match cars.iter_mut().find(|other| other.name == car.name) {
+            Some(other) => *other = car,
+            None => cars.push(car),
+        }
+        Ok(())
+    }
+
+    /// Take the car called `name` out of the fleet
+    pub fn remove(&self, name: &str) -> Option<FleetCar> {
+        let mut cars = self.cars.lock().unwrap();
+        let index = cars.iter().position(|car| car.name == name)?;
+        Some(cars.remove(index))
+    }
+
+    pub fn cars(&self) -> Vec<FleetCar> {
+        self.cars.lock().unwrap().clone()
+    }
+}
+
+/// The status and telemetry of every car, asking them all at once
+pub async fn status(connection: &Connection, cars: &[FleetCar]) -> Vec<CarStatus> {
+    future::join_all(cars.iter().map(|car| async move {
+        let telemetry = async {
+            let client = connection.client(&car.ip, car.key.as_deref()).await?;
+            Ok::<_, ApiError>(client.telemetry().await?)
+        }
+        .await;
+        CarStatus {
+            name: car.name.clone(),
+            ip: car.ip.clone(),
+            connected: connection.is_open(&car.ip).await,
+            error: telemetry.as_ref().err().cloned(),
+            telemetry: telemetry.ok(),
+        }
+    }))
+    .await
+}
+
+/// Send `command` to every car at once, returning the cars it failed on
+pub async fn broadcast(
+    connection: &Connection,
+    cars: &[FleetCar],
+    command: Broadcast,
+) -> Vec<CarFailure> {
+    let results = future::join_all(cars.iter().map(|car| async move {
+        let client = connection.client(&car.ip, car.key.as_deref()).await?;
+        let result = match command {
+            Broadcast::Stop => client.stop().await,
+            Broadcast::Horn => client.play_melody(Melody::Horn).await,
+            Broadcast::Manual => client.set_mode(DriveMode::Manual).await,
+            Broadcast::FollowLight => {
+                client
+                    .set_mode(DriveMode::LightFollow(LightTarget::Brighter))
+                    .await
+            }
+            Broadcast::AvoidLight => {
+                client
+                    .set_mode(DriveMode::LightFollow(LightTarget::Darker))
+                    .await
+            }
+        };
+        Ok::<_, ApiError>(result?)
+    }))
+    .await;
+
+    cars.iter()
+        .zip(results)
+        .filter_map(|(car, result)| {
+            let error = result.err()?;
+            Some(CarFailure {
+                name: car.name.clone(),
+                error,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn car(name: &str, ip: &str) -> FleetCar {
+        FleetCar {
+            name: name.to_string(),
+            ip: ip.to_string(),
+            key: None,
+        }
+    }
+
+    #[test]
+    fn cars_are_named_once_and_addressed_once() {
+        let fleet = Fleet::default();
+        fleet.add(car("red", "192.168.0.2")).unwrap();
+        fleet.add(car("blue", "192.168.0.3")).unwrap();
+        assert!(fleet.add(car("", "192.168.0.4")).is_err());
+        assert!(fleet.add(car("green", "192.168.0.3")).is_err());
+
+        fleet.add(car("red", "192.168.0.5")).unwrap();
+        assert_eq!(
+            fleet.cars(),
+            [car("red", "192.168.0.5"), car("blue", "192.168.0.3")]
+        );
+        assert_eq!(fleet.remove("blue"), Some(car("blue", "192.168.0.3")));
+        assert_eq!(fleet.remove("blue"), None);
+    }
+}
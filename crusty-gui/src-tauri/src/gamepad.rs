@@ -0,0 +1,287 @@
+//! Gamepad driving: the sticks and triggers stream throttle, strafe and steering to the car
+//!
+//! A thread reads the pads with gilrs and keeps the input of the last pad used. A task sends
+//! that input to the car at a fixed rate while gamepad driving is on, which also keeps a
+//! configured deadman happy, and stops the car once the controls are back at rest. Buttons act
+//! once per press through their bindings.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use gilrs::{Axis, Button, EventType, Gamepad as Pad, Gilrs};
+use serde::{Deserialize, Serialize};
+use shared::{DriveMode, LightTarget, Melody};
+use tauri::{AppHandle, Manager};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Instant};
+
+use crate::api::ApiEventTrigger;
+use crate::connection::{Connection, Target};
+use crate::drive::{self, PadInput, Shaping, GEARS};
+use crate::error::ApiError;
+
+/// What a button does when pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum PadAction {
+    Stop,
+    Horn,
+    Manual,
+    FollowLight,
+    AvoidLight,
+    GearUp,
+    GearDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+pub struct Binding {
+    /// The gilrs name of the button, like `South`
+    #[specta(type = String)]
+    pub button: Button,
+    pub action: PadAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, specta::Type)]
+pub struct GamepadSettings {
+    pub shaping: Shaping,
+    /// How often the input is sent to the car
+    pub rate_hz: u16,
+    /// The left stick moves the car sideways instead of steering, for mecanum wheels
+    pub strafe: bool,
+    pub bindings: Vec<Binding>,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        let bind = |button, action| Binding { button, action };
+        Self {
+            shaping: Shaping::default(),
+            rate_hz: 20,
+            strafe: false,
+            bindings: vec![
+                bind(Button::South, PadAction::Stop),
+                bind(Button::East, PadAction::Horn),
+                bind(Button::Select, PadAction::Manual),
+                bind(Button::West, PadAction::FollowLight),
+                bind(Button::North, PadAction::AvoidLight),
+                bind(Button::RightTrigger, PadAction::GearUp),
+                bind(Button::LeftTrigger, PadAction::GearDown),
+            ],
+        }
+    }
+}
+
+/// Where the gamepad drives and how
+#[derive(Default)]
+pub struct Gamepad {
+    settings: Mutex<GamepadSettings>,
+    target: Mutex<Option<Target>>,
+}
+
+impl Gamepad {
+    pub fn settings(&self) -> GamepadSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn set_settings(&self, settings: GamepadSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// Drive `target` with the gamepad, or stop using the gamepad with `None`
+    pub fn set_target(&self, target: Option<Target>) {
+        *self.target.lock().unwrap() = target;
+    }
+
+    fn target(&self) -> Option<Target> {
+        self.target.lock().unwrap().clone()
+    }
+}
+
+/// What the frontend shows about the gamepad, sent as the `gamepad` event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, specta::Type)]
+pub struct GamepadStatus {
+    name: Option<String>,
+    driving: bool,
+    throttle: i8,
+    strafe: i8,
+    steer: i8,
+    gear: u8, // 1 is the slowest
+    error: Option<String>,
+}
+
+/// The pad in use and its analog controls
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PadState {
+    name: Option<String>,
+    input: PadInput,
+}
+
+/// Start reading gamepads and streaming their input to the car
+pub fn start(app: AppHandle) {
+    let (pad, pad_updates) = watch::channel(PadState::default());
+    let (presses, pressed) = mpsc::unbounded_channel();
+    std::thread::spawn(move || read_pads(pad, presses));
+    tauri::async_runtime::spawn(stream(app, pad_updates, pressed));
+}
+
+// gilrs blocks waiting for events, so it gets a thread of its own
+fn read_pads(pad: watch::Sender<PadState>, presses: mpsc::UnboundedSender<Button>) {
+    let mut gilrs = match Gilrs::new() {
+        Ok(gilrs) => gilrs,
+        Err(err) => {
+            eprintln!("Gamepads unavailable: {}", err);
+            return;
+        }
+    };
+
+    let mut active = gilrs.gamepads().next().map(|(id, _)| id);
+    loop {
+        let Some(event) = gilrs.next_event_blocking(None) else {
+            continue;
+        };
+        match event.event {
+            EventType::Disconnected if active == Some(event.id) => {
+                active = gilrs
+                    .gamepads()
+                    .map(|(id, _)| id)
+                    .find(|&id| id != event.id);
+            }
+            EventType::Disconnected | EventType::Dropped => {}
+            event_type => {
+                // The pad touched last is the one driving
+                active = Some(event.id);
+                if let EventType::ButtonPressed(button, _) = event_type {
+                    if presses.send(button).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let state = active
+            .map(|id| {
+                let gamepad = gilrs.gamepad(id);
+                PadState {
+                    name: Some(gamepad.name().to_string()),
+                    input: PadInput {
+                        left_x: gamepad.value(Axis::LeftStickX),
+                        left_y: gamepad.value(Axis::LeftStickY),
+                        right_x: gamepad.value(Axis::RightStickX),
+                        left_trigger: trigger(&gamepad, Button::LeftTrigger2),
+                        right_trigger: trigger(&gamepad, Button::RightTrigger2),
+                    },
+                }
+            })
+            .unwrap_or_default();
+        pad.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+}
+
+fn trigger(gamepad: &Pad, button: Button) -> f32 {
+    gamepad.button_data(button).map_or(0.0, |data| data.value())
+}
+
+async fn stream(
+    app: AppHandle,
+    pad: watch::Receiver<PadState>,
+    mut pressed: mpsc::UnboundedReceiver<Button>,
+) {
+    let gamepad = app.state::<Gamepad>();
+    let connection = app.state::<Connection>();
+    let events = ApiEventTrigger::new(app.clone());
+    let mut shown = GamepadStatus::default();
+    let mut gear = 0;
+    let mut controls = (0, 0, 0);
+    // The car being driven, to stop it when the controls come to rest or the gamepad is switched off
+    let mut driving: Option<Target> = None;
+    let mut next = Instant::now();
+
+    loop {
+        let settings = gamepad.settings();
+        let result = tokio::select! {
+            Some(button) = pressed.recv() => {
+                let binding = settings.bindings.iter().find(|binding| binding.button == button);
+                match (binding.map(|binding| binding.action), gamepad.target()) {
+                    (Some(PadAction::GearUp), _) => {
+                        gear = (gear + 1).min(GEARS.len() - 1);
+                        Ok(())
+                    }
+                    (Some(PadAction::GearDown), _) => {
+                        gear = gear.saturating_sub(1);
+                        Ok(())
+                    }
+                    (Some(action), Some(target)) => act(&connection, &target, action).await,
+                    _ => Ok(()),
+                }
+            }
+            _ = time::sleep_until(next) => {
+                next += Duration::from_secs(1) / settings.rate_hz.max(1) as u32;
+                next = next.max(Instant::now());
+
+                let target = gamepad.target();
+                let state = pad.borrow().clone();
+                controls = match (&target, &state.name) {
+                    (Some(_), Some(_)) => {
+                        drive::pad_drive(&state.input, settings.shaping, settings.strafe, gear)
+                    }
+                    _ => (0, 0, 0),
+                };
+
+                match target {
+                    Some(target) if controls != (0, 0, 0) => {
+                        let result = async {
+                            let car = connection.client_for(&target).await?;
+                            Ok::<_, ApiError>(drive::send(&car, controls).await?)
+                        }
+                        .await;
+                        driving = Some(target);
+                        result
+                    }
+                    _ => match driving.take() {
+                        Some(target) => act(&connection, &target, PadAction::Stop).await,
+                        None => Ok(()),
+                    },
+                }
+            }
+        };
+
+        let (throttle, strafe, steer) = controls;
+        let status = GamepadStatus {
+            name: pad.borrow().name.clone(),
+            driving: driving.is_some(),
+            throttle,
+            strafe,
+            steer,
+            gear: gear as u8 + 1,
+            error: result.err().map(|err| err.to_string()),
+        };
+        if status != shown {
+            let _ = events.gamepad(status.clone());
+            shown = status;
+        }
+    }
+}
+
+/// Carry out a button's action on the car
+async fn act(connection: &Connection, target: &Target, action: PadAction) -> Result<(), ApiError> {
+    let car = connection.client_for(target).await?;
+    let result = match action {
+        PadAction::Stop => car.stop().await,
+        PadAction::Horn => car.play_melody(Melody::Horn).await,
+        PadAction::Manual => car.set_mode(DriveMode::Manual).await,
+        PadAction::FollowLight => {
+            car.set_mode(DriveMode::LightFollow(LightTarget::Brighter))
+                .await
+        }
+        PadAction::AvoidLight => {
+            car.set_mode(DriveMode::LightFollow(LightTarget::Darker))
+                .await
+        }
+        PadAction::GearUp | PadAction::GearDown => Ok(()),
+    };
+    Ok(result?)
+}
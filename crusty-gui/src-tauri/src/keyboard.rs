@@ -0,0 +1,148 @@
+//! Keyboard driving: the direction and strafe keys drive the car while they are held
+//!
+//! The frontend reports every direction key going down and up, and lets go of all of them when
+//! the window loses focus. A task sends the drive for the keys held at a fixed rate, like a
+//! transmitter would, and stops the car as soon as the last one is released. Key repeat doesn't
+//! change what is held, so it doesn't send anything either.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+use crate::api::ApiEventTrigger;
+use crate::connection::{Connection, Target};
+use crate::drive::{self, Direction, Held};
+use crate::error::ApiError;
+
+/// How often the drive is sent while keys are held
+const PERIOD: Duration = Duration::from_millis(50);
+
+/// The direction keys held and the car they drive
+pub struct Keyboard {
+    keys: watch::Sender<Keys>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Keys {
+    held: Held,
+    speed: u8,
+    target: Option<Target>,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self {
+            keys: watch::Sender::new(Keys::default()),
+        }
+    }
+}
+
+impl Keyboard {
+    /// Hold or release `direction` to drive the car at `target` with `speed`
+    pub fn press(&self, direction: Direction, pressed: bool, speed: u8, target: Target) {
+        self.keys.send_if_modified(|keys| {
+            let mut next = keys.clone();
+            next.held.set(direction, pressed);
+            next.speed = speed;
+            next.target = Some(target);
+            let changed = *keys != next;
+            *keys = next;
+            changed
+        });
+    }
+
+    /// Let go of every key
+    pub fn release_all(&self) {
+        self.keys.send_if_modified(|keys| {
+            let changed = keys.held != Held::default();
+            keys.held = Held::default();
+            changed
+        });
+    }
+}
+
+/// What the frontend shows about keyboard driving, sent as the `drive` event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, specta::Type)]
+pub struct DriveState {
+    held: Held,
+    driving: bool,
+    throttle: i8,
+    strafe: i8,
+    steer: i8,
+    error: Option<String>,
+}
+
+/// Start streaming the held keys to the car
+pub fn start(app: AppHandle) {
+    tauri::async_runtime::spawn(stream(app));
+}
+
+async fn stream(app: AppHandle) {
+    let keyboard = app.state::<Keyboard>();
+    let connection = app.state::<Connection>();
+    let events = ApiEventTrigger::new(app.clone());
+    let mut keys = keyboard.keys.subscribe();
+    let mut shown = DriveState::default();
+    // The car being driven, to stop it once the keys are released
+    let mut driving: Option<Target> = None;
+    let mut next = Instant::now();
+
+    loop {
+        // Send right away when the keys change, otherwise keep repeating the drive
+        tokio::select! {
+            changed = keys.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = time::sleep_until(next) => {}
+        }
+        next = Instant::now() + PERIOD;
+
+        let Keys {
+            held,
+            speed,
+            target,
+        } = keys.borrow_and_update().clone();
+        let controls = drive::key_drive(held, speed);
+        let (throttle, strafe, steer) = controls;
+
+        let result = match target {
+            Some(target) if controls != (0, 0, 0) => {
+                let result = async {
+                    let car = connection.client_for(&target).await?;
+                    Ok::<_, ApiError>(drive::send(&car, controls).await?)
+                }
+                .await;
+                driving = Some(target);
+                result
+            }
+            _ => match driving.take() {
+                Some(target) => {
+                    async {
+                        let car = connection.client_for(&target).await?;
+                        Ok::<_, ApiError>(car.stop().await?)
+                    }
+                    .await
+                }
+                None => Ok(()),
+            },
+        };
+
+        let state = DriveState {
+            held,
+            driving: driving.is_some(),
+            throttle,
+            strafe,
+            steer,
+            error: result.err().map(|err| err.to_string()),
+        };
+        if state != shown {
+            let _ = events.drive(state.clone());
+            shown = state;
+        }
+    }
+}
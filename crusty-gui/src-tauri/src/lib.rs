@@ -1,51 +1,38 @@
-// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-
-use client::CarClient;
-
-mod client;
-
-#[tauri::command]
-async fn forward(speed: u8, ip: String) -> Result<(), String> {
-    let mut car_client = CarClient::connect(&ip).await?;
-    car_client.go_forward(speed).await?;
-    Ok(())
-}
-
-#[tauri::command]
-async fn backward(speed: u8, ip: String) -> Result<(), String> {
-    let mut car_client = CarClient::connect(&ip).await?;
-    car_client.go_backward(speed).await?;
-    Ok(())
-}
-
-#[tauri::command]
-async fn left(speed: u8, ip: String) -> Result<(), String> {
-    let mut car_client = CarClient::connect(&ip).await?;
-    car_client.turn_left(speed).await?;
-    Ok(())
-}
-
-#[tauri::command]
-async fn right(speed: u8, ip: String) -> Result<(), String> {
-    let mut car_client = CarClient::connect(&ip).await?;
-    car_client.turn_right(speed).await?;
-    Ok(())
-}
-
-#[tauri::command]
-async fn stop(ip: String) -> Result<(), String> {
-    let mut car_client = CarClient::connect(&ip).await?;
-    car_client.stop().await?;
-    Ok(())
-}
+// The commands the frontend calls are the taurpc procedures in api.rs
+
+use api::{Api, ApiImpl};
+use connection::Connection;
+use fleet::Fleet;
+use gamepad::Gamepad;
+use keyboard::Keyboard;
+use script::Scripts;
+
+mod api;
+mod calibration;
+mod connection;
+mod drive;
+mod error;
+mod fleet;
+mod gamepad;
+mod keyboard;
+mod mission;
+mod script;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![
-            forward, stop, left, right, backward
-        ])
+        .manage(Connection::default())
+        .manage(Fleet::default())
+        .manage(Gamepad::default())
+        .manage(Keyboard::default())
+        .manage(Scripts::default())
+        .setup(|app| {
+            gamepad::start(app.handle().clone());
+            keyboard::start(app.handle().clone());
+            Ok(())
+        })
+        .invoke_handler(taurpc::create_ipc_handler(ApiImpl.into_handler()))
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
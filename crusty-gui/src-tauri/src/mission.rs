@@ -0,0 +1,85 @@
+//! Missions edited in the frontend: a list of timed steps the car runs on its own
+
+use serde::{Deserialize, Serialize};
+use shared::mission::{self, Mission, MissionStatus, MissionStep, MISSION_LEN};
+
+use crate::error::ApiError;
+
+/// One step as the mission editor sends it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, specta::Type)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Step {
+    Drive { throttle: i8, steer: i8, ms: u16 },
+    Turn { speed: i8, ms: u16 },
+    Wait { ms: u16 },
+    Beep { freq: u16, ms: u16 },
+    Lights { r: u8, g: u8, b: u8 },
+}
+
+impl From<Step> for MissionStep {
+    fn from(step: Step) -> Self {
+        match step {
+            Step::Drive {
+                throttle,
+                steer,
+                ms,
+            } => MissionStep::Drive {
+                throttle: throttle.clamp(-100, 100),
+                steer: steer.clamp(-100, 100),
+                ms,
+            },
+            Step::Turn { speed, ms } => MissionStep::Turn {
+                speed: speed.clamp(-100, 100),
+                ms,
+            },
+            Step::Wait { ms } => MissionStep::Wait { ms },
+            Step::Beep { freq, ms } => MissionStep::Beep { freq, ms },
+            Step::Lights { r, g, b } => MissionStep::Lights { r, g, b },
+        }
+    }
+}
+
+/// The mission running `steps`
+pub fn build(steps: &[Step]) -> Result<Mission, ApiError> {
+    let steps: Vec<MissionStep> = steps.iter().map(|&step| step.into()).collect();
+    mission::mission(&steps).ok_or(ApiError::Invalid(format!(
+        "A mission has at most {MISSION_LEN} steps"
+    )))
+}
+
+/// How scripts see the progress of a mission
+pub fn status(status: MissionStatus) -> String {
+    match status {
+        MissionStatus::Idle => "idle".to_string(),
+        MissionStatus::Running { step } => format!("step {}", step + 1),
+        MissionStatus::Done => "done".to_string(),
+        MissionStatus::Aborted => "aborted".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_steps_become_a_bounded_mission() {
+        let steps: Vec<Step> = serde_json::from_str(
+            r#"[{"kind": "drive", "throttle": 50, "steer": -120, "ms": 800},
+                {"kind": "lights", "r": 0, "g": 255, "b": 0}]"#,
+        )
+        .unwrap();
+        let mission = build(&steps).unwrap();
+        assert_eq!(
+            mission[0],
+            Some(MissionStep::Drive {
+                throttle: 50,
+                steer: -100,
+                ms: 800
+            })
+        );
+        assert_eq!(mission[1], Some(MissionStep::Lights { r: 0, g: 255, b: 0 }));
+        assert_eq!(mission[2], None);
+
+        assert!(build(&[Step::Wait { ms: 10 }; MISSION_LEN + 1]).is_err());
+    }
+}
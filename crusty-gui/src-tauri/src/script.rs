@@ -0,0 +1,347 @@
+//! Rhai scripts driving the car, for experiments that shouldn't need a new build of the GUI
+//!
+//! A script runs on its own thread and calls the car through the functions registered here:
+//!
+//!     drive(throttle, steer)  forward(speed)  backward(speed)  left(speed)  right(speed)
+//!     stop()  beep(freq, ms)  wait(ms)  distance()  telemetry()
+//!
+//! `print` goes to the console in the GUI. `wait` keeps sending the last drive while it waits,
+//! like the keyboard does, so the car's deadman doesn't stop it. The car is stopped when the
+//! script ends, fails or is stopped from the GUI.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crusty_client::CarClient;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Position};
+use serde::Serialize;
+use shared::{DriveMode, LightTarget, Telemetry};
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::runtime::Handle;
+
+use crate::api::ApiEventTrigger;
+use crate::error::ApiError;
+
+/// How often `wait` sends the drive again
+const PERIOD: Duration = Duration::from_millis(50);
+
+/// The car as scripts see it
+pub trait Car {
+    fn drive(&self, throttle: i8, steer: i8) -> Result<(), String>;
+    fn stop(&self) -> Result<(), String>;
+    fn beep(&self, freq: u16, ms: u16) -> Result<(), String>;
+    fn telemetry(&self) -> Result<Telemetry, String>;
+}
+
+/// A car on the GUI's connection, called from the script's thread
+struct Remote {
+    runtime: Handle,
+    client: CarClient,
+}
+
+impl Car for Remote {
+    fn drive(&self, throttle: i8, steer: i8) -> Result<(), String> {
+        let result = self.runtime.block_on(self.client.drive(throttle, steer));
+        result.map_err(|e| e.to_string())
+    }
+
+    fn stop(&self) -> Result<(), String> {
+        let result = self.runtime.block_on(self.client.stop());
+        result.map_err(|e| e.to_string())
+    }
+
+    fn beep(&self, freq: u16, ms: u16) -> Result<(), String> {
+        let result = self.runtime.block_on(self.client.beep(freq, ms));
+        result.map_err(|e| e.to_string())
+    }
+
+    fn telemetry(&self) -> Result<Telemetry, String> {
+        let result = self.runtime.block_on(self.client.telemetry());
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// What the frontend is told about the script, sent as the `script` event
+#[derive(Debug, Clone, Serialize, specta::Type)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScriptEvent {
+    Output { text: String },
+    Finished { error: Option<String> },
+}
+
+/// The script running, if any
+#[derive(Default)]
+pub struct Scripts {
+    stop: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl Scripts {
+    /// Run `source` against the car on its own thread, one script at a time
+    pub fn start<R: Runtime>(
+        &self,
+        app: AppHandle<R>,
+        source: String,
+        client: CarClient,
+    ) -> Result<(), ApiError> {
+        let mut running = self.stop.lock().unwrap();
+        if running.is_some() {
+            return Err(ApiError::Invalid("A script is already running".to_string()));
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        *running = Some(stop.clone());
+
+        let car = Remote {
+            runtime: Handle::current(),
+            client,
+        };
+        thread::spawn(move || {
+            let output = ApiEventTrigger::new(app.clone());
+            let result = run(&source, Rc::new(car), &stop, move |text| {
+                let _ = output.script(ScriptEvent::Output { text });
+            });
+            app.state::<Scripts>().stop.lock().unwrap().take();
+            let _ = ApiEventTrigger::new(app.clone()).script(ScriptEvent::Finished {
+                error: result.err(),
+            });
+        });
+        Ok(())
+    }
+
+    /// Stop the script running, it ends at its next step
+    pub fn stop(&self) {
+        if let Some(stop) = self.stop.lock().unwrap().as_ref() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Run `source` until it ends or `stop` is set, then stop the car
+pub fn run(
+    source: &str,
+    car: Rc<dyn Car>,
+    stop: &Arc<AtomicBool>,
+    print: impl Fn(String) + 'static,
+) -> Result<(), String> {
+    let engine = engine(car.clone(), stop.clone(), print);
+    let result = match engine.run(source) {
+        Ok(()) => Ok(()),
+        Err(err) if matches!(*err, EvalAltResult::ErrorTerminated(..)) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    };
+    let stopped = car.stop();
+    result.and(stopped)
+}
+
+fn engine(car: Rc<dyn Car>, stop: Arc<AtomicBool>, print: impl Fn(String) + 'static) -> Engine {
+    let mut engine = Engine::new();
+    let print = Rc::new(print);
+    let debug = print.clone();
+    engine.on_print(move |text| print(text.to_string()));
+    engine.on_debug(move |text, _, _| debug(text.to_string()));
+    let terminate = stop.clone();
+    engine.on_progress(move |_| terminate.load(Ordering::Relaxed).then_some(Dynamic::UNIT));
+
+    // The drive `wait` keeps sending, none once stopped
+    let driving = Rc::new(Cell::new(None));
+    let drive = {
+        let (car, driving) = (car.clone(), driving.clone());
+        move |throttle: i64, steer: i64| -> Result<(), Box<EvalAltResult>> {
+            let (throttle, steer) = (percent(throttle), percent(steer));
+            driving.set(Some((throttle, steer)));
+            car.drive(throttle, steer).map_err(Into::into)
+        }
+    };
+    engine.register_fn("drive", drive.clone());
+    let forward = drive.clone();
+    engine.register_fn("forward", move |speed: i64| forward(speed, 0));
+    let backward = drive.clone();
+    engine.register_fn("backward", move |speed: i64| backward(-speed, 0));
+    let left = drive.clone();
+    engine.register_fn("left", move |speed: i64| left(0, -speed));
+    engine.register_fn("right", move |speed: i64| drive(0, speed));
+
+    let (stop_car, stopped) = (car.clone(), driving.clone());
+    engine.register_fn("stop", move || -> Result<(), Box<EvalAltResult>> {
+        stopped.set(None);
+        stop_car.stop().map_err(Into::into)
+    });
+
+    let beep = car.clone();
+    engine.register_fn(
+        "beep",
+        move |freq: i64, ms: i64| -> Result<(), Box<EvalAltResult>> {
+            let freq = freq.clamp(0, u16::MAX as i64) as u16;
+            let ms = ms.clamp(0, u16::MAX as i64) as u16;
+            beep.beep(freq, ms).map_err(Into::into)
+        },
+    );
+
+    let waiting = car.clone();
+    engine.register_fn("wait", move |ms: i64| -> Result<(), Box<EvalAltResult>> {
+        let until = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Err(EvalAltResult::ErrorTerminated(Dynamic::UNIT, Position::NONE).into());
+            }
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(left.min(PERIOD));
+            if let Some((throttle, steer)) = driving.get() {
+                waiting.drive(throttle, steer)?;
+            }
+        }
+    });
+
+    let distance = car.clone();
+    engine.register_fn(
+        "distance",
+        move || -> Result<Dynamic, Box<EvalAltResult>> {
+            let telemetry = distance.telemetry()?;
+            Ok(telemetry
+                .distance_cm
+                .map_or(Dynamic::UNIT, |cm| Dynamic::from_int(cm.into())))
+        },
+    );
+
+    engine.register_fn("telemetry", move || -> Result<Map, Box<EvalAltResult>> {
+        let telemetry = car.telemetry()?;
+        let mut map = Map::new();
+        map.insert("mode".into(), mode_name(telemetry.mode).into());
+        map.insert(
+            "light_left".into(),
+            Dynamic::from_int(telemetry.light_left.into()),
+        );
+        map.insert(
+            "light_right".into(),
+            Dynamic::from_int(telemetry.light_right.into()),
+        );
+        map.insert(
+            "distance_cm".into(),
+            telemetry
+                .distance_cm
+                .map_or(Dynamic::UNIT, |cm| Dynamic::from_int(cm.into())),
+        );
+        map.insert(
+            "motor_left".into(),
+            Dynamic::from_int(telemetry.motor_left.into()),
+        );
+        map.insert(
+            "motor_right".into(),
+            Dynamic::from_int(telemetry.motor_right.into()),
+        );
+        map.insert(
+            "mission".into(),
+            crate::mission::status(telemetry.mission).into(),
+        );
+        Ok(map)
+    });
+
+    engine
+}
+
+fn mode_name(mode: DriveMode) -> &'static str {
+    match mode {
+        DriveMode::Manual => "manual",
+        DriveMode::LightFollow(LightTarget::Brighter) => "follow_light",
+        DriveMode::LightFollow(LightTarget::Darker) => "avoid_light",
+    }
+}
+
+fn percent(value: i64) -> i8 {
+    value.clamp(-100, 100) as i8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Logs what the script asks of the car, with an obstacle at 30 cm
+    #[derive(Default)]
+    struct Logged {
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl Car for Logged {
+        fn drive(&self, throttle: i8, steer: i8) -> Result<(), String> {
+            self.calls
+                .borrow_mut()
+                .push(format!("drive {throttle} {steer}"));
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), String> {
+            self.calls.borrow_mut().push("stop".to_string());
+            Ok(())
+        }
+
+        fn beep(&self, freq: u16, ms: u16) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("beep {freq} {ms}"));
+            Ok(())
+        }
+
+        fn telemetry(&self) -> Result<Telemetry, String> {
+            Ok(Telemetry {
+                distance_cm: Some(30),
+                ..Telemetry::new()
+            })
+        }
+    }
+
+    fn run_logged(source: &str, stop: &Arc<AtomicBool>) -> (Result<(), String>, Vec<String>) {
+        let car = Rc::new(Logged::default());
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let output = printed.clone();
+        let result = run(source, car.clone(), stop, move |text| {
+            output.borrow_mut().push(text)
+        });
+        let mut calls = car.calls.take();
+        calls.extend(printed.take());
+        (result, calls)
+    }
+
+    #[test]
+    fn scripts_drive_until_the_obstacle_and_stop_at_the_end() {
+        let source = r#"
+            forward(150);
+            wait(120);
+            if distance() < 40 { left(60); beep(880, 100) }
+            print(telemetry().mode);
+        "#;
+        let (result, calls) = run_logged(source, &Arc::new(AtomicBool::new(false)));
+        assert_eq!(result, Ok(()));
+
+        let drives = calls.iter().filter(|call| *call == "drive 100 0").count();
+        assert!(drives >= 2, "wait keeps driving: {calls:?}");
+        let end = &calls[drives..];
+        assert_eq!(end, ["drive 0 -60", "beep 880 100", "stop", "manual"]);
+    }
+
+    #[test]
+    fn stopped_scripts_end_and_failed_scripts_report_why() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopper.store(true, Ordering::Relaxed);
+        });
+        let (result, calls) = run_logged("forward(50); loop { wait(10) }", &stop);
+        let calls: Vec<_> = calls
+            .into_iter()
+            .filter(|call| call != "drive 50 0")
+            .collect();
+        assert_eq!(result, Ok(()));
+        assert_eq!(calls, ["stop"]);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (result, calls) = run_logged("forward(50); fly()", &stop);
+        assert!(result.unwrap_err().contains("fly"));
+        assert_eq!(calls, ["drive 50 0", "stop"]);
+    }
+}
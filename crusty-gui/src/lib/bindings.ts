@@ -0,0 +1,217 @@
+// This file has been generated by Specta. DO NOT EDIT.
+
+import { createTauRPCProxy as createProxy, type InferCommandOutput } from 'taurpc'
+type TAURI_CHANNEL<T> = (response: T) => void
+
+
+export type ApiError = 
+/**
+ * The car didn't connect or answer in time
+ */
+{ kind: "timeout" } | 
+/**
+ * The connection couldn't be made or broke down
+ */
+{ kind: "connection"; detail: string } | 
+/**
+ * The car asked for a key and none was given
+ */
+{ kind: "key_required" } | 
+/**
+ * The car refused the command
+ */
+{ kind: "rejected"; detail: ErrorCode } | 
+/**
+ * The car answered with something that doesn't make sense
+ */
+{ kind: "protocol"; detail: string } | 
+/**
+ * The request can't be carried out as given
+ */
+{ kind: "invalid"; detail: string } | 
+/**
+ * A session or export file couldn't be read or written
+ */
+{ kind: "file"; detail: string }
+
+export type Binding = { 
+/**
+ * The gilrs name of the button, like `South`
+ */
+button: string; action: PadAction }
+
+/**
+ * A command sent to every car of the fleet
+ */
+export type Broadcast = "stop" | "horn" | "manual" | "follow_light" | "avoid_light"
+
+export type Calibration = { front_left: WheelCalibration; front_right: WheelCalibration; rear_left: WheelCalibration; rear_right: WheelCalibration }
+
+export type CarCommand = { Forward: number } | { Backward: number } | { TurnLeft: number } | { TurnRight: number } | "Stop" | { SetAuthKey: [number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number] | null } | { Beep: { freq: number; ms: number } } | { PlayMelody: Melody } | { SetIrKeymap: [(IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null)] } | { SetMode: DriveMode } | "GetTelemetry" | { Drive: { throttle: number; steer: number } } | "GetConfig" | { SetConfig: Settings } | { RunMission: [(MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null), (MissionStep | null)] } | { SetCalibration: { calibration: Calibration; store: boolean } } | "GetCalibration" | { Holonomic: { vx: number; vy: number; omega: number } } | { ReadLog: { from: number } } | { SetProfile: Profile } | { LockProfile: ProfileLock } | { TurnBy: { degrees: number } } | "GetProfileChallenge"
+
+/**
+ * A car a broadcast command failed on
+ */
+export type CarFailure = { name: string; error: ApiError }
+
+/**
+ * How a car of the fleet is doing
+ */
+export type CarStatus = { name: string; ip: string; connected: boolean; telemetry: Telemetry | null; error: ApiError | null }
+
+export type Chassis = "Tank" | "Mecanum"
+
+/**
+ * A direction key of the keyboard or the on-screen pad
+ */
+export type Direction = "forward" | "backward" | "left" | "right" | "strafe_left" | "strafe_right"
+
+/**
+ * Which way the car pulled while driving straight
+ */
+export type Drift = "left" | "right"
+
+export type DriveMode = "Manual" | { LightFollow: LightTarget }
+
+/**
+ * What the frontend shows about keyboard driving, sent as the `drive` event
+ */
+export type DriveState = { held: Held; driving: boolean; throttle: number; strafe: number; steer: number; error: string | null }
+
+export type ErrorCode = "Malformed" | "Unauthorized" | "AuthFailed" | "BadSignature" | "Replay" | "StorageFailed" | "ProfileLocked" | "NoImu" | "OutOfRange"
+
+export type Event = { Boot: ResetReason } | "Connected" | "Disconnected" | "Failsafe" | { Rejected: { code: ErrorCode; count: number } } | "LowBattery"
+
+/**
+ * A car of the fleet
+ */
+export type FleetCar = { name: string; ip: string; key: string | null }
+
+export type GamepadSettings = { shaping: Shaping; 
+/**
+ * How often the input is sent to the car
+ */
+rate_hz: number; 
+/**
+ * The left stick moves the car sideways instead of steering, for mecanum wheels
+ */
+strafe: boolean; bindings: Binding[] }
+
+/**
+ * What the frontend shows about the gamepad, sent as the `gamepad` event
+ */
+export type GamepadStatus = { name: string | null; driving: boolean; throttle: number; strafe: number; steer: number; gear: number; error: string | null }
+
+/**
+ * The direction keys held down
+ */
+export type Held = { forward: boolean; backward: boolean; left: boolean; right: boolean; strafe_left: boolean; strafe_right: boolean }
+
+export type ImuReading = { heading_deg: number; yaw_rate_dps: number; accel_g: [number, number, number] }
+
+export type IrAction = "Forward" | "Backward" | "TurnLeft" | "TurnRight" | "Stop" | "Horn" | "SpeedUp" | "SpeedDown"
+
+export type IrBinding = { command: number; action: IrAction }
+
+export type LightTarget = "Brighter" | "Darker"
+
+export type LogEntry = { seq: number; boot: number; uptime_ms: number; event: Event }
+
+export type Melody = "Boot" | "Connect" | "Disconnect" | "LowBattery" | "Obstacle" | "Horn"
+
+export type MissionStatus = "Idle" | { Running: { step: number } } | "Done" | "Aborted"
+
+export type MissionStep = { Drive: { throttle: number; steer: number; ms: number } } | { Turn: { speed: number; ms: number } } | { Wait: { ms: number } } | { Beep: { freq: number; ms: number } } | { Lights: { r: number; g: number; b: number } }
+
+/**
+ * What a button does when pressed
+ */
+export type PadAction = "stop" | "horn" | "manual" | "follow_light" | "avoid_light" | "gear_up" | "gear_down"
+
+export type Profile = "Kid" | "Indoor" | "Race"
+
+export type ProfileLock = { Lock: { admin: [number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number] } } | { Unlock: { proof: [number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number, number] } }
+
+export type PwmSettings = { frequency_hz: number; phase_correct: boolean }
+
+export type ResetReason = "PowerOn" | "Watchdog" | "Forced"
+
+/**
+ * What the frontend is told about the script, sent as the `script` event
+ */
+export type ScriptEvent = { kind: "output"; text: string } | { kind: "finished"; error: string | null }
+
+export type Settings = { deadman_ms: number; ir_keymap: [(IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null), (IrBinding | null)]; chassis: Chassis; pwm: PwmSettings }
+
+/**
+ * How stick travel maps to speed
+ */
+export type Shaping = { 
+/**
+ * Travel around the centre that is ignored, from 0 to 1
+ */
+deadzone: number; 
+/**
+ * 0 is linear, 1 is cubic for finer control around the centre
+ */
+expo: number }
+
+/**
+ * One step as the mission editor sends it
+ */
+export type Step = { kind: "drive"; throttle: number; steer: number; ms: number } | { kind: "turn"; speed: number; ms: number } | { kind: "wait"; ms: number } | { kind: "beep"; freq: number; ms: number } | { kind: "lights"; r: number; g: number; b: number }
+
+/**
+ * The car at `ip`, unlocked with `key`
+ */
+export type Target = { ip: string; key: string | null }
+
+export type Telemetry = { mode: DriveMode; light_left: number; light_right: number; distance_cm: number | null; loop_latency_us: number; motor_left: number; motor_right: number; mission: MissionStatus; profile: Profile; profile_locked: boolean; imu: ImuReading | null }
+
+export type Wheel = "front_left" | "front_right" | "rear_left" | "rear_right"
+
+export type WheelCalibration = { invert: boolean; gain: number; min_duty: number; deadband: number }
+
+const ARGS_MAP = { '':'{"run_script":["car","source"],"calibration_trim":["calibration","drift"],"export_telemetry":["path","csv_path"],"gamepad_settings":[],"calibration":["car"],"lock_profile":["car","admin","lock"],"fleet_status":[],"replay_session":["car","path","speed"],"fleet_broadcast":["command"],"set_gamepad_settings":["settings"],"set_settings":["car","settings"],"settings":["car"],"horn":["car"],"calibration_spin":["car","calibration","wheel","duty"],"fleet_cars":[],"calibration_straight":["car","calibration","speed"],"event_log":["car"],"fleet_add":["car"],"drive":["update"],"set_mode":["car","mode"],"script":["event"],"telemetry":["car"],"set_calibration":["car","calibration","store"],"send":["car","command"],"stop_script":[],"start_recording":["car","path"],"drive_key":["car","direction","pressed","speed"],"set_key":["car","new_key"],"export_event_log":["entries","csv_path"],"fleet_remove":["name"],"release_keys":[],"turn_by":["car","degrees"],"stop_recording":[],"set_profile":["car","profile"],"gamepad":["status"],"run_mission":["car","steps"],"gamepad_drive":["car"],"stop":["car"]}' }
+export type Router = { '': { stop: (car: Target) => Promise<null>, 
+horn: (car: Target) => Promise<null>, 
+set_mode: (car: Target, mode: DriveMode) => Promise<null>, 
+telemetry: (car: Target) => Promise<Telemetry>, 
+send: (car: Target, command: CarCommand) => Promise<null>, 
+settings: (car: Target) => Promise<Settings>, 
+set_settings: (car: Target, settings: Settings) => Promise<null>, 
+calibration: (car: Target) => Promise<Calibration>, 
+set_calibration: (car: Target, calibration: Calibration, store: boolean) => Promise<null>, 
+calibration_spin: (car: Target, calibration: Calibration, wheel: Wheel, duty: number) => Promise<null>, 
+calibration_straight: (car: Target, calibration: Calibration, speed: number) => Promise<null>, 
+calibration_trim: (calibration: Calibration, drift: Drift) => Promise<Calibration>, 
+set_key: (car: Target, newKey: string) => Promise<null>, 
+set_profile: (car: Target, profile: Profile) => Promise<null>, 
+lock_profile: (car: Target, admin: string, lock: boolean) => Promise<null>, 
+turn_by: (car: Target, degrees: number) => Promise<null>, 
+run_mission: (car: Target, steps: Step[]) => Promise<null>, 
+run_script: (car: Target, source: string) => Promise<null>, 
+stop_script: () => Promise<void>, 
+start_recording: (car: Target, path: string) => Promise<null>, 
+stop_recording: () => Promise<null>, 
+replay_session: (car: Target, path: string, speed: number) => Promise<null>, 
+export_telemetry: (path: string, csvPath: string) => Promise<null>, 
+event_log: (car: Target) => Promise<LogEntry[]>, 
+export_event_log: (entries: LogEntry[], csvPath: string) => Promise<null>, 
+fleet_cars: () => Promise<FleetCar[]>, 
+fleet_add: (car: FleetCar) => Promise<null>, 
+fleet_remove: (name: string) => Promise<null>, 
+fleet_status: () => Promise<CarStatus[]>, 
+fleet_broadcast: (command: Broadcast) => Promise<CarFailure[]>, 
+gamepad_settings: () => Promise<GamepadSettings>, 
+set_gamepad_settings: (settings: GamepadSettings) => Promise<void>, 
+gamepad_drive: (car: Target | null) => Promise<void>, 
+drive_key: (car: Target, direction: Direction, pressed: boolean, speed: number) => Promise<void>, 
+release_keys: () => Promise<void>, 
+drive: (update: DriveState) => Promise<void>, 
+gamepad: (status: GamepadStatus) => Promise<void>, 
+script: (event: ScriptEvent) => Promise<void> } };
+
+
+export type { InferCommandOutput }
+export const createTauRPCProxy = () => createProxy<Router>(ARGS_MAP)
@@ -0,0 +1,314 @@
+//! Runs the car's command handling and motion control on the host
+//!
+//! The simulator serves the command port with the same code as the firmware and drives a
+//! kinematic model of the car instead of the motors, so clients and failsafes can be tested
+//! without hardware. Drive setpoints are taken over UDP on the same port number, like the car,
+//! and the browser controller page is served on the HTTP port.
+//!
+//!     crusty-sim [--port PORT] [--http-port PORT] [--key PASSPHRASE] [--deadman-ms MS] [--wall METERS] [--no-imu]
+//!
+//! The page is served on port 8080 by default, as port 80 needs privileges on most hosts. `--port 0`
+//! and `--http-port 0` pick free ports. `--no-imu` simulates a car without an IMU. The addresses are printed on stdout once the simulator
+//! listens, the command port first.
+
+mod model;
+
+use core::cell::{Cell, RefCell};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+use std::process::exit;
+
+use async_io::Async;
+use crusty_core::drive::DriveChannel;
+use crusty_core::motion::{deadman_lease, Arbiter, Controller, MotionRequest, Source, Wheels, CONTROL_PERIOD};
+use crusty_core::server::{self, Platform};
+use crusty_core::web;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_adapters::futures_03::FromFutures;
+use log::*;
+use model::Model;
+use shared::auth::{self, Key};
+use shared::config::{Calibration, CarConfig};
+use shared::drive::MAX_DATAGRAM_LEN;
+use shared::event_log::{Event, LogEntry, LogPage, ResetReason, LOG_PAGE_LEN};
+use shared::{DriveMode, Melody, Telemetry, COMMAND_PORT};
+
+static MOTION_REQUESTS: Channel<CriticalSectionRawMutex, MotionRequest, 8> = Channel::new();
+
+static MODE: Mutex<CriticalSectionRawMutex, Cell<DriveMode>> = Mutex::new(Cell::new(DriveMode::Manual));
+
+static TELEMETRY: Mutex<CriticalSectionRawMutex, RefCell<Telemetry>> = Mutex::new(RefCell::new(Telemetry::new()));
+
+static DRIVE_CHANNEL: Mutex<CriticalSectionRawMutex, RefCell<DriveChannel<IpAddr>>> =
+    Mutex::new(RefCell::new(DriveChannel::new()));
+
+// Nothing to persist to, the event log lasts until the simulator exits
+static EVENT_LOG: Mutex<CriticalSectionRawMutex, RefCell<Vec<LogEntry>>> = Mutex::new(RefCell::new(Vec::new()));
+
+fn update_telemetry(f: impl FnOnce(&mut Telemetry)) {
+    TELEMETRY.lock(|telemetry| f(&mut telemetry.borrow_mut()));
+}
+
+fn record(event: Event) {
+    info!("event {event:?}");
+    EVENT_LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        let entry = LogEntry {
+            seq: log.len() as u32,
+            boot: 0,
+            uptime_ms: Instant::now().as_millis() as u32,
+            event,
+        };
+        log.push(entry);
+    });
+}
+
+struct Options {
+    port: u16,
+    http_port: u16,
+    key: Option<String>,
+    deadman_ms: u16,
+    wall: Option<f32>,
+    imu: bool,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        port: COMMAND_PORT,
+        http_port: 8080,
+        key: None,
+        deadman_ms: 0,
+        wall: None,
+        imu: true,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|e| format!("bad port: {e}"))?,
+            "--http-port" => options.http_port = value()?.parse().map_err(|e| format!("bad HTTP port: {e}"))?,
+            "--key" => options.key = Some(value()?),
+            "--deadman-ms" => options.deadman_ms = value()?.parse().map_err(|e| format!("bad deadman: {e}"))?,
+            "--wall" => options.wall = Some(value()?.parse().map_err(|e| format!("bad wall distance: {e}"))?),
+            "--no-imu" => options.imu = false,
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+    Ok(options)
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        eprintln!("usage: crusty-sim [--port PORT] [--http-port PORT] [--key PASSPHRASE] [--deadman-ms MS] [--wall METERS] [--no-imu]");
+        exit(2);
+    });
+
+    let car_config = CarConfig {
+        auth_key: options.key.as_deref().map(auth::key_from_passphrase),
+        deadman_ms: options.deadman_ms,
+        ..CarConfig::default()
+    };
+    let network_lease = deadman_lease(car_config.deadman_ms);
+
+    let listener = Async::<TcpListener>::bind((Ipv4Addr::UNSPECIFIED, options.port)).unwrap_or_else(|e| {
+        eprintln!("failed to listen on port {}: {e}", options.port);
+        exit(1);
+    });
+    let address = listener.get_ref().local_addr().unwrap();
+    let socket = Async::<UdpSocket>::bind((Ipv4Addr::UNSPECIFIED, address.port())).unwrap_or_else(|e| {
+        eprintln!("failed to listen on UDP port {}: {e}", address.port());
+        exit(1);
+    });
+    let http = Async::<TcpListener>::bind((Ipv4Addr::UNSPECIFIED, options.http_port)).unwrap_or_else(|e| {
+        eprintln!("failed to listen on HTTP port {}: {e}", options.http_port);
+        exit(1);
+    });
+    println!("Listening on {address}");
+    println!("Controller page on http://{}/", http.get_ref().local_addr().unwrap());
+    record(Event::Boot(ResetReason::PowerOn));
+
+    spawner.must_spawn(motion_task(Arbiter::new(network_lease), Model::new(options.wall), options.imu));
+    spawner.must_spawn(server_task(listener, http, car_config));
+    spawner.must_spawn(drive_task(socket));
+}
+
+// Runs the control loop against the model, like the firmware's motion task on core 1
+#[embassy_executor::task]
+async fn motion_task(arbiter: Arbiter, mut model: Model, imu: bool) {
+    let mut controller = Controller::new(arbiter);
+    let mut worst = Duration::from_ticks(0);
+    let mut due = Instant::now();
+    let mut last_log = Instant::now();
+
+    loop {
+        Timer::at(due).await;
+        let now = Instant::now();
+        controller.set_heading(imu.then(|| model.imu().heading_deg));
+
+        while let Ok(request) = MOTION_REQUESTS.try_receive() {
+            if let Some(mode) = controller.request(request, MODE.lock(Cell::get), now) {
+                MODE.lock(|cell| cell.set(mode));
+                update_telemetry(|telemetry| telemetry.mode = mode);
+            }
+        }
+
+        let distance = model.distance_cm();
+        let tick = controller.tick(now, controller.too_close(distance));
+        if tick.obstacle {
+            info!("obstacle at {:?} cm", distance);
+        }
+        if tick.deadman == Some(Source::Network) {
+            record(Event::Failsafe);
+        }
+        if let Some((freq, ms)) = tick.beep {
+            info!("beep {freq} Hz for {ms} ms");
+        }
+        if let Some(colour) = tick.lights {
+            info!("lights {colour:?}");
+        }
+
+        let wheels = controller.output();
+        model.step(wheels, CONTROL_PERIOD.as_micros() as f32 / 1e6);
+        update_telemetry(|telemetry| {
+            telemetry.motor_left = wheels.left();
+            telemetry.motor_right = wheels.right();
+            telemetry.distance_cm = model.distance_cm();
+            telemetry.mission = controller.mission();
+            telemetry.imu = imu.then(|| model.imu());
+        });
+
+        let latency = Instant::now() - due;
+        if latency > worst {
+            worst = latency;
+            update_telemetry(|telemetry| telemetry.loop_latency_us = worst.as_micros() as u32);
+        }
+
+        if now - last_log >= Duration::from_secs(1) && wheels != Wheels::STOP {
+            debug!("pose {:?}", model.pose());
+            last_log = now;
+        }
+
+        due = (due + CONTROL_PERIOD).max(Instant::now());
+    }
+}
+
+// Serves one client at a time on either port, like the firmware's TCP task
+#[embassy_executor::task]
+async fn server_task(listener: Async<TcpListener>, http: Async<TcpListener>, mut car_config: CarConfig) {
+    loop {
+        let (accepted, browser) = match select(listener.accept(), http.accept()).await {
+            Either::First(accepted) => (accepted, false),
+            Either::Second(accepted) => (accepted, true),
+        };
+        let (stream, address) = match accepted {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("accept error: {e}");
+                continue;
+            }
+        };
+        info!("Received connection from {address}");
+
+        let mut socket = FromFutures::new(stream);
+        let mut platform = Simulator { peer: address.ip() };
+        if browser {
+            match web::respond(&mut socket).await {
+                Ok(Some(mut websocket)) => {
+                    server::serve(&mut websocket, rand::random(), &mut car_config, &mut platform).await;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("HTTP error: {e}"),
+            }
+        } else {
+            server::serve(&mut socket, rand::random(), &mut car_config, &mut platform).await;
+        }
+        DRIVE_CHANNEL.lock(|channel| channel.borrow_mut().close());
+        info!("Connection closed");
+    }
+}
+
+// Applies the drive setpoints of the connected client, like the firmware's UDP task
+#[embassy_executor::task]
+async fn drive_task(socket: Async<UdpSocket>) {
+    let mut datagram = [0; MAX_DATAGRAM_LEN];
+    loop {
+        let (n, from) = match socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("UDP receive error: {e}");
+                continue;
+            }
+        };
+        let motion = DRIVE_CHANNEL.lock(|channel| channel.borrow_mut().receive(&from.ip(), &datagram[..n]));
+        if let Some(motion) = motion {
+            MOTION_REQUESTS.send(MotionRequest::Set { source: Source::Network, motion }).await;
+        }
+    }
+}
+
+/// The model behind the commands of a connection
+struct Simulator {
+    peer: IpAddr,
+}
+
+impl Platform for Simulator {
+    async fn motion(&mut self, request: MotionRequest) {
+        MOTION_REQUESTS.send(request).await;
+    }
+
+    fn set_mode(&mut self, mode: DriveMode) {
+        MODE.lock(|cell| cell.set(mode));
+        update_telemetry(|telemetry| telemetry.mode = mode);
+    }
+
+    fn telemetry(&self) -> Telemetry {
+        TELEMETRY.lock(|telemetry| telemetry.borrow().clone())
+    }
+
+    fn beep(&mut self, freq: u16, ms: u16) {
+        info!("beep {freq} Hz for {ms} ms");
+    }
+
+    fn play(&mut self, melody: Melody) {
+        info!("playing {melody:?}");
+    }
+
+    fn store_config(&mut self, _config: &CarConfig) -> bool {
+        // Nothing to persist to, the configuration lasts until the simulator exits
+        true
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        // The model's motors are all alike, there is nothing to correct
+        info!("calibration {calibration:?}");
+    }
+
+    fn open_drive_channel(&mut self, key: Option<Key>) {
+        DRIVE_CHANNEL.lock(|channel| channel.borrow_mut().open(self.peer, key));
+    }
+
+    fn record(&mut self, event: Event) {
+        record(event);
+    }
+
+    async fn read_log(&mut self, from: u32) -> LogPage {
+        let mut page = [None; LOG_PAGE_LEN];
+        EVENT_LOG.lock(|log| {
+            let log = log.borrow();
+            let entries = log.get(from as usize..).unwrap_or_default();
+            for (slot, entry) in page.iter_mut().zip(entries) {
+                *slot = Some(*entry);
+            }
+        });
+        page
+    }
+}
@@ -0,0 +1,160 @@
+//! Kinematic model of the car: four driven wheels on a flat floor
+//!
+//! With both wheels of each side at the same speed it is a differential drive, wheels driven apart
+//! move it sideways like mecanum wheels do.
+//!
+//! The car starts at the origin facing along +x. An optional wall runs across the floor at a
+//! fixed x, it stops the car and is what the simulated ultrasonic sensor sees.
+//!
+//! The simulated IMU is perfect: no bias, no drift, the heading is the pose's.
+
+use core::f32::consts::PI;
+
+use crusty_core::motion::Wheels;
+use shared::ImuReading;
+
+/// Ground speed at 100% duty cycle, in m/s
+pub const MAX_SPEED: f32 = 0.5;
+
+/// Distance between the left and right wheels, in m
+pub const TRACK_WIDTH: f32 = 0.14;
+
+/// Furthest the HC-SR04 reports an echo from, in m
+const SENSOR_RANGE: f32 = 4.0;
+
+/// Half the angle of the HC-SR04's beam
+const SENSOR_CONE: f32 = 15.0 * PI / 180.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pose {
+    pub x: f32,       // m
+    pub y: f32,       // m
+    pub heading: f32, // rad counter-clockwise from +x, -pi to pi
+}
+
+pub struct Model {
+    pose: Pose,
+    turn_rate: f32, // rad/s counter-clockwise
+    wall: Option<f32>,
+}
+
+impl Model {
+    /// `wall` is the x of a wall in front of the car, in m
+    pub fn new(wall: Option<f32>) -> Self {
+        Self {
+            pose: Pose::default(),
+            turn_rate: 0.0,
+            wall,
+        }
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Move the car for `dt` seconds with the wheels at `wheels` percent
+    pub fn step(&mut self, wheels: Wheels, dt: f32) {
+        let [front_left, front_right, rear_left, rear_right] =
+            [wheels.front_left, wheels.front_right, wheels.rear_left, wheels.rear_right]
+                .map(|speed| speed as f32 / 100.0 * MAX_SPEED);
+        let speed = (front_left + front_right + rear_left + rear_right) / 4.0;
+        // Positive to the car's right
+        let sideways = (front_left - front_right - rear_left + rear_right) / 4.0;
+        let turn_rate = ((front_right + rear_right) - (front_left + rear_left)) / 2.0 / TRACK_WIDTH;
+
+        self.turn_rate = turn_rate;
+        let pose = &mut self.pose;
+        let (sin, cos) = pose.heading.sin_cos();
+        pose.x += (speed * cos + sideways * sin) * dt;
+        pose.y += (speed * sin - sideways * cos) * dt;
+        pose.heading = (pose.heading + turn_rate * dt + PI).rem_euclid(2.0 * PI) - PI;
+
+        // The car bumps into the wall rather than driving through it
+        if let Some(wall) = self.wall {
+            pose.x = pose.x.min(wall);
+        }
+    }
+
+    /// What the IMU measures, clockwise like on the car
+    pub fn imu(&self) -> ImuReading {
+        ImuReading {
+            heading_deg: -self.pose.heading.to_degrees(),
+            yaw_rate_dps: -self.turn_rate.to_degrees(),
+            accel_g: [0.0, 0.0, 1.0],
+        }
+    }
+
+    /// What the ultrasonic sensor measures, in cm
+    pub fn distance_cm(&self) -> Option<u16> {
+        let wall = self.wall?;
+        if self.pose.heading.abs() > SENSOR_CONE {
+            return None;
+        }
+
+        let distance = (wall - self.pose.x) / self.pose.heading.cos();
+        (distance <= SENSOR_RANGE).then_some((distance * 100.0).round() as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(model: &mut Model, (left, right): (i8, i8), seconds: f32) {
+        run_wheels(model, Wheels::sides(left, right), seconds);
+    }
+
+    fn run_wheels(model: &mut Model, wheels: Wheels, seconds: f32) {
+        for _ in 0..(seconds * 100.0) as u32 {
+            model.step(wheels, 0.01);
+        }
+    }
+
+    #[test]
+    fn drives_straight() {
+        let mut model = Model::new(None);
+        run(&mut model, (100, 100), 2.0);
+        let pose = model.pose();
+        assert!((pose.x - 2.0 * MAX_SPEED).abs() < 1e-3);
+        assert!(pose.y.abs() < 1e-3);
+        assert_eq!(pose.heading, 0.0);
+    }
+
+    #[test]
+    fn spins_in_place() {
+        let mut model = Model::new(None);
+        // Half a turn takes pi * track / 2 / speed
+        let seconds = PI * TRACK_WIDTH / 2.0 / (0.5 * MAX_SPEED);
+        run(&mut model, (-50, 50), seconds);
+        let pose = model.pose();
+        assert!(pose.x.abs() < 1e-3 && pose.y.abs() < 1e-3);
+        assert!(pose.heading.abs() > PI - 0.05);
+    }
+
+    #[test]
+    fn strafes_sideways() {
+        let mut model = Model::new(None);
+        run_wheels(&mut model, crusty_core::motion::mecanum(0, 100, 0), 2.0);
+        let pose = model.pose();
+        assert!(pose.x.abs() < 1e-3);
+        assert!((pose.y + 2.0 * MAX_SPEED).abs() < 1e-3);
+        assert_eq!(pose.heading, 0.0);
+    }
+
+    #[test]
+    fn senses_and_stops_at_the_wall() {
+        let mut model = Model::new(Some(1.0));
+        assert_eq!(model.distance_cm(), Some(100));
+
+        run(&mut model, (100, 100), 1.0);
+        assert!(model.distance_cm().is_some_and(|cm| cm < 51));
+
+        run(&mut model, (100, 100), 2.0);
+        assert_eq!(model.pose().x, 1.0);
+        assert_eq!(model.distance_cm(), Some(0));
+
+        // Facing away the sensor sees nothing
+        run(&mut model, (-50, 50), 1.0);
+        assert_eq!(model.distance_cm(), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Drives the simulator over its command port, the way a client drives the car
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use shared::auth::{self, ClientSession};
+use shared::drive::{self, Sender, MAX_DATAGRAM_LEN};
+use shared::frame;
+use shared::{CarCommand, ClientMessage, ErrorCode, ServerMessage, Telemetry};
+
+/// A simulator process on a free port, killed when dropped
+struct Sim {
+    child: Child,
+    port: u16,
+    http_port: u16,
+}
+
+impl Sim {
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_crusty-sim"))
+            .args(["--port", "0", "--http-port", "0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start simulator");
+
+        // The command port, then the controller page
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut port = || {
+            let mut line = String::new();
+            stdout.read_line(&mut line).unwrap();
+            line.trim().trim_end_matches('/').rsplit(':').next().unwrap().parse().unwrap()
+        };
+        let (port, http_port) = (port(), port());
+        Self { child, port, http_port }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut client = Client {
+            stream,
+            session: None,
+            challenge: None,
+        };
+        let ServerMessage::Hello { challenge } = client.read() else {
+            panic!("expected a greeting");
+        };
+        client.challenge = challenge;
+        client
+    }
+}
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    session: Option<ClientSession>,
+    challenge: Option<auth::Nonce>,
+}
+
+impl Client {
+    fn write(&mut self, message: &ClientMessage) {
+        let mut buf = [0; frame::HEADER_LEN + frame::MAX_FRAME_LEN];
+        let n = frame::encode(message, &mut buf).unwrap();
+        self.stream.write_all(&buf[..n]).unwrap();
+    }
+
+    fn read(&mut self) -> ServerMessage {
+        let mut header = [0; frame::HEADER_LEN];
+        self.stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; frame::payload_len(header)];
+        self.stream.read_exact(&mut payload).unwrap();
+        frame::decode(&payload).unwrap()
+    }
+
+    fn authenticate(&mut self, passphrase: &str) -> ServerMessage {
+        let key = auth::key_from_passphrase(passphrase);
+        let challenge = self.challenge.expect("the simulator didn't send a challenge");
+        let (session, response) = ClientSession::respond(&key, &challenge, [7; auth::NONCE_LEN]);
+        self.session = Some(session);
+        self.write(&ClientMessage::Auth(response));
+        self.read()
+    }
+
+    fn send(&mut self, command: CarCommand) -> ServerMessage {
+        let message = match &mut self.session {
+            Some(session) => ClientMessage::Signed(session.sign(command)),
+            None => ClientMessage::Command(command),
+        };
+        self.write(&message);
+        self.read()
+    }
+
+    fn telemetry(&mut self) -> Telemetry {
+        match self.send(CarCommand::GetTelemetry) {
+            ServerMessage::Telemetry(telemetry) => telemetry,
+            other => panic!("expected telemetry, got {other:?}"),
+        }
+    }
+
+    /// Poll the telemetry until `done` holds, panicking after a second
+    fn wait_for(&mut self, done: impl Fn(&Telemetry) -> bool) -> Telemetry {
+        let start = Instant::now();
+        loop {
+            let telemetry = self.telemetry();
+            if done(&telemetry) {
+                return telemetry;
+            }
+            assert!(start.elapsed() < Duration::from_secs(1), "timed out at {telemetry:?}");
+            sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+#[test]
+fn forward_ramps_up_and_stop_is_immediate() {
+    let sim = Sim::start(&[]);
+    let mut client = sim.connect();
+
+    assert_eq!(client.send(CarCommand::Forward(60)), ServerMessage::Ack);
+    let telemetry = client.telemetry();
+    assert!(telemetry.motor_left < 60, "motors jumped straight to {telemetry:?}");
+    client.wait_for(|telemetry| telemetry.motor_left == 60 && telemetry.motor_right == 60);
+
+    assert_eq!(client.send(CarCommand::Stop), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left == 0 && telemetry.motor_right == 0);
+}
+
+#[test]
+fn turns_drive_the_sides_apart() {
+    let sim = Sim::start(&[]);
+    let mut client = sim.connect();
+
+    assert_eq!(client.send(CarCommand::TurnLeft(40)), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left == -40 && telemetry.motor_right == 40);
+}
+
+#[test]
+fn turns_by_an_angle_with_the_imu() {
+    let sim = Sim::start(&[]);
+    let mut client = sim.connect();
+
+    // Like on the car, there is no heading until the IMU's first reading
+    client.wait_for(|telemetry| telemetry.imu.is_some());
+    assert_eq!(client.send(CarCommand::TurnBy { degrees: 90 }), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left > 0 && telemetry.motor_right < 0);
+    let telemetry = client.wait_for(|telemetry| telemetry.motor_left == 0 && telemetry.motor_right == 0);
+    let heading = telemetry.imu.unwrap().heading_deg;
+    assert!((heading - 90.0).abs() < 3.0, "stopped at {heading}");
+
+    let sim = Sim::start(&["--no-imu"]);
+    let mut client = sim.connect();
+    assert_eq!(client.telemetry().imu, None);
+    assert_eq!(client.send(CarCommand::TurnBy { degrees: 90 }), ServerMessage::Error(ErrorCode::NoImu));
+}
+
+#[test]
+fn deadman_stops_the_car() {
+    let sim = Sim::start(&["--deadman-ms", "200"]);
+    let mut client = sim.connect();
+
+    assert_eq!(client.send(CarCommand::Forward(50)), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left == 50);
+    client.wait_for(|telemetry| telemetry.motor_left == 0);
+}
+
+#[test]
+fn obstacle_stops_forward_motion() {
+    let sim = Sim::start(&["--wall", "0.3"]);
+    let mut client = sim.connect();
+    client.wait_for(|telemetry| telemetry.distance_cm == Some(30));
+
+    assert_eq!(client.send(CarCommand::Forward(50)), ServerMessage::Ack);
+    let telemetry = client.wait_for(|telemetry| telemetry.distance_cm.is_some_and(|cm| cm < 20));
+    assert!(telemetry.distance_cm.unwrap() > 5, "car didn't stop in time: {telemetry:?}");
+    client.wait_for(|telemetry| telemetry.motor_left == 0);
+
+    // Forward stays blocked but the car can back away
+    assert_eq!(client.send(CarCommand::Forward(50)), ServerMessage::Ack);
+    sleep(Duration::from_millis(50));
+    assert_eq!(client.telemetry().motor_left, 0);
+    assert_eq!(client.send(CarCommand::Backward(50)), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left == -50);
+}
+
+#[test]
+fn commands_need_the_key_when_one_is_set() {
+    let sim = Sim::start(&["--key", "open sesame"]);
+
+    // The simulator serves one connection at a time, like the car
+    {
+        let mut client = sim.connect();
+        assert_eq!(
+            client.send(CarCommand::Forward(50)),
+            ServerMessage::Error(ErrorCode::Unauthorized)
+        );
+    }
+    {
+        let mut client = sim.connect();
+        assert_eq!(
+            client.authenticate("wrong"),
+            ServerMessage::Error(ErrorCode::AuthFailed)
+        );
+    }
+
+    let mut client = sim.connect();
+    assert_eq!(client.authenticate("open sesame"), ServerMessage::Authenticated);
+    assert_eq!(client.send(CarCommand::Forward(50)), ServerMessage::Ack);
+    client.wait_for(|telemetry| telemetry.motor_left == 50);
+}
+
+#[test]
+fn udp_setpoints_drive_and_late_ones_are_dropped() {
+    let sim = Sim::start(&["--key", "open sesame"]);
+    let mut client = sim.connect();
+    assert_eq!(client.authenticate("open sesame"), ServerMessage::Authenticated);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let send = |setpoint: &drive::Setpoint| {
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let n = drive::encode(setpoint, &mut buf).unwrap();
+        socket.send_to(&buf[..n], ("127.0.0.1", sim.port)).unwrap();
+    };
+
+    // Setpoints need the session's drive key
+    send(&Sender::new(None).setpoint(50, 0));
+    sleep(Duration::from_millis(50));
+    assert_eq!(client.telemetry().motor_left, 0);
+
+    let mut sender = Sender::new(Some(client.session.as_ref().unwrap().drive_key()));
+    let forward = sender.setpoint(50, 0);
+    send(&forward);
+    client.wait_for(|telemetry| telemetry.motor_left == 50 && telemetry.motor_right == 50);
+
+    // A forward arriving after the stop that followed it doesn't move the car again
+    send(&sender.setpoint(0, 0));
+    send(&forward);
+    client.wait_for(|telemetry| telemetry.motor_left == 0);
+    sleep(Duration::from_millis(50));
+    assert_eq!(client.telemetry().motor_left, 0);
+}
+
+#[test]
+fn browsers_get_the_page_and_drive_over_a_websocket() {
+    let sim = Sim::start(&[]);
+
+    let mut page = TcpStream::connect(("127.0.0.1", sim.http_port)).unwrap();
+    page.write_all(b"GET / HTTP/1.1\r\nHost: car\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    page.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(crusty_core::web::PAGE));
+
+    let mut stream = TcpStream::connect(("127.0.0.1", sim.http_port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    stream
+        .write_all(b"GET /ws HTTP/1.1\r\nHost: car\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+    // Each message carries command port frames, the replies come back the same way
+    let mut replies = stream.try_clone().unwrap();
+    let mut request = |command| {
+        let mut buf = [0; frame::HEADER_LEN + frame::MAX_FRAME_LEN];
+        let n = frame::encode(&ClientMessage::Command(command), &mut buf).unwrap();
+        let mask = [1, 2, 3, 4];
+        let mut message = vec![0x82, 0x80 | n as u8];
+        message.extend_from_slice(&mask);
+        message.extend(buf[..n].iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        stream.write_all(&message).unwrap();
+    };
+    let mut reply = || {
+        let mut header = [0; 2];
+        replies.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x82);
+        let mut payload = vec![0; header[1] as usize];
+        replies.read_exact(&mut payload).unwrap();
+        frame::decode::<ServerMessage>(&payload[frame::HEADER_LEN..]).unwrap()
+    };
+
+    assert_eq!(reply(), ServerMessage::Hello { challenge: None });
+    request(CarCommand::Drive { throttle: 50, steer: 0 });
+    assert_eq!(reply(), ServerMessage::Ack);
+    request(CarCommand::GetTelemetry);
+    assert!(matches!(reply(), ServerMessage::Telemetry(_)));
+}
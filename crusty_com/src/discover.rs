@@ -0,0 +1,30 @@
+//! Finds cars by trying the command port on every address of a /24 network
+//!
+//! An address counts as a car when it greets the connection like one, so other services
+//! listening on the same port aren't reported.
+
+use std::time::Duration;
+
+use crusty_client::CarClient;
+use tokio::task::JoinSet;
+
+pub async fn scan(subnet: &str, port: u16, timeout: Duration) {
+    let mut probes = JoinSet::new();
+    for host in 1..=254u8 {
+        let address = format!("{subnet}.{host}");
+        probes.spawn(async move {
+            let requires_key = CarClient::probe(&address, port, timeout).await.ok()?;
+            Some((host, address, requires_key))
+        });
+    }
+
+    let mut found: Vec<_> = probes.join_all().await.into_iter().flatten().collect();
+    found.sort_by_key(|(host, ..)| *host);
+
+    if found.is_empty() {
+        eprintln!("No car found on {subnet}.0/24 port {port}");
+    }
+    for (_, address, requires_key) in found {
+        println!("{address}\t{}", if requires_key { "key required" } else { "open" });
+    }
+}
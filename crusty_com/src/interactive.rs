@@ -0,0 +1,111 @@
+//! Keyboard driving from the terminal
+//!
+//! Terminals only report key presses, not releases, so the arrows (or WASD) step a latched
+//! throttle and steering rather than being held. While the car moves the drive command is
+//! resent every [`REFRESH`], which keeps it going when the car has a deadman configured.
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use crusty_client::CarClient;
+use shared::Melody;
+use tokio::sync::mpsc;
+
+/// How often the drive command is repeated while moving
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Throttle and steering change per key press
+const STEP: i8 = 10;
+
+const HELP: &str = "arrows/WASD drive, space stops, c centres the steering, h horn, q quits";
+
+// Puts the terminal back however the session ends
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        println!();
+    }
+}
+
+pub async fn run(car: &CarClient) -> crusty_client::Result<()> {
+    println!("{HELP}");
+    let _raw = RawMode::enable()?;
+
+    // crossterm reads the terminal with blocking calls, keep them off the runtime
+    let (keys, mut key_events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event
+                && key.kind != KeyEventKind::Release
+                && keys.send(key).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let (mut throttle, mut steer) = (0i8, 0i8);
+    let mut refresh = tokio::time::interval(REFRESH);
+    let result = loop {
+        tokio::select! {
+            Some(key) = key_events.recv() => {
+                let step = |value: i8, delta: i8| value.saturating_add(delta).clamp(-100, 100);
+                match key {
+                    KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, .. } => break Ok(()),
+                    KeyEvent { code, .. } => match code {
+                        KeyCode::Up | KeyCode::Char('w') => throttle = step(throttle, STEP),
+                        KeyCode::Down | KeyCode::Char('s') => throttle = step(throttle, -STEP),
+                        KeyCode::Left | KeyCode::Char('a') => steer = step(steer, -STEP),
+                        KeyCode::Right | KeyCode::Char('d') => steer = step(steer, STEP),
+                        KeyCode::Char('c') => steer = 0,
+                        KeyCode::Char(' ') => {
+                            (throttle, steer) = (0, 0);
+                            if let Err(err) = car.stop().await {
+                                break Err(err);
+                            }
+                            print!("\rthrottle {throttle:4}  steer {steer:4} ");
+                            let _ = io::stdout().flush();
+                            continue;
+                        }
+                        KeyCode::Char('h') => {
+                            if let Err(err) = car.play_melody(Melody::Horn).await {
+                                break Err(err);
+                            }
+                            continue;
+                        }
+                        KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                        _ => continue,
+                    },
+                }
+                // Also sent when back at zero, which brings the car to a halt
+                if let Err(err) = car.drive(throttle, steer).await {
+                    break Err(err);
+                }
+                print!("\rthrottle {throttle:4}  steer {steer:4} ");
+                let _ = io::stdout().flush();
+            }
+            _ = refresh.tick() => {
+                if (throttle, steer) != (0, 0)
+                    && let Err(err) = car.drive(throttle, steer).await
+                {
+                    break Err(err);
+                }
+            }
+        }
+    };
+
+    // Never leave the car running when the session ends
+    let stopped = car.stop().await;
+    result.and(stopped)
+}
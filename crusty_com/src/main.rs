@@ -1,66 +1,484 @@
-use std::io::{self, BufRead, Read, Write};
-use std::net::TcpStream;
-use std::str;
-
-fn main() -> io::Result<()> {
-    // Default connection parameters
-    let host = "192.168.0.177";
-    let port = 1234;
-
-    println!("Attempting to connect to {}:{}", host, port);
-
-    // Attempt to connect to the TCP server
-    match TcpStream::connect(format!("{}:{}", host, port)) {
-        Ok(mut stream) => {
-            println!("Successfully connected to server");
-
-            // Set up a reader for user input
-            let stdin = io::stdin();
-            let mut reader = stdin.lock();
-
-            loop {
-                println!("Enter message to send (or 'quit' to exit):");
-
-                // Read input from the user
-                let mut message = String::new();
-                reader.read_line(&mut message)?;
-
-                // Trim whitespace and check if user wants to quit
-                let message = message.trim();
-                if message.eq_ignore_ascii_case("quit") {
-                    println!("Exiting...");
-                    break;
-                }
-
-                // Send the message to the server
-                stream.write_all(message.as_bytes())?;
-                println!("Message sent: {}", message);
-
-                // Read response from server
-                let mut response = vec![0; 1024];
-                match stream.read(&mut response) {
-                    Ok(n) => {
-                        if n == 0 {
-                            println!("Server closed the connection");
-                            break;
-                        }
-
-                        let response_text =
-                            str::from_utf8(&response[0..n]).unwrap_or("Invalid UTF-8");
-                        println!("Server response: {}", response_text);
-                    }
-                    Err(e) => {
-                        println!("Failed to receive response: {}", e);
-                        break;
-                    }
-                }
-            }
+//! Command line client for the car
+//!
+//!     crusty_com [--host HOST] [--port PORT] [--key PASSPHRASE] [--udp] <COMMAND>
+//!
+//! Every command opens its own connection, `--record` writes what it sent and received to a
+//! session file for `replay` and `export`. `--udp` sends drive setpoints as datagrams. The exit code tells scripts what went wrong: 2 for
+//! bad usage, 3 when the car can't be reached, 4 when authentication fails, 5 when the car
+//! rejects the command and 6 when it replies with something that doesn't make sense.
+
+mod discover;
+mod interactive;
+mod mission;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use crusty_client::event_log;
+use crusty_client::session::{self, Recorder};
+use crusty_client::{CarClient, Error, Timeouts, Transport};
+use futures_util::StreamExt;
+use shared::config::{Chassis, Profile, PwmSettings, Settings};
+use shared::mission::{self as missions, MISSION_LEN, MissionStep};
+use shared::{COMMAND_PORT, DriveMode, ErrorCode, LightTarget, Melody, Telemetry};
+
+#[derive(Parser)]
+#[command(version, about = "Drive and configure the car from the command line")]
+struct Cli {
+    /// Address of the car
+    #[arg(long, env = "CRUSTY_HOST", default_value = "192.168.0.2", global = true)]
+    host: String,
+
+    /// Command port of the car
+    #[arg(long, default_value_t = COMMAND_PORT, global = true)]
+    port: u16,
+
+    /// Passphrase of the car's pre-shared key
+    #[arg(long, env = "CRUSTY_KEY", global = true, hide_env_values = true)]
+    key: Option<String>,
+
+    /// How long to wait for the car to connect and to answer each request, in ms
+    #[arg(long, default_value_t = 2000, global = true)]
+    timeout: u64,
+
+    /// Send drive setpoints over UDP, where a late one can't hold up a newer one
+    #[arg(long, global = true)]
+    udp: bool,
+
+    /// Record the commands sent and the telemetry received to this session file
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Drive forward at a speed from 0 to 100
+    Forward {
+        #[arg(value_parser = speed_range())]
+        speed: u8,
+    },
+    /// Drive backward at a speed from 0 to 100
+    Backward {
+        #[arg(value_parser = speed_range())]
+        speed: u8,
+    },
+    /// Turn left on the spot at a speed from 0 to 100
+    Left {
+        #[arg(value_parser = speed_range())]
+        speed: u8,
+    },
+    /// Turn right on the spot at a speed from 0 to 100
+    Right {
+        #[arg(value_parser = speed_range())]
+        speed: u8,
+    },
+    /// Stop the car
+    Stop,
+    /// Drive with a throttle and steering, both from -100 to 100
+    Drive {
+        #[arg(long, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-100..=100))]
+        throttle: i8,
+        /// Positive steers right
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-100..=100))]
+        steer: i8,
+    },
+    /// Drive forward, sideways and turning at once, from -100 to 100 each
+    ///
+    /// Cars with mecanum wheels strafe, the others ignore the sideways speed.
+    Move {
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-100..=100))]
+        forward: i8,
+        /// Positive moves right
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-100..=100))]
+        sideways: i8,
+        /// Positive turns clockwise
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-100..=100))]
+        turn: i8,
+    },
+    /// Turn on the spot by an angle measured with the car's IMU, positive turns right
+    TurnBy {
+        #[arg(allow_negative_numbers = true)]
+        degrees: i16,
+    },
+    /// Sound the horn
+    Horn,
+    /// Switch the drive mode
+    Mode { mode: Mode },
+    /// Check the connection and show a telemetry snapshot
+    Status,
+    /// Show the car's telemetry
+    Telemetry {
+        /// Keep printing samples until interrupted
+        #[arg(long, short)]
+        follow: bool,
+        /// Time between samples when following, in ms
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+    },
+    /// Show the car's event log: boots, connections, failsafe stops and errors
+    Log {
+        /// Print the entries as CSV
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Show or switch the driving profile, which limits speed, acceleration, turning and the obstacle stop
+    ///
+    /// --unlock is applied before switching and --lock after it.
+    Profile {
+        profile: Option<ProfileArg>,
+        /// Lock the profile with the admin passphrase, it can't be switched until unlocked. Only a car with a key can be locked
+        #[arg(long, requires = "admin", conflicts_with = "unlock")]
+        lock: bool,
+        /// Unlock the profile with the admin passphrase it was locked with
+        #[arg(long, requires = "admin")]
+        unlock: bool,
+        /// Admin passphrase for --lock and --unlock
+        #[arg(long, env = "CRUSTY_ADMIN_KEY", hide_env_values = true)]
+        admin: Option<String>,
+    },
+    /// Look for cars on the local network
+    Discover {
+        /// First three octets of the /24 network to scan
+        #[arg(long, default_value = "192.168.0")]
+        subnet: String,
+    },
+    /// Read or change the car's configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Drive with the keyboard
+    Interactive,
+    /// Have the car run a sequence of timed steps and show its progress
+    ///
+    /// Steps are drive:THROTTLE,STEER,MS turn:SPEED,MS wait:MS beep:FREQ,MS and lights:R,G,B,
+    /// turn speeds are positive to the right.
+    Mission {
+        #[arg(required = true, value_parser = mission::parse_step)]
+        steps: Vec<MissionStep>,
+        /// Start the mission and return without following it
+        #[arg(long)]
+        detach: bool,
+    },
+    /// Send the driving commands of a recorded session to the car again
+    Replay {
+        session: PathBuf,
+        /// How much faster than recorded to replay, 0.5 is half speed
+        #[arg(long, default_value_t = 1.0)]
+        speed: f32,
+    },
+    /// Write the telemetry of a recorded session as CSV
+    Export {
+        session: PathBuf,
+        /// CSV file to write, standard output when left out
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn speed_range() -> clap::builder::RangedI64ValueParser<u8> {
+    clap::value_parser!(u8).range(0..=100)
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Show the car's settings
+    Get,
+    /// Change one setting
+    #[command(subcommand)]
+    Set(ConfigSet),
+}
+
+#[derive(Subcommand)]
+enum ConfigSet {
+    /// Stop network driven motion not refreshed within this time, 0 disables
+    DeadmanMs { ms: u16 },
+    /// Replace the pre-shared key, an empty passphrase disables authentication
+    Key { passphrase: String },
+    /// Which wheels the car has, mecanum wheels can strafe
+    Chassis { chassis: ChassisArg },
+    /// Motor PWM frequency, 20000 and up is silent; taken on the next boot
+    Pwm {
+        frequency_hz: u32,
+        /// Centre the pulses, at half the duty cycle resolution
+        #[arg(long)]
+        phase_correct: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ChassisArg {
+    Tank,
+    Mecanum,
+}
+
+impl From<ChassisArg> for Chassis {
+    fn from(chassis: ChassisArg) -> Self {
+        match chassis {
+            ChassisArg::Tank => Chassis::Tank,
+            ChassisArg::Mecanum => Chassis::Mecanum,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProfileArg {
+    Kid,
+    Indoor,
+    Race,
+}
+
+impl From<ProfileArg> for Profile {
+    fn from(profile: ProfileArg) -> Self {
+        match profile {
+            ProfileArg::Kid => Profile::Kid,
+            ProfileArg::Indoor => Profile::Indoor,
+            ProfileArg::Race => Profile::Race,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Manual,
+    FollowLight,
+    AvoidLight,
+}
+
+impl From<Mode> for DriveMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Manual => DriveMode::Manual,
+            Mode::FollowLight => DriveMode::LightFollow(LightTarget::Brighter),
+            Mode::AvoidLight => DriveMode::LightFollow(LightTarget::Darker),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
 
+async fn run(cli: Cli) -> crusty_client::Result<()> {
+    if let Command::Discover { subnet } = &cli.command {
+        discover::scan(subnet, cli.port, Duration::from_millis(cli.timeout)).await;
+        return Ok(());
+    }
+    if let Command::Export { session, output } = &cli.command {
+        return export(session, output.as_deref());
+    }
+
+    let timeouts = Timeouts {
+        connect: Duration::from_millis(cli.timeout),
+        request: Duration::from_millis(cli.timeout),
+    };
+    let connected = Instant::now();
+    let transport = if cli.udp { Transport::Udp } else { Transport::Tcp };
+    let car = CarClient::connect_over(&cli.host, cli.port, cli.key.as_deref(), timeouts, transport).await?;
+    if let Some(path) = &cli.record {
+        car.start_recording(Recorder::create(path)?);
+    }
+
+    let result = match cli.command {
+        Command::Forward { speed } => car.go_forward(speed).await,
+        Command::Backward { speed } => car.go_backward(speed).await,
+        Command::Left { speed } => car.turn_left(speed).await,
+        Command::Right { speed } => car.turn_right(speed).await,
+        Command::Stop => car.stop().await,
+        Command::Drive { throttle, steer } => car.drive(throttle, steer).await,
+        Command::Move { forward, sideways, turn } => car.holonomic(forward, sideways, turn).await,
+        Command::TurnBy { degrees } => car.turn_by(degrees).await,
+        Command::Horn => car.play_melody(Melody::Horn).await,
+        Command::Mode { mode } => car.set_mode(mode.into()).await,
+        Command::Status => {
+            let connect_time = connected.elapsed();
+            let sent = Instant::now();
+            let telemetry = car.telemetry().await?;
+            let round_trip = sent.elapsed();
+
+            println!("car        {}:{}", cli.host, cli.port);
+            println!("auth       {}", if car.is_authenticated() { "key required, authenticated" } else { "open" });
+            println!("connect    {} ms", connect_time.as_millis());
+            println!("round trip {} ms", round_trip.as_millis());
+            println!("{}", describe(&telemetry));
+            Ok(())
+        }
+        Command::Telemetry { follow, interval } => {
+            if !follow {
+                println!("{}", describe(&car.telemetry().await?));
+                return Ok(());
+            }
+            let mut samples = pin!(car.subscribe(Duration::from_millis(interval.max(10))));
+            while let Some(sample) = samples.next().await {
+                println!("{}", describe(&sample?));
+            }
+            Ok(())
+        }
+        Command::Log { csv } => {
+            let entries = car.event_log().await?;
+            if csv {
+                event_log::write_csv(&entries, io::stdout().lock())?;
+                return Ok(());
+            }
+            for entry in &entries {
+                let uptime = Duration::from_millis(entry.uptime_ms.into());
+                println!("#{} boot {} +{:.3} s {}", entry.seq, entry.boot, uptime.as_secs_f32(), event_log::describe(&entry.event));
+            }
+            Ok(())
+        }
+        Command::Profile { profile, lock, unlock, admin } => {
+            let admin = admin.unwrap_or_default();
+            if unlock {
+                car.lock_profile(&admin, false).await?;
+            }
+            if let Some(profile) = profile {
+                car.set_profile(profile.into()).await?;
+            }
+            if lock {
+                car.lock_profile(&admin, true).await?;
+            }
+            let telemetry = car.telemetry().await?;
+            let limits = telemetry.profile.limits();
+            println!("profile {}{}", profile_name(telemetry.profile), if telemetry.profile_locked { " (locked)" } else { "" });
+            println!("max speed {}%", limits.max_speed);
+            println!("acceleration {}%/s", limits.accel_per_s);
+            println!("max turn {}%", limits.max_turn);
+            println!("stop distance {} cm", limits.stop_distance_cm);
+            Ok(())
+        }
+        Command::Config(ConfigCommand::Get) => {
+            print_settings(&car.settings().await?);
             Ok(())
         }
-        Err(e) => {
-            println!("Failed to connect: {}", e);
-            Err(e)
+        Command::Config(ConfigCommand::Set(ConfigSet::DeadmanMs { ms })) => {
+            let settings = Settings {
+                deadman_ms: ms,
+                ..car.settings().await?
+            };
+            car.set_settings(settings).await
         }
+        Command::Config(ConfigCommand::Set(ConfigSet::Chassis { chassis })) => {
+            let settings = Settings {
+                chassis: chassis.into(),
+                ..car.settings().await?
+            };
+            car.set_settings(settings).await
+        }
+        Command::Config(ConfigCommand::Set(ConfigSet::Pwm { frequency_hz, phase_correct })) => {
+            let settings = Settings {
+                pwm: PwmSettings { frequency_hz, phase_correct },
+                ..car.settings().await?
+            };
+            car.set_settings(settings).await
+        }
+        Command::Config(ConfigCommand::Set(ConfigSet::Key { passphrase })) => car.set_key(&passphrase).await,
+        Command::Interactive => interactive::run(&car).await,
+        Command::Mission { steps, detach } => {
+            let Some(mission) = missions::mission(&steps) else {
+                eprintln!("A mission has at most {MISSION_LEN} steps");
+                return Err(Error::Rejected(ErrorCode::Malformed));
+            };
+            car.run_mission(mission).await?;
+            if detach {
+                return Ok(());
+            }
+            mission::follow(&car, &steps).await
+        }
+        Command::Replay { session, speed } => car.replay(&session::load(session)?, speed).await,
+        Command::Discover { .. } | Command::Export { .. } => unreachable!(),
+    };
+
+    if let Some(recorder) = car.stop_recording() {
+        recorder.finish()?;
+    }
+    result
+}
+
+fn export(session: &Path, output: Option<&Path>) -> crusty_client::Result<()> {
+    let records = session::load(session)?;
+    match output {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            session::write_telemetry_csv(&records, &mut out)?;
+            out.flush()?;
+        }
+        None => session::write_telemetry_csv(&records, io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn exit_code(err: &Error) -> u8 {
+    match err {
+        Error::Timeout | Error::Io(_) | Error::Closed => 3,
+        Error::KeyRequired
+        | Error::Rejected(ErrorCode::Unauthorized | ErrorCode::AuthFailed | ErrorCode::BadSignature | ErrorCode::Replay) => 4,
+        Error::Rejected(ErrorCode::Malformed) | Error::Frame(_) | Error::Unexpected(_) => 6,
+        Error::Rejected(_) => 5,
+    }
+}
+
+/// One line summary of the car's state
+fn describe(telemetry: &Telemetry) -> String {
+    let mode = match telemetry.mode {
+        DriveMode::Manual => "manual",
+        DriveMode::LightFollow(LightTarget::Brighter) => "follow-light",
+        DriveMode::LightFollow(LightTarget::Darker) => "avoid-light",
+    };
+    let distance = match telemetry.distance_cm {
+        Some(cm) => format!("{cm} cm"),
+        None => "-".into(),
+    };
+    let heading = match telemetry.imu {
+        Some(imu) => format!("{:.1}°", imu.heading_deg),
+        None => "-".into(),
+    };
+    format!(
+        "mode {mode} | profile {} | motors {}/{} | heading {heading} | obstacle {distance} | light {}/{} | loop {} us",
+        profile_name(telemetry.profile),
+        telemetry.motor_left,
+        telemetry.motor_right,
+        telemetry.light_left,
+        telemetry.light_right,
+        telemetry.loop_latency_us
+    )
+}
+
+fn profile_name(profile: Profile) -> &'static str {
+    match profile {
+        Profile::Kid => "kid",
+        Profile::Indoor => "indoor",
+        Profile::Race => "race",
+    }
+}
+
+fn print_settings(settings: &Settings) {
+    println!("deadman-ms {}", settings.deadman_ms);
+    let chassis = match settings.chassis {
+        Chassis::Tank => "tank",
+        Chassis::Mecanum => "mecanum",
+    };
+    println!("chassis {chassis}");
+    println!(
+        "pwm {} Hz{}",
+        settings.pwm.frequency_hz,
+        if settings.pwm.phase_correct { " phase-correct" } else { "" }
+    );
+    println!("ir keymap");
+    for binding in settings.ir_keymap.iter().flatten() {
+        println!("  {:#04x} {:?}", binding.command, binding.action);
     }
 }
@@ -0,0 +1,115 @@
+//! The `mission` command: steps given on the command line, run by the car with its own timing
+//!
+//! Each step is a name and its numbers separated by commas:
+//!
+//!     drive:THROTTLE,STEER,MS  turn:SPEED,MS  wait:MS  beep:FREQ,MS  lights:R,G,B
+//!
+//! so a square is `drive:50,0,1000 turn:50,400` four times over.
+
+use std::time::{Duration, Instant};
+
+use crusty_client::{CarClient, Result};
+use shared::mission::{MissionStatus, MissionStep};
+
+/// How often the progress is checked while following the mission, which also keeps a deadman happy
+const POLL_PERIOD: Duration = Duration::from_millis(100);
+
+/// How long the telemetry may still show how the previous mission ended
+const START_GRACE: Duration = Duration::from_millis(500);
+
+/// Parse one step, for clap
+pub fn parse_step(step: &str) -> core::result::Result<MissionStep, String> {
+    let (name, args) = step.split_once(':').unwrap_or((step, ""));
+    let numbers: Vec<i64> = args
+        .split(',')
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| arg.trim().parse().map_err(|_| format!("`{arg}` is not a number")))
+        .collect::<core::result::Result<_, _>>()?;
+
+    let number = |index: usize, min: i64, max: i64| {
+        let value = numbers[index];
+        (min..=max)
+            .contains(&value)
+            .then_some(value)
+            .ok_or(format!("{value} is not within {min}..={max} in `{step}`"))
+    };
+    let expect = |count: usize, usage: &str| {
+        if numbers.len() == count {
+            Ok(())
+        } else {
+            Err(format!("expected {name}:{usage}, got `{step}`"))
+        }
+    };
+    let speed = |index| number(index, -100, 100).map(|value| value as i8);
+    let ms = |index| number(index, 0, u16::MAX as i64).map(|value| value as u16);
+    let byte = |index| number(index, 0, 255).map(|value| value as u8);
+
+    match name {
+        "drive" => {
+            expect(3, "THROTTLE,STEER,MS")?;
+            Ok(MissionStep::Drive {
+                throttle: speed(0)?,
+                steer: speed(1)?,
+                ms: ms(2)?,
+            })
+        }
+        "turn" => {
+            expect(2, "SPEED,MS")?;
+            Ok(MissionStep::Turn {
+                speed: speed(0)?,
+                ms: ms(1)?,
+            })
+        }
+        "wait" => {
+            expect(1, "MS")?;
+            Ok(MissionStep::Wait { ms: ms(0)? })
+        }
+        "beep" => {
+            expect(2, "FREQ,MS")?;
+            Ok(MissionStep::Beep {
+                freq: ms(0)?,
+                ms: ms(1)?,
+            })
+        }
+        "lights" => {
+            expect(3, "R,G,B")?;
+            Ok(MissionStep::Lights {
+                r: byte(0)?,
+                g: byte(1)?,
+                b: byte(2)?,
+            })
+        }
+        other => Err(format!("unknown step `{other}`, expected drive, turn, wait, beep or lights")),
+    }
+}
+
+/// Print the progress of the mission the car is running until it ends
+pub async fn follow(car: &CarClient, steps: &[MissionStep]) -> Result<()> {
+    let started = Instant::now();
+    let mut shown = None;
+    loop {
+        let status = car.telemetry().await?.mission;
+        let ended = matches!(status, MissionStatus::Done | MissionStatus::Aborted);
+        // The car picks the mission up on its next control tick
+        let current = shown.is_some() || !ended || started.elapsed() > START_GRACE;
+
+        if current && shown != Some(status) {
+            match status {
+                MissionStatus::Running { step } => {
+                    let index = step as usize;
+                    if let Some(step) = steps.get(index) {
+                        println!("step {}/{} {:?}", index + 1, steps.len(), step);
+                    }
+                }
+                MissionStatus::Done => println!("mission done"),
+                MissionStatus::Aborted => println!("mission aborted"),
+                MissionStatus::Idle => {}
+            }
+            shown = Some(status);
+        }
+        if current && ended {
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_PERIOD).await;
+    }
+}
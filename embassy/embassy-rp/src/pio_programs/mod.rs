@@ -2,6 +2,7 @@
 
 pub mod hd44780;
 pub mod i2s;
+pub mod nec;
 pub mod onewire;
 pub mod pwm;
 pub mod rotary_encoder;
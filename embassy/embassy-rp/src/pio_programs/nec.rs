@@ -0,0 +1,141 @@
+//! PIO backed receiver for the NEC infrared remote control protocol
+//!
+//! Expects a demodulating IR receiver (e.g. VS1838B) whose output idles high and is pulled low
+//! while a 38 kHz burst is received. NEC timing is built from a 562.5us burst period: a frame is
+//! a 9ms sync burst, a 4.5ms gap and 32 data bits, where a `0` is a burst followed by a short gap
+//! and a `1` a burst followed by a long gap. A held key repeats as a sync burst, a 2.25ms gap and
+//! a single burst.
+
+use fixed::traits::ToFixed;
+
+use crate::clocks::clk_sys_freq;
+use crate::gpio::Pull;
+use crate::pio::{
+    Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection, StateMachine,
+};
+use crate::Peri;
+
+/// Word pushed by the program when it detects a repeat code, never a valid frame
+const REPEAT_MARKER: u32 = 0xFFFF_FFFF;
+
+/// This struct represents a NEC receiver program loaded into pio instruction memory.
+pub struct PioNecProgram<'a, PIO: Instance> {
+    prg: LoadedProgram<'a, PIO>,
+}
+
+impl<'a, PIO: Instance> PioNecProgram<'a, PIO> {
+    /// Load the program into the given pio
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        let prg = pio::pio_asm!(
+            r#"
+                ; The state machine runs at 10 cycles per 562.5us burst period
+
+                .define BURST_LOOP_COUNTER 30           ; bursts longer than this are a sync burst
+                .define BIT_SAMPLE_DELAY 15             ; sample 1.5 burst periods after a data burst ends
+                .define GAP_LOOP_COUNTER 20             ; 3 cycles per loop, so 6 bursts tell a repeat from a frame gap
+
+                .wrap_target
+                next_burst:
+                    set x, BURST_LOOP_COUNTER
+                    wait 0 pin 0                        ; wait for the next burst to start
+
+                burst_loop:
+                    jmp pin data_bit                    ; the burst ended before the counter expired
+                    jmp x-- burst_loop                  ; wait for the burst to end
+
+                                                        ; the counter expired, this is a sync burst
+                    mov isr, null                       ; reset the input shift register
+                    wait 1 pin 0                        ; wait for the sync burst to finish
+                    set x, GAP_LOOP_COUNTER
+
+                gap_loop:
+                    jmp pin gap_high                    ; still in the gap after the sync burst
+                    mov isr, ~null                      ; the gap was short, this is a repeat code
+                    push noblock
+                    jmp next_burst
+
+                gap_high:
+                    jmp x-- gap_loop [1]
+                    jmp next_burst                      ; long gap, data bits follow
+
+                data_bit:
+                    nop [BIT_SAMPLE_DELAY - 1]
+                    in pins, 1                          ; a new burst (low) means a short gap, so a 0 bit
+                                                        ; after 32 bits the ISR is pushed to the RX FIFO
+                .wrap
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// A decoded NEC message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NecEvent {
+    /// A complete frame
+    Frame {
+        /// Address of the remote, the inverted copy has already been checked
+        address: u8,
+        /// Key code, the inverted copy has already been checked
+        command: u8,
+    },
+    /// The last key is still held down
+    Repeat,
+}
+
+/// Pio backed NEC infrared receiver
+pub struct PioNec<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioNec<'d, PIO, SM> {
+    /// Configure a state machine with the loaded [PioNecProgram]
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        pin: Peri<'d, impl PioPin>,
+        program: &PioNecProgram<'d, PIO>,
+    ) -> Self {
+        let mut pin = common.make_pio_pin(pin);
+        pin.set_pull(Pull::Up);
+        sm.set_pin_dirs(Direction::In, &[&pin]);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+        cfg.set_in_pins(&[&pin]);
+        cfg.set_jmp_pin(&pin);
+        cfg.fifo_join = FifoJoin::RxOnly;
+        cfg.shift_in = ShiftConfig {
+            auto_fill: true,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+
+        // 10 cycles per 562.5us
+        let divider = (clk_sys_freq() as u64 * 5625 / 100_000_000) as u32;
+        cfg.clock_divider = divider.to_fixed();
+
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+        Self { sm }
+    }
+
+    /// Wait for the next frame or repeat code, frames that fail the inverted copy check are skipped
+    pub async fn read(&mut self) -> NecEvent {
+        loop {
+            let word = self.sm.rx().wait_pull().await;
+            if word == REPEAT_MARKER {
+                return NecEvent::Repeat;
+            }
+
+            let [address, address_inv, command, command_inv] = word.to_le_bytes();
+            if address == !address_inv && command == !command_inv {
+                return NecEvent::Frame { address, command };
+            }
+        }
+    }
+}
@@ -1,12 +1,11 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! This build script copies the memory layout of the chip being built for,
+//! `memory-rp2040.x` or `memory-rp235x.x` from the crate root, into a
+//! directory where the linker finds it as `memory.x`. There is no
+//! `memory.x` in the crate root, as the linker would pick it up from there
+//! before the output directory whichever chip the board profile is on.
+//! Additionally, by requesting that Cargo re-run the build script whenever
+//! a layout is changed, updating it ensures a rebuild of the application
+//! with the new memory settings.
 
 use std::env;
 use std::fs::File;
@@ -14,23 +13,33 @@ use std::io::Write;
 use std::path::PathBuf;
 
 fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // Put the layout in our output directory as `memory.x` and ensure
+    // it's on the linker search path. Only the Pico 2 W board profile
+    // is on an RP235x.
+    let rp235x = env::var_os("CARGO_FEATURE_BOARD_PICO2W").is_some();
+    let memory: &[u8] = if rp235x {
+        include_bytes!("memory-rp235x.x")
+    } else {
+        include_bytes!("memory-rp2040.x")
+    };
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
+    // any file in the project changes. By specifying the layouts
     // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // one of them is changed.
+    println!("cargo:rerun-if-changed=memory-rp2040.x");
+    println!("cargo:rerun-if-changed=memory-rp235x.x");
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    if !rp235x {
+        println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    }
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
@@ -0,0 +1,119 @@
+//! Battery voltage on VSYS, for the low battery warning
+//!
+//! The Pico W and the Pico 2 W read VSYS through a divider by 3 on GPIO29 (ADC3). That pin is
+//! also the clock of the cyw43's SPI bus, and the divider only reads VSYS while the cyw43's chip
+//! select is high, so like pico-sdk the pin is taken from the cyw43 in between two of its
+//! transactions: [`WifiSpi`] holds [`BUS`] for each transaction and [`read_vsys_mv`] holds it while
+//! the ADC samples the pin. The light task owns the ADC, so it takes the readings.
+
+use crusty_core::battery::BatteryMonitor;
+use cyw43::SpiBusCyw43;
+use embassy_rp::adc::{Adc, Async, Channel};
+use embassy_rp::gpio::Pull;
+use embassy_rp::pac;
+use embassy_rp::peripherals::PIN_29;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use shared::event_log::Event;
+use shared::Melody;
+
+use crate::buzzer::{self, Sound};
+use crate::event_log;
+
+/// How often the battery is read
+pub const CHECK_PERIOD: Duration = Duration::from_secs(5);
+
+/// VSYS the battery counts as low below, the car's regulator can't hold it up once the batteries
+/// run down
+pub const LOW_MV: u16 = 4500;
+
+/// GPIO and ADC input VSYS is read on
+const VSYS_PIN: usize = 29;
+/// Function select of a GPIO driven by no peripheral
+const FUNCSEL_NULL: u8 = 0x1f;
+
+/// The cyw43's SPI bus, held by whoever is using its clock pin
+pub static BUS: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// The cyw43's SPI bus, giving up its clock pin to [`read_vsys_mv`] in between transactions
+pub struct WifiSpi<S>(pub S);
+
+impl<S: SpiBusCyw43> SpiBusCyw43 for WifiSpi<S> {
+    async fn cmd_write(&mut self, write: &[u32]) -> u32 {
+        let _bus = BUS.lock().await;
+        self.0.cmd_write(write).await
+    }
+
+    async fn cmd_read(&mut self, write: u32, read: &mut [u32]) -> u32 {
+        let _bus = BUS.lock().await;
+        self.0.cmd_read(write, read).await
+    }
+
+    async fn wait_for_event(&mut self) {
+        self.0.wait_for_event().await
+    }
+}
+
+/// Read VSYS in mV, borrowing the clock pin from the cyw43
+pub async fn read_vsys_mv(adc: &mut Adc<'_, Async>) -> Option<u16> {
+    let _bus = BUS.lock().await;
+    let pad = pac::PADS_BANK0.gpio(VSYS_PIN);
+    let ctrl = pac::IO_BANK0.gpio(VSYS_PIN).ctrl();
+    let (pad_saved, ctrl_saved) = (pad.read(), ctrl.read());
+    ctrl.write(|w| w.set_funcsel(FUNCSEL_NULL));
+
+    // SAFETY: the cyw43 doesn't touch the pin while the bus is held, and gets it back below
+    let mut channel = Channel::new_pin(unsafe { PIN_29::steal() }, Pull::None);
+    let raw = adc.blocking_read(&mut channel);
+    drop(channel);
+
+    pad.write_value(pad_saved);
+    ctrl.write_value(ctrl_saved);
+    match raw {
+        // 12 bits of the 3.3 V reference, of a third of VSYS
+        Ok(raw) => Some((raw as u32 * 3 * 3300 / 4096) as u16),
+        Err(e) => {
+            defmt::warn!("battery read failed: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Reads the battery every [`CHECK_PERIOD`], once it runs low it plays [`Melody::LowBattery`] and
+/// logs [`Event::LowBattery`]
+pub struct BatteryCheck {
+    monitor: BatteryMonitor,
+    due: Instant,
+}
+
+impl BatteryCheck {
+    pub fn new() -> Self {
+        Self {
+            monitor: BatteryMonitor::new(LOW_MV),
+            due: Instant::now(),
+        }
+    }
+
+    /// Read the battery with `adc` if a reading is due
+    pub async fn run(&mut self, adc: &mut Adc<'_, Async>) {
+        if Instant::now() < self.due {
+            return;
+        }
+        self.due += CHECK_PERIOD;
+        let Some(mv) = read_vsys_mv(adc).await else {
+            return;
+        };
+        if self.monitor.update(mv) {
+            defmt::warn!("battery low, VSYS at {} mV", mv);
+            buzzer::play(Sound::Melody(Melody::LowBattery));
+            event_log::record(Event::LowBattery);
+        }
+    }
+}
+
+impl Default for BatteryCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,21 +1,34 @@
 #![no_std]
 #![no_main]
 
-use core::str::from_utf8;
+use core::cell::RefCell;
 
+use crusty_core::event_log::EventLog;
+use crusty_core::motion::deadman_lease;
+use crusty_core::pwm;
+use crusty_core::server::{self, Platform};
+use crusty_core::web::{self, HTTP_PORT};
 use cyw43::{Control, JoinOptions};
 use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
 use defmt::*;
 use defmt::{info, warn};
-use embassy_executor::Spawner;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_executor::{Executor, Spawner};
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
 use embassy_net::Ipv4Address;
 use embassy_net::Ipv4Cidr;
 use embassy_net::Stack;
-use embassy_net::{tcp::TcpSocket, StackResources};
-use embassy_rp::i2c::{Async, I2c};
-use embassy_rp::peripherals::{DMA_CH1, I2C0, I2C1, PIN_16, PIO1};
+use embassy_net::{tcp::TcpSocket, IpAddress, StackResources};
+use embassy_rp::adc::{self, Adc, Channel as AdcChannel, Config as AdcConfig};
+use embassy_rp::flash::{Blocking, Flash};
+use embassy_rp::gpio::{Input, Pull};
+use embassy_rp::multicore::{self, spawn_core1};
+use embassy_rp::peripherals::{I2C0, I2C1, PIO1};
+use embassy_rp::pio_programs::nec::{PioNec, PioNecProgram};
 use embassy_rp::pio_programs::ws2812::{PioWs2812, PioWs2812Program};
-use embassy_rp::pwm::{Config as PwmConfig, Pwm};
+use embassy_rp::pwm::{Config as PwmConfig, Pwm, PwmBatch};
+use embassy_rp::watchdog::{self, Watchdog};
 use embassy_rp::{
     bind_interrupts,
     clocks::RoscRng,
@@ -23,23 +36,52 @@ use embassy_rp::{
     peripherals::{DMA_CH0, PIO0},
     pio::{InterruptHandler, Pio},
 };
-use embassy_rp::{i2c, Peri};
+use embassy_rp::i2c;
+#[cfg(feature = "battery")]
+use embassy_rp_examples::battery::BatteryCheck;
+use embassy_rp_examples::battery::WifiSpi;
+use embassy_rp_examples::board::{self, *}; // split_resources! names every resource group
+use embassy_rp_examples::buzzer::{self, Buzzer, Note, Sound};
 use embassy_rp_examples::car::{initialize_car, Car};
+use embassy_rp_examples::config::{ConfigStore, FlashPartition, SharedFlash, FLASH_SIZE};
+use embassy_rp_examples::drive;
+use embassy_rp_examples::event_log;
+use embassy_rp_examples::imu;
+use embassy_rp_examples::ir::{self, IrRemote};
+use embassy_rp_examples::leds;
+use embassy_rp_examples::light::{self, LightSensor};
+use embassy_rp_examples::motion::{self, Arbiter, MotionRequest, Source};
+use embassy_rp_examples::obstacle::{self, Ultrasonic};
+use embassy_rp_examples::split_resources;
+use embassy_rp_examples::telemetry;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
 use embassy_time::{Duration, Ticker, Timer};
-use embedded_io_async::Write;
 use heapless::Vec;
 use ht16k33_async::HT16K33;
 use rand::RngCore;
-use shared::CarCommand;
+use shared::auth::{self, Key};
+use shared::config::{Calibration, CarConfig, DriveLimits, PwmSettings};
+use shared::drive::MAX_DATAGRAM_LEN;
+use shared::event_log::{Event, LogPage, ResetReason};
+use shared::{DriveMode, Melody, Telemetry, COMMAND_PORT};
 use smart_leds::RGB8;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+// The RP2350's bootrom only boots an image that declares itself with an IMAGE_DEF block
+#[cfg(feature = "board-pico2w")]
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: embassy_rp::block::ImageDef = embassy_rp::block::ImageDef::secure_exe();
+
 // Define interrupt handlers
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => InterruptHandler<PIO0>;
     PIO1_IRQ_0 => InterruptHandler<PIO1>;
     I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
 });
 
 // Define interrupt handlers
@@ -47,11 +89,19 @@ bind_interrupts!(struct Irqs {
 //     I2C0_IRQ => i2c::InterruptHandler<I2C0>;
 // });
 
+// The LED matrix and the IMU share I2C0, both from core 0
+type I2c0Bus = AsyncMutex<NoopRawMutex, i2c::I2c<'static, I2C0, i2c::Async>>;
+type I2c0Device = I2cDevice<'static, NoopRawMutex, i2c::I2c<'static, I2C0, i2c::Async>>;
+
+// Core 1 runs the motion control loop on its own executor
+static mut CORE1_STACK: multicore::Stack<8192> = multicore::Stack::new();
+static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
+
 const WIFI_NETWORK: &str = ""; // change to your network SSID
 const WIFI_PASSWORD: &str = ""; // change to your network password
 
 #[embassy_executor::task]
-async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, WifiSpi<PioSpi<'static, PIO0, 0, DMA_CH0>>>) -> ! {
     runner.run().await
 }
 
@@ -62,8 +112,9 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    defmt::info!("Initializing Freenove 4WD Car Control");
+    defmt::info!("Initializing car control on the {}", board::NAME);
     let p = embassy_rp::init(Default::default());
+    let r = split_resources!(p);
 
     let mut rng = RoscRng;
 
@@ -77,19 +128,24 @@ async fn main(spawner: Spawner) {
     //let fw = unsafe { core::slice::from_raw_parts(0x10100000 as *const u8, 230321) };
     //let clm = unsafe { core::slice::from_raw_parts(0x10140000 as *const u8, 4752) };
 
-    let pwr = Output::new(p.PIN_23, Level::Low);
-    let cs = Output::new(p.PIN_25, Level::High);
-    let mut pio = Pio::new(p.PIO0, Irqs);
-    let spi = PioSpi::new(
+    let pwr = Output::new(r.wifi.pwr, Level::Low);
+    let cs = Output::new(r.wifi.cs, Level::High);
+    let mut pio = Pio::new(r.wifi.pio, Irqs);
+    // The battery reading borrows the clock pin in between transactions
+    let spi = WifiSpi(PioSpi::new(
         &mut pio.common,
         pio.sm0,
         DEFAULT_CLOCK_DIVIDER,
         pio.irq0,
         cs,
-        p.PIN_24,
-        p.PIN_29,
-        p.DMA_CH0,
-    );
+        r.wifi.dio,
+        r.wifi.clk,
+        r.wifi.dma,
+    ));
+
+    // IR receiver, sharing PIO0 with the cyw43 SPI
+    let nec_program = PioNecProgram::new(&mut pio.common);
+    let nec = PioNec::new(&mut pio.common, pio.sm1, r.ir.pin, &nec_program);
 
     static STATE: StaticCell<cyw43::State> = StaticCell::new();
     let state = STATE.init(cyw43::State::new());
@@ -118,39 +174,108 @@ async fn main(spawner: Spawner) {
     let seed = rng.next_u64();
 
     // Init network stack
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
     let (stack, runner) = embassy_net::new(net_device, config, RESOURCES.init(StackResources::new()), seed);
 
-    // Configure PWM for 500Hz, matching the C++ implementation
-    let desired_freq_hz = 500;
+    // Persistent configuration: pre-shared key, deadman, IR key bindings, motor calibration and PWM
+    static FLASH: StaticCell<SharedFlash> = StaticCell::new();
+    let flash = FLASH.init(Mutex::new(RefCell::new(Flash::<_, Blocking, FLASH_SIZE>::new_blocking(r.flash.flash))));
+    let mut config_store = ConfigStore::new(flash);
+    let car_config = config_store.load();
+
+    // Event log, starting with why the car was reset
+    let event_log = event_log::open(flash);
+    let reset_reason = match Watchdog::new(p.WATCHDOG).reset_reason() {
+        None => ResetReason::PowerOn,
+        Some(watchdog::ResetReason::TimedOut) => ResetReason::Watchdog,
+        Some(watchdog::ResetReason::Forced) => ResetReason::Forced,
+    };
+    event_log::record(Event::Boot(reset_reason));
+
+    // Motor PWM at the configured frequency
     let clock_freq_hz = embassy_rp::clocks::clk_sys_freq();
-    let divider = 16u8;
-    let period = (clock_freq_hz / (desired_freq_hz * divider as u32)) as u16 - 1;
+    let pwm_settings = if pwm::timing(clock_freq_hz, car_config.pwm).is_some() {
+        car_config.pwm
+    } else {
+        warn!("PWM at {} Hz is out of reach, using the default", car_config.pwm.frequency_hz);
+        PwmSettings::new()
+    };
+    let timing = unwrap!(pwm::timing(clock_freq_hz, pwm_settings));
+    info!(
+        "PWM at {} Hz, divider {} top {}",
+        timing.frequency_hz(clock_freq_hz, pwm_settings.phase_correct),
+        timing.divider,
+        timing.top
+    );
 
+    // The slices are started together below, so the pulses of all four wheels line up
     let mut config = PwmConfig::default();
-    config.top = period;
-    config.divider = divider.into();
-
-    let pwm_fl = Pwm::new_output_ab(p.PWM_SLICE1, p.PIN_18, p.PIN_19, config.clone());
-    let pwm_fr = Pwm::new_output_ab(p.PWM_SLICE4, p.PIN_8, p.PIN_9, config.clone());
-    let pwm_rl = Pwm::new_output_ab(p.PWM_SLICE2, p.PIN_20, p.PIN_21, config.clone());
-    let pwm_rr = Pwm::new_output_ab(p.PWM_SLICE3, p.PIN_6, p.PIN_7, config.clone());
+    config.top = timing.top;
+    config.divider = timing.divider.into();
+    config.phase_correct = pwm_settings.phase_correct;
+    config.enable = false;
+
+    let motors = r.motors;
+    let pwm_fl = Pwm::new_output_ab(motors.front_left_slice, motors.front_left_a, motors.front_left_b, config.clone());
+    let pwm_fr = Pwm::new_output_ab(motors.front_right_slice, motors.front_right_a, motors.front_right_b, config.clone());
+    let pwm_rl = Pwm::new_output_ab(motors.rear_left_slice, motors.rear_left_a, motors.rear_left_b, config.clone());
+    let pwm_rr = Pwm::new_output_ab(motors.rear_right_slice, motors.rear_right_a, motors.rear_right_b, config.clone());
+    PwmBatch::set_enabled(true, |batch| {
+        for pwm in [&pwm_fl, &pwm_fr, &pwm_rl, &pwm_rr] {
+            batch.enable(pwm);
+        }
+    });
 
-    let pio = Pio::new(p.PIO1, Irqs);
+    let buzzer = Buzzer::new(Pwm::new_output_a(r.buzzer.slice, r.buzzer.pin, PwmConfig::default()));
 
-    let car = initialize_car(pwm_fl, pwm_fr, pwm_rl, pwm_rr);
+    let mut car = initialize_car(pwm_fl, pwm_fr, pwm_rl, pwm_rr);
 
-    let scl = p.PIN_5;
-    let sda = p.PIN_4;
-    let i2c = i2c::I2c::new_async(p.I2C0, scl, sda, Irqs, i2c::Config::default());
-    let mut driver = HT16K33::new(i2c, 0x71);
+    // Photoresistors
+    let adc = Adc::new(r.light.adc, Irqs, AdcConfig::default());
+    let light_sensor = LightSensor::new(
+        adc,
+        AdcChannel::new_pin(r.light.left, Pull::None),
+        AdcChannel::new_pin(r.light.right, Pull::None),
+        r.light.dma.into(),
+    );
 
-    driver.setup().await.unwrap();
+    // HC-SR04 ultrasonic sensor
+    let ultrasonic = Ultrasonic::new(Output::new(r.ultrasonic.trigger, Level::Low), Input::new(r.ultrasonic.echo, Pull::None));
+
+    ir::set_keymap(car_config.ir_keymap);
+    car.set_calibration(car_config.calibration);
+    let network_lease = deadman_lease(car_config.deadman_ms);
+    let limits = car_config.profile.limits();
+    info!("Driving with the {:?} profile", car_config.profile);
+
+    // Motors belong to core 1, core 0 only talks to them through channels
+    spawn_core1(
+        p.CORE1,
+        unsafe { &mut *core::ptr::addr_of_mut!(CORE1_STACK) },
+        move || {
+            let executor1 = EXECUTOR1.init(Executor::new());
+            executor1.run(|spawner| {
+                unwrap!(spawner.spawn(motion_task(car, Arbiter::new(network_lease), limits)));
+            });
+        },
+    );
 
-    unwrap!(spawner.spawn(led_matrix(driver)));
-    unwrap!(spawner.spawn(led_task(pio, p.DMA_CH1, p.PIN_16)));
+    unwrap!(spawner.spawn(obstacle_task(ultrasonic)));
+    unwrap!(spawner.spawn(event_log_task(event_log)));
+    unwrap!(spawner.spawn(buzzer_task(buzzer)));
+    buzzer::play(Sound::Melody(Melody::Boot));
+
+    static I2C_BUS: StaticCell<I2c0Bus> = StaticCell::new();
+    let i2c = i2c::I2c::new_async(r.i2c.i2c, r.i2c.scl, r.i2c.sda, Irqs, i2c::Config::default());
+    let i2c_bus = I2C_BUS.init(AsyncMutex::new(i2c));
+    unwrap!(spawner.spawn(led_matrix(I2cDevice::new(i2c_bus))));
+    unwrap!(spawner.spawn(imu_task(I2cDevice::new(i2c_bus))));
+    unwrap!(spawner.spawn(led_task(r.leds)));
+    unwrap!(spawner.spawn(ir_task(nec)));
+    unwrap!(spawner.spawn(light_task(light_sensor)));
     unwrap!(spawner.spawn(net_task(runner)));
-    unwrap!(spawner.spawn(tcp_task(stack, control, car)));
+    unwrap!(spawner.spawn(tcp_task(stack, control, config_store, car_config)));
+    unwrap!(spawner.spawn(udp_task(stack)));
 }
 
 /// Input a value 0 to 255 to get a color value
@@ -169,7 +294,10 @@ fn wheel(mut wheel_pos: u8) -> RGB8 {
 }
 
 #[embassy_executor::task]
-async fn led_matrix(mut driver: HT16K33<I2c<'static, I2C0, Async>>) {
+async fn led_matrix(i2c: I2c0Device) {
+    let mut driver = HT16K33::new(i2c, 0x71);
+    driver.setup().await.unwrap();
+
     let mut buffer = [0u8; 2 * 8];
 
     loop {
@@ -185,10 +313,93 @@ async fn led_matrix(mut driver: HT16K33<I2c<'static, I2C0, Async>>) {
     }
 }
 
+// Tracks the heading if there is an IMU, ends right away if there isn't
+#[embassy_executor::task]
+async fn imu_task(i2c: I2c0Device) {
+    imu::run(i2c).await
+}
+
+// Writes the recorded events to flash
+#[embassy_executor::task]
+async fn event_log_task(log: EventLog<FlashPartition>) {
+    event_log::run(log).await
+}
+
+#[embassy_executor::task]
+async fn buzzer_task(mut buzzer: Buzzer<'static>) {
+    loop {
+        match buzzer::SOUNDS.receive().await {
+            Sound::Tone(note) => buzzer.tone(note).await,
+            Sound::Melody(melody) => buzzer.play(buzzer::notes(melody)).await,
+        }
+    }
+}
+
+// Owns the car and applies motion requests from all input sources, runs on core 1
+#[embassy_executor::task]
+async fn motion_task(mut car: Car<'static>, arbiter: Arbiter, limits: DriveLimits) {
+    info!("Motion control running on core 1");
+    motion::run(&mut car, arbiter, limits).await
+}
+
+// Measures the distance ahead for the obstacle stop
+//
+// Runs on core 0: the echo pin is awaited through the GPIO interrupt, which only core 0 handles.
+// The motion loop on core 1 reads the published distance.
+#[embassy_executor::task]
+async fn obstacle_task(mut ultrasonic: Ultrasonic<'static>) {
+    let mut ticker = Ticker::every(obstacle::SAMPLE_PERIOD);
+    let mut answered = false;
+    loop {
+        let cm = ultrasonic.measure().await;
+        if let (Some(cm), false) = (cm, answered) {
+            info!("Ultrasonic sensor answered, {} cm ahead", cm);
+            answered = true;
+        }
+        obstacle::publish(cm);
+        ticker.next().await;
+    }
+}
+
+#[embassy_executor::task]
+async fn ir_task(mut nec: PioNec<'static, PIO0, 1>) {
+    let mut remote = IrRemote::new();
+    loop {
+        let event = nec.read().await;
+        remote.handle(event).await;
+    }
+}
+
+// Samples the photoresistors and steers the car while following light
+#[embassy_executor::task]
+async fn light_task(mut sensor: LightSensor<'static>) {
+    sensor.calibrate().await;
+
+    #[cfg(feature = "battery")]
+    let mut battery = BatteryCheck::new();
+    let mut ticker = Ticker::every(light::SAMPLE_PERIOD);
+    loop {
+        #[cfg(feature = "battery")]
+        battery.run(sensor.adc()).await;
+
+        let reading = sensor.read().await;
+        telemetry::update(|telemetry| {
+            telemetry.light_left = reading.left;
+            telemetry.light_right = reading.right;
+        });
+
+        if let DriveMode::LightFollow(target) = motion::mode() {
+            motion::request(Source::Autonomous, light::steer(reading, target)).await;
+        }
+        ticker.next().await;
+    }
+}
+
 #[embassy_executor::task]
-async fn led_task(mut pio: Pio<'static, PIO1>, dma: Peri<'static, DMA_CH1>, pin: Peri<'static, PIN_16>) {
+async fn led_task(r: LedResources) {
+    let mut pio = Pio::new(r.pio, Irqs);
     let program = PioWs2812Program::new(&mut pio.common);
-    let mut ws2812 = PioWs2812::new(&mut pio.common, pio.sm0, dma, pin, &program);
+    let mut ws2812 = PioWs2812::new(&mut pio.common, pio.sm0, r.dma, r.pin, &program);
 
     const NUM_LEDS: usize = 8;
     let mut data = [RGB8::default(); NUM_LEDS];
@@ -197,7 +408,9 @@ async fn led_task(mut pio: Pio<'static, PIO1>, dma: Peri<'static, DMA_CH1>, pin:
         for j in 0..(256 * 5) {
             debug!("New Colors:");
             for i in 0..NUM_LEDS {
-                data[i] = wheel((((i * 256) as u16 / NUM_LEDS as u16 + j as u16) & 255) as u8);
+                // A mission step's colour takes the place of the rainbow
+                data[i] = leds::colour()
+                    .unwrap_or_else(|| wheel((((i * 256) as u16 / NUM_LEDS as u16 + j as u16) & 255) as u8));
                 debug!("R: {} G: {} B: {}", data[i].r, data[i].g, data[i].b);
             }
             ws2812.write(&data).await;
@@ -208,7 +421,17 @@ async fn led_task(mut pio: Pio<'static, PIO1>, dma: Peri<'static, DMA_CH1>, pin:
 
 // TCP server task that receives commands and controls the car
 #[embassy_executor::task]
-async fn tcp_task(stack: Stack<'static>, mut control: Control<'static>, mut car: Car<'static>) {
+async fn tcp_task(
+    stack: Stack<'static>,
+    mut control: Control<'static>,
+    config_store: ConfigStore,
+    mut car_config: CarConfig,
+) {
+    let mut rng = RoscRng;
+    if car_config.auth_key.is_some() {
+        info!("Pre-shared key configured, clients must authenticate");
+    }
+
     // Connect to WiFi
     loop {
         match control
@@ -234,103 +457,153 @@ async fn tcp_task(stack: Stack<'static>, mut control: Control<'static>, mut car:
         info!("IP address: {}", config.address);
     }
 
-    // Set up bincode configuration
-    let config = bincode::config::standard();
-
-    // TCP server loop
+    // TCP server loop, one client at a time on either the command port or the HTTP port
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
-    let mut buf = [0; 4096];
+    let mut http_rx_buffer = [0; 2048];
+    let mut http_tx_buffer = [0; 4096];
+    let mut platform = Firmware { config_store, peer: None };
 
     loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(Duration::from_secs(10)));
+        let mut command_socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        command_socket.set_timeout(Some(Duration::from_secs(10)));
+        let mut http_socket = TcpSocket::new(stack, &mut http_rx_buffer, &mut http_tx_buffer);
+        http_socket.set_timeout(Some(Duration::from_secs(10)));
 
         // Set LED off while waiting for connection
         control.gpio_set(0, false).await;
-        info!("Listening on TCP:1234...");
-
-        if let Err(e) = socket.accept(1234).await {
+        info!("Listening on TCP:{} and HTTP:{}...", COMMAND_PORT, HTTP_PORT);
+
+        let (accepted, browser) = match select(command_socket.accept(COMMAND_PORT), http_socket.accept(HTTP_PORT)).await {
+            Either::First(accepted) => (accepted, false),
+            Either::Second(accepted) => (accepted, true),
+        };
+        let (socket, other) = if browser {
+            (&mut http_socket, &mut command_socket)
+        } else {
+            (&mut command_socket, &mut http_socket)
+        };
+        other.abort();
+        if let Err(e) = accepted {
             warn!("accept error: {:?}", e);
             continue;
         }
-
-        // Set LED on after connection established
         info!("Received connection from {:?}", socket.remote_endpoint());
-        control.gpio_set(0, true).await;
-
-        // Connection handling loop
-        loop {
-            let n = match socket.read(&mut buf).await {
-                Ok(0) => {
-                    warn!("read EOF");
-                    break;
-                }
-                Ok(n) => n,
-                Err(e) => {
-                    warn!("read error: {:?}", e);
-                    break;
+
+        let mut nonce = [0; auth::NONCE_LEN];
+        rng.fill_bytes(&mut nonce);
+        platform.peer = socket.remote_endpoint().map(|endpoint| endpoint.addr);
+        if browser {
+            // Page loads come and go, only a WebSocket drives the car
+            match web::respond(&mut *socket).await {
+                Ok(Some(mut websocket)) => {
+                    control.gpio_set(0, true).await;
+                    buzzer::play(Sound::Melody(Melody::Connect));
+                    server::serve(&mut websocket, nonce, &mut car_config, &mut platform).await;
                 }
-            };
-
-            // Try to parse the received data as a CarCommand using bincode
-            match bincode::decode_from_slice::<CarCommand, _>(&buf[..n], config) {
-                Ok((command, _)) => {
-                    // Execute the command on the car
-                    match command {
-                        CarCommand::Forward(speed) => {
-                            info!("Moving forward with speed {}", speed);
-                            car.forward(speed).await;
-                        }
-                        CarCommand::Backward(speed) => {
-                            info!("Moving backward with speed {}", speed);
-                            car.backward(speed).await;
-                        }
-                        CarCommand::TurnLeft(speed) => {
-                            info!("Turning left with speed {}", speed);
-                            car.turn_left(speed).await;
-                        }
-                        CarCommand::TurnRight(speed) => {
-                            info!("Turning right with speed {}", speed);
-                            car.turn_right(speed).await;
-                        }
-                        CarCommand::Stop => {
-                            info!("Stopping car");
-                            car.stop().await;
-                        }
-                    }
-
-                    // Send back acknowledgment
-                    let ack = "ACK";
-                    if let Err(e) = socket.write_all(ack.as_bytes()).await {
-                        warn!("write error: {:?}", e);
-                        break;
-                    }
+                Ok(None) => {
+                    socket.close();
+                    continue;
                 }
-                Err(_) => {
-                    // If parsing fails, try to interpret as plain text (for debugging)
-                    // warn!("Failed to parse command: {:?}", e);
-
-                    if let Ok(str_data) = from_utf8(&buf[..n]) {
-                        info!("Received raw data (text): {}", str_data);
-                    } else {
-                        info!("Received raw data (binary): {:?}", &buf[..n]);
-                    }
-
-                    // Echo back the data for debugging purposes
-                    match socket.write_all(&buf[..n]).await {
-                        Ok(()) => {}
-                        Err(e) => {
-                            warn!("write error: {:?}", e);
-                            break;
-                        }
-                    };
+                Err(e) => {
+                    warn!("HTTP error: {:?}", e);
+                    continue;
                 }
             }
+        } else {
+            // Set LED on after connection established
+            control.gpio_set(0, true).await;
+            buzzer::play(Sound::Melody(Melody::Connect));
+            server::serve(socket, nonce, &mut car_config, &mut platform).await;
+        }
+        drive::close();
+        socket.close();
+
+        // serve() has already stopped whatever the client was driving
+        info!("Connection closed, car stopped");
+        buzzer::play(Sound::Melody(Melody::Disconnect));
+    }
+}
+
+// Applies the drive setpoints the connected client sends over UDP
+#[embassy_executor::task]
+async fn udp_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 0];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(COMMAND_PORT));
+    info!("Listening on UDP:{}...", COMMAND_PORT);
+
+    let mut datagram = [0; MAX_DATAGRAM_LEN];
+    loop {
+        let (n, meta) = match socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("UDP receive error: {:?}", e);
+                continue;
+            }
+        };
+        if let Some(motion) = drive::receive(meta.endpoint.addr, &datagram[..n]) {
+            motion::request(Source::Network, motion).await;
         }
+    }
+}
+
+/// The hardware behind the commands of a connection
+struct Firmware {
+    config_store: ConfigStore,
+    peer: Option<IpAddress>, // The connected client, the only one the drive channel takes setpoints from
+}
+
+impl Platform for Firmware {
+    async fn motion(&mut self, request: MotionRequest) {
+        motion::MOTION_REQUESTS.send(request).await;
+    }
+
+    fn set_mode(&mut self, mode: DriveMode) {
+        motion::set_mode(mode);
+    }
+
+    fn telemetry(&self) -> Telemetry {
+        telemetry::snapshot()
+    }
+
+    fn beep(&mut self, freq: u16, ms: u16) {
+        buzzer::play(Sound::Tone(Note { freq, ms }));
+    }
+
+    fn play(&mut self, melody: Melody) {
+        buzzer::play(Sound::Melody(melody));
+    }
+
+    fn open_drive_channel(&mut self, key: Option<Key>) {
+        if let Some(peer) = self.peer {
+            drive::open(peer, key);
+        }
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        motion::set_calibration(calibration);
+    }
+
+    fn store_config(&mut self, config: &CarConfig) -> bool {
+        ir::set_keymap(config.ir_keymap);
+        match self.config_store.store(config) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("failed to store config: {:?}", e);
+                false
+            }
+        }
+    }
+
+    fn record(&mut self, event: Event) {
+        event_log::record(event);
+    }
 
-        // When connection is closed, stop the car for safety
-        info!("Connection closed, stopping car");
-        // car.stop().await;
+    async fn read_log(&mut self, from: u32) -> LogPage {
+        event_log::read(from).await
     }
 }
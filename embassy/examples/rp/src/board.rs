@@ -0,0 +1,160 @@
+//! Pin assignment of the rigs the firmware runs on
+//!
+//! Every rig is a board profile, picked with one of the `board-*` cargo features. A profile hands
+//! out the same resource groups, only the pins and peripherals behind them differ, so `main`
+//! splits the peripherals with `split_resources!` and passes each group to whatever drives it.
+//!
+//! - `board-freenove` (default): the Freenove 4WD car with a Pico W
+//! - `board-breadboard`: a bare Pico W on a breadboard with two dual H-bridges
+//! - `board-pico2w`: the Freenove 4WD car with a Pico 2 W, built for `thumbv8m.main-none-eabihf`
+//!
+//! The cyw43 is wired the same on the Pico W and the Pico 2 W. A motor that turns the wrong way
+//! on a rig is fixed with the motor calibration rather than a profile.
+
+use assign_resources::assign_resources;
+use embassy_rp::peripherals;
+use embassy_rp::Peri;
+
+#[cfg(not(any(feature = "board-freenove", feature = "board-breadboard", feature = "board-pico2w")))]
+compile_error!("pick a board profile with one of the `board-*` features");
+
+#[cfg(any(
+    all(feature = "board-freenove", feature = "board-breadboard"),
+    all(feature = "board-freenove", feature = "board-pico2w"),
+    all(feature = "board-breadboard", feature = "board-pico2w"),
+))]
+compile_error!("only one `board-*` feature can be enabled, add `--no-default-features` to pick another");
+
+/// Name of the rig, logged at boot
+#[cfg(feature = "board-freenove")]
+pub const NAME: &str = "Freenove 4WD with a Pico W";
+#[cfg(feature = "board-breadboard")]
+pub const NAME: &str = "Pico W breadboard";
+#[cfg(feature = "board-pico2w")]
+pub const NAME: &str = "Freenove 4WD with a Pico 2 W";
+
+/// Flash size of the Pico W
+#[cfg(any(feature = "board-freenove", feature = "board-breadboard"))]
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Flash size of the Pico 2 W
+#[cfg(feature = "board-pico2w")]
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+// Freenove 4WD, on the Pico W and the Pico 2 W alike
+#[cfg(any(feature = "board-freenove", feature = "board-pico2w"))]
+assign_resources! {
+    wifi: WifiResources {
+        pwr: PIN_23,
+        cs: PIN_25,
+        dio: PIN_24,
+        clk: PIN_29,
+        pio: PIO0,
+        dma: DMA_CH0,
+    }
+    // IR receiver, on a state machine of the cyw43's PIO
+    ir: IrResources {
+        pin: PIN_3,
+    }
+    motors: MotorResources {
+        front_left_slice: PWM_SLICE1,
+        front_left_a: PIN_18,
+        front_left_b: PIN_19,
+        front_right_slice: PWM_SLICE4,
+        front_right_a: PIN_8,
+        front_right_b: PIN_9,
+        rear_left_slice: PWM_SLICE2,
+        rear_left_a: PIN_20,
+        rear_left_b: PIN_21,
+        rear_right_slice: PWM_SLICE3,
+        rear_right_a: PIN_6,
+        rear_right_b: PIN_7,
+    }
+    // Passive buzzer, slice 7 is not shared with any motor
+    buzzer: BuzzerResources {
+        slice: PWM_SLICE7,
+        pin: PIN_14,
+    }
+    light: LightResources {
+        adc: ADC,
+        left: PIN_26,
+        right: PIN_27,
+        dma: DMA_CH2,
+    }
+    // HC-SR04
+    ultrasonic: UltrasonicResources {
+        trigger: PIN_10,
+        echo: PIN_11,
+    }
+    // I2C bus of the HT16K33 LED matrix, shared with the optional MPU6050 IMU
+    i2c: I2cResources {
+        i2c: I2C0,
+        scl: PIN_5,
+        sda: PIN_4,
+    }
+    // WS2812 strip
+    leds: LedResources {
+        pio: PIO1,
+        dma: DMA_CH1,
+        pin: PIN_16,
+    }
+    flash: FlashResources {
+        flash: FLASH,
+    }
+}
+
+// Pico W breadboard, the motors take GP0 to GP7 so the other parts move up
+#[cfg(feature = "board-breadboard")]
+assign_resources! {
+    wifi: WifiResources {
+        pwr: PIN_23,
+        cs: PIN_25,
+        dio: PIN_24,
+        clk: PIN_29,
+        pio: PIO0,
+        dma: DMA_CH0,
+    }
+    ir: IrResources {
+        pin: PIN_15,
+    }
+    motors: MotorResources {
+        front_left_slice: PWM_SLICE0,
+        front_left_a: PIN_0,
+        front_left_b: PIN_1,
+        front_right_slice: PWM_SLICE1,
+        front_right_a: PIN_2,
+        front_right_b: PIN_3,
+        rear_left_slice: PWM_SLICE2,
+        rear_left_a: PIN_4,
+        rear_left_b: PIN_5,
+        rear_right_slice: PWM_SLICE3,
+        rear_right_a: PIN_6,
+        rear_right_b: PIN_7,
+    }
+    buzzer: BuzzerResources {
+        slice: PWM_SLICE7,
+        pin: PIN_14,
+    }
+    light: LightResources {
+        adc: ADC,
+        left: PIN_26,
+        right: PIN_27,
+        dma: DMA_CH2,
+    }
+    ultrasonic: UltrasonicResources {
+        trigger: PIN_10,
+        echo: PIN_11,
+    }
+    i2c: I2cResources {
+        i2c: I2C0,
+        scl: PIN_9,
+        sda: PIN_8,
+    }
+    leds: LedResources {
+        pio: PIO1,
+        dma: DMA_CH1,
+        pin: PIN_16,
+    }
+    flash: FlashResources {
+        flash: FLASH,
+    }
+}
@@ -0,0 +1,113 @@
+//! Passive buzzer driven by a PWM slice
+//!
+//! The buzzer only sounds when it is driven with a square wave, so the tone frequency is set by
+//! reprogramming the slice's divider and top, with the compare value at half the period.
+
+use embassy_rp::pwm::{Config, Pwm};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+use shared::Melody;
+
+/// Longest tone a `Beep` command may request
+pub const MAX_TONE_MS: u16 = 5_000;
+
+/// A single note, a frequency of 0 is a rest
+#[derive(Clone, Copy)]
+pub struct Note {
+    pub freq: u16,
+    pub ms: u16,
+}
+
+const fn note(freq: u16, ms: u16) -> Note {
+    Note { freq, ms }
+}
+
+const BOOT: &[Note] = &[note(523, 80), note(659, 80), note(784, 80), note(1047, 160)];
+const CONNECT: &[Note] = &[note(784, 60), note(1047, 100)];
+const DISCONNECT: &[Note] = &[note(1047, 60), note(784, 100)];
+const LOW_BATTERY: &[Note] = &[note(440, 200), note(0, 100), note(440, 200), note(0, 100), note(440, 200)];
+const OBSTACLE: &[Note] = &[note(1760, 50), note(0, 50), note(1760, 50)];
+const HORN: &[Note] = &[note(392, 150), note(0, 40), note(392, 300)];
+
+/// The notes making up a built-in melody
+pub fn notes(melody: Melody) -> &'static [Note] {
+    match melody {
+        Melody::Boot => BOOT,
+        Melody::Connect => CONNECT,
+        Melody::Disconnect => DISCONNECT,
+        Melody::LowBattery => LOW_BATTERY,
+        Melody::Obstacle => OBSTACLE,
+        Melody::Horn => HORN,
+    }
+}
+
+/// Something for the buzzer task to play
+#[derive(Clone, Copy)]
+pub enum Sound {
+    Tone(Note),
+    Melody(Melody),
+}
+
+/// Sounds queued for the buzzer task
+pub static SOUNDS: Channel<CriticalSectionRawMutex, Sound, 4> = Channel::new();
+
+/// Queue a sound without waiting, it is dropped if the queue is already full
+pub fn play(sound: Sound) {
+    if SOUNDS.try_send(sound).is_err() {
+        defmt::debug!("buzzer busy, dropping sound");
+    }
+}
+
+pub struct Buzzer<'a> {
+    pwm: Pwm<'a>,
+    config: Config,
+}
+
+impl<'a> Buzzer<'a> {
+    /// Takes a slice with the buzzer on its A output
+    pub fn new(pwm: Pwm<'a>) -> Self {
+        let mut buzzer = Self {
+            pwm,
+            config: Config::default(),
+        };
+        buzzer.silence();
+        buzzer
+    }
+
+    fn silence(&mut self) {
+        self.config.compare_a = 0;
+        self.pwm.set_config(&self.config);
+    }
+
+    fn set_frequency(&mut self, freq: u16) {
+        let clock_freq_hz = embassy_rp::clocks::clk_sys_freq();
+        let freq = freq as u32;
+
+        // Smallest integer divider that keeps the period within the 16 bit counter
+        let divider = clock_freq_hz.div_ceil(freq * 0x1_0000).clamp(1, 255);
+        let top = (clock_freq_hz / (freq * divider)).clamp(2, 0x1_0000) - 1;
+
+        self.config.divider = (divider as u8).into();
+        self.config.top = top as u16;
+        self.config.compare_a = (top / 2) as u16;
+        self.pwm.set_config(&self.config);
+    }
+
+    /// Play a tone for `note.ms` milliseconds, then go quiet
+    pub async fn tone(&mut self, note: Note) {
+        if note.freq == 0 {
+            self.silence();
+        } else {
+            self.set_frequency(note.freq);
+        }
+        Timer::after_millis(note.ms.min(MAX_TONE_MS) as u64).await;
+        self.silence();
+    }
+
+    pub async fn play(&mut self, notes: &[Note]) {
+        for &note in notes {
+            self.tone(note).await;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Car configuration persisted in the last sectors of flash
+
+use core::cell::RefCell;
+
+use crusty_core::config_store::{self, Loaded, CONFIG_SECTORS};
+use embassy_embedded_hal::flash::partition::{self, BlockingPartition};
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::flash::{self, Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use rand::RngCore;
+use shared::auth::KEY_LEN;
+use shared::config::{CarConfig, Restored};
+
+pub use crate::board::FLASH_SIZE;
+
+/// The flash, shared by the config and the event log through partitions
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, RefCell<Flash<'static, FLASH, Blocking, FLASH_SIZE>>>;
+
+/// Part of the [`SharedFlash`]
+pub type FlashPartition = BlockingPartition<'static, CriticalSectionRawMutex, Flash<'static, FLASH, Blocking, FLASH_SIZE>>;
+
+/// Start of the config sectors, the last ones of the flash
+pub const CONFIG_OFFSET: u32 = (FLASH_SIZE - CONFIG_SECTORS as usize * ERASE_SIZE) as u32;
+
+pub type StoreError = config_store::StoreError<partition::Error<flash::Error>>;
+
+/// Reads and writes the [`CarConfig`] record
+pub struct ConfigStore {
+    store: config_store::ConfigStore<FlashPartition>,
+}
+
+impl ConfigStore {
+    pub fn new(flash: &'static SharedFlash) -> Self {
+        Self {
+            store: config_store::ConfigStore::new(FlashPartition::new(flash, CONFIG_OFFSET, CONFIG_SECTORS * ERASE_SIZE as u32)),
+        }
+    }
+
+    /// Load the newest stored config, falling back to defaults if none was ever stored
+    ///
+    /// A record in a layout this firmware doesn't know keeps its keys, or locks the car with a random
+    /// one if even those are lost, so authentication is never dropped by accident.
+    pub fn load(&mut self) -> CarConfig {
+        match self.store.load() {
+            Loaded::Blank => {
+                defmt::info!("no stored config, using defaults");
+                CarConfig::default()
+            }
+            Loaded::Config(config, Restored::All) => config,
+            Loaded::Config(config, Restored::KeyOnly) => {
+                defmt::warn!("stored config has an unknown layout, only its keys were kept");
+                config
+            }
+            Loaded::Unreadable => locked(),
+        }
+    }
+
+    /// Store `config`, the previous one is kept until it is written completely
+    pub fn store(&mut self, config: &CarConfig) -> Result<(), StoreError> {
+        self.store.store(config)
+    }
+}
+
+/// Defaults locked with a key nobody knows, for a stored config too corrupt to read its key from
+fn locked() -> CarConfig {
+    defmt::error!("stored config is unreadable, locking the car with a random key");
+    let mut key = [0; KEY_LEN];
+    RoscRng.fill_bytes(&mut key);
+    CarConfig {
+        auth_key: Some(key),
+        ..CarConfig::default()
+    }
+}
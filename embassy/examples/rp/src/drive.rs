@@ -0,0 +1,28 @@
+//! The UDP drive channel of the connected client, opened by the TCP task and read by the UDP task
+
+use core::cell::RefCell;
+
+use crusty_core::drive::DriveChannel;
+use crusty_core::motion::Motion;
+use embassy_net::IpAddress;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use shared::auth::Key;
+
+static CHANNEL: Mutex<CriticalSectionRawMutex, RefCell<DriveChannel<IpAddress>>> =
+    Mutex::new(RefCell::new(DriveChannel::new()));
+
+/// Take setpoints from `peer`, checked against the session's drive key when the car has a key
+pub fn open(peer: IpAddress, key: Option<Key>) {
+    CHANNEL.lock(|channel| channel.borrow_mut().open(peer, key));
+}
+
+/// Stop taking setpoints, when the connection ends
+pub fn close() {
+    CHANNEL.lock(|channel| channel.borrow_mut().close());
+}
+
+/// The motion a datagram from `from` asks for, `None` if it is dropped
+pub fn receive(from: IpAddress, datagram: &[u8]) -> Option<Motion> {
+    CHANNEL.lock(|channel| channel.borrow_mut().receive(&from, datagram))
+}
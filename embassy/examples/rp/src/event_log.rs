@@ -0,0 +1,73 @@
+//! Event log kept in the flash sectors before the config
+//!
+//! Any task, on either core, queues events with [`record`]. The log task writes them to flash in
+//! the order they came in, see [`crusty_core::event_log`] for how they are kept there. Erasing a
+//! sector runs from RAM with core 1 paused, and the motion loop with it, so an entry that starts a
+//! new sector waits in the queue until the car stands still.
+
+use crusty_core::event_log::EventLog;
+use embassy_rp::flash::ERASE_SIZE;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use shared::event_log::{Event, LogPage, LOG_PAGE_LEN};
+
+use crate::config::{FlashPartition, SharedFlash, CONFIG_OFFSET};
+use crate::motion;
+
+/// Sectors the log takes, 255 entries each
+pub const LOG_SECTORS: u32 = 8;
+
+const LOG_OFFSET: u32 = CONFIG_OFFSET - LOG_SECTORS * ERASE_SIZE as u32;
+
+/// How often an entry that has to erase a sector checks whether the car stopped
+const STOPPED_POLL: Duration = Duration::from_millis(100);
+
+/// Events waiting to be written, with their uptime in ms
+static EVENTS: Channel<CriticalSectionRawMutex, (u32, Event), 8> = Channel::new();
+
+/// The log, once the log task has it
+static LOG: Mutex<CriticalSectionRawMutex, Option<EventLog<FlashPartition>>> = Mutex::new(None);
+
+/// Open the log kept in `flash`
+pub fn open(flash: &'static SharedFlash) -> EventLog<FlashPartition> {
+    let log = EventLog::open(FlashPartition::new(flash, LOG_OFFSET, LOG_SECTORS * ERASE_SIZE as u32));
+    defmt::info!("event log at boot {}, next entry {}", log.boot(), log.next_seq());
+    log
+}
+
+/// Queue `event` for the log task, dropped if the queue is full
+pub fn record(event: Event) {
+    let uptime_ms = Instant::now().as_millis() as u32;
+    if EVENTS.try_send((uptime_ms, event)).is_err() {
+        defmt::warn!("event log queue full, dropping {:?}", event);
+    }
+}
+
+/// Entries from `from` on, empty until the log task has started
+pub async fn read(from: u32) -> LogPage {
+    match LOG.lock().await.as_mut() {
+        Some(log) => log.read(from),
+        None => [None; LOG_PAGE_LEN],
+    }
+}
+
+/// Write the queued events to `log` forever
+pub async fn run(log: EventLog<FlashPartition>) -> ! {
+    *LOG.lock().await = Some(log);
+    loop {
+        let (uptime_ms, event) = EVENTS.receive().await;
+        loop {
+            if let Some(log) = LOG.lock().await.as_mut() {
+                if !log.append_erases() || motion::stopped() {
+                    if let Err(e) = log.append(uptime_ms, event) {
+                        defmt::warn!("failed to log {:?}: {:?}", event, e);
+                    }
+                    break;
+                }
+            }
+            Timer::after(STOPPED_POLL).await;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! MPU6050 IMU on the LED matrix's I2C bus, for the heading hold and turns by an angle
+//!
+//! The IMU is optional: the task looks for it at startup and ends if nothing answers, the car
+//! then drives open loop and refuses turns by an angle. The gyro's bias is measured first, so the
+//! car has to stand still for the first two seconds. See [`crusty_core::imu`] for the rest.
+
+use core::cell::Cell;
+
+use crusty_core::imu::{Bias, Heading, Mpu6050, ADDRESS};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_hal_async::i2c::I2c;
+use shared::ImuReading;
+
+use crate::telemetry;
+
+/// The IMU is sampled as often as the control loop runs
+pub const SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+
+/// A reading older than this is ignored
+const MAX_AGE: Duration = Duration::from_millis(50);
+
+static READING: Mutex<CriticalSectionRawMutex, Cell<Option<(ImuReading, Instant)>>> = Mutex::new(Cell::new(None));
+
+/// Heading in degrees clockwise since startup, `None` without a recent reading
+pub fn heading() -> Option<f32> {
+    READING
+        .lock(Cell::get)
+        .filter(|&(_, at)| at.elapsed() < MAX_AGE)
+        .map(|(reading, _)| reading.heading_deg)
+}
+
+/// Store a reading for the motion loop
+fn publish(reading: Option<ImuReading>) {
+    READING.lock(|cell| cell.set(reading.map(|reading| (reading, Instant::now()))));
+    telemetry::update(|telemetry| telemetry.imu = reading);
+}
+
+/// Look for the IMU on `i2c`, measure the gyro's bias and then track the heading forever
+///
+/// Returns right away if there is no IMU.
+pub async fn run<I: I2c>(i2c: I)
+where
+    I::Error: defmt::Format,
+{
+    let mut imu = match Mpu6050::new(i2c, ADDRESS).await {
+        Ok(imu) => imu,
+        Err(e) => {
+            defmt::info!("no IMU found ({:?}), driving without heading hold", e);
+            return;
+        }
+    };
+
+    defmt::info!("calibrating the gyro, keep the car still");
+    let mut ticker = Ticker::every(SAMPLE_PERIOD);
+    let mut bias = Bias::default();
+    while !bias.done() {
+        match imu.read().await {
+            Ok(sample) => bias.add(sample.yaw_rate_dps()),
+            Err(e) => defmt::warn!("IMU read failed: {:?}", e),
+        }
+        ticker.next().await;
+    }
+    defmt::info!("gyro bias {} deg/s", bias.bias_dps());
+
+    let mut heading = Heading::new(bias.bias_dps());
+    loop {
+        match imu.read().await {
+            Ok(sample) => publish(Some(heading.update(&sample, Instant::now()))),
+            Err(e) => {
+                defmt::warn!("IMU read failed: {:?}", e);
+                publish(None);
+            }
+        }
+        ticker.next().await;
+    }
+}
@@ -0,0 +1,95 @@
+//! Translates IR remote keys into motion requests
+//!
+//! Movement keys keep the car moving only while the key is held, each frame or repeat code
+//! refreshes the [`Source::Ir`] lease and the motion task stops the car once the repeats end.
+
+use core::cell::Cell;
+
+use embassy_rp::pio_programs::nec::NecEvent;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use shared::config::{self, IrAction, IrKeymap, DEFAULT_IR_KEYMAP};
+use shared::Melody;
+
+use crate::buzzer::{self, Sound};
+use crate::motion::{self, Motion, Source};
+
+const SPEED_STEP: u8 = 10;
+
+static KEYMAP: Mutex<CriticalSectionRawMutex, Cell<IrKeymap>> = Mutex::new(Cell::new(DEFAULT_IR_KEYMAP));
+
+/// Replace the key bindings used by the IR task
+pub fn set_keymap(keymap: IrKeymap) {
+    KEYMAP.lock(|cell| cell.set(keymap));
+}
+
+fn action(command: u8) -> Option<IrAction> {
+    KEYMAP.lock(|cell| config::ir_action(&cell.get(), command))
+}
+
+/// State of the remote between key presses
+pub struct IrRemote {
+    speed: u8,
+    held: Option<Motion>,
+}
+
+impl IrRemote {
+    pub const fn new() -> Self {
+        Self { speed: 50, held: None }
+    }
+
+    /// Act on a received frame or repeat code
+    pub async fn handle(&mut self, event: NecEvent) {
+        let command = match event {
+            NecEvent::Frame { address, command } => {
+                defmt::debug!("IR key address={:#x} command={:#x}", address, command);
+                command
+            }
+            NecEvent::Repeat => {
+                // Keep the held key's motion alive, other actions don't repeat
+                if let Some(motion) = self.held {
+                    motion::request(Source::Ir, motion).await;
+                }
+                return;
+            }
+        };
+
+        self.held = None;
+        let motion = match action(command) {
+            Some(IrAction::Forward) => Motion::Forward(self.speed),
+            Some(IrAction::Backward) => Motion::Backward(self.speed),
+            Some(IrAction::TurnLeft) => Motion::TurnLeft(self.speed),
+            Some(IrAction::TurnRight) => Motion::TurnRight(self.speed),
+            Some(IrAction::Stop) => Motion::Stop,
+            Some(IrAction::Horn) => {
+                buzzer::play(Sound::Melody(Melody::Horn));
+                return;
+            }
+            Some(IrAction::SpeedUp) => {
+                self.speed = self.speed.saturating_add(SPEED_STEP).min(100);
+                defmt::info!("IR speed {}%", self.speed);
+                return;
+            }
+            Some(IrAction::SpeedDown) => {
+                self.speed = self.speed.saturating_sub(SPEED_STEP);
+                defmt::info!("IR speed {}%", self.speed);
+                return;
+            }
+            None => {
+                defmt::info!("IR key {:#x} is not bound", command);
+                return;
+            }
+        };
+
+        if motion != Motion::Stop {
+            self.held = Some(motion);
+        }
+        motion::request(Source::Ir, motion).await;
+    }
+}
+
+impl Default for IrRemote {
+    fn default() -> Self {
+        Self::new()
+    }
+}
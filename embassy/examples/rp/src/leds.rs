@@ -0,0 +1,19 @@
+//! Colour of the WS2812 LEDs, set by mission steps and cleared when the mission ends
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use smart_leds::RGB8;
+
+static COLOUR: Mutex<CriticalSectionRawMutex, Cell<Option<RGB8>>> = Mutex::new(Cell::new(None));
+
+/// Light every LED in `colour`, `None` goes back to the rainbow
+pub fn set(colour: Option<RGB8>) {
+    COLOUR.lock(|cell| cell.set(colour));
+}
+
+/// The colour asked for, `None` while the LEDs show the rainbow
+pub fn colour() -> Option<RGB8> {
+    COLOUR.lock(Cell::get)
+}
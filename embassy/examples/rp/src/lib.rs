@@ -1,5 +1,17 @@
 #![no_std]
 #![no_main]
 
+pub mod battery;
+pub mod board;
+pub mod buzzer;
 pub mod car;
-
+pub mod config;
+pub mod drive;
+pub mod event_log;
+pub mod imu;
+pub mod ir;
+pub mod leds;
+pub mod light;
+pub mod motion;
+pub mod obstacle;
+pub mod telemetry;
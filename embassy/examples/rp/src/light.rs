@@ -0,0 +1,128 @@
+//! Photoresistor pair and the light following controller
+//!
+//! Both sensors are sampled together with a DMA multichannel read. The readings at startup are
+//! taken as the ambient level, so steering only reacts to light added on top of it, and
+//! differences between the two sensors at rest cancel out.
+
+use embassy_rp::adc::{Adc, Async, Channel};
+use embassy_rp::dma::AnyChannel;
+use embassy_rp::Peri;
+use embassy_time::{Duration, Timer};
+use shared::LightTarget;
+
+use crate::motion::Motion;
+
+/// How often the light following controller runs
+pub const SAMPLE_PERIOD: Duration = Duration::from_millis(50);
+
+const CHANNELS: usize = 2;
+const SAMPLES: usize = 16;
+const CALIBRATION_ROUNDS: u32 = 16;
+
+// 48 MHz / (10 kHz * 2 channels) - 1
+const ADC_DIV: u16 = 2399;
+
+/// Light above ambient needed before the car starts following it
+const MIN_LIGHT: u16 = 100;
+/// Speed of both sides while the light is straight ahead
+const BASE_SPEED: i32 = 40;
+/// Extra speed difference between the sides at full left/right imbalance
+const STEER_GAIN: i32 = 60;
+
+/// Light on each side above the ambient level, brighter is higher
+#[derive(Debug, Clone, Copy, Default, defmt::Format)]
+pub struct LightReading {
+    pub left: u16,
+    pub right: u16,
+}
+
+pub struct LightSensor<'d> {
+    adc: Adc<'d, Async>,
+    channels: [Channel<'d>; CHANNELS],
+    dma: Peri<'d, AnyChannel>,
+    ambient: [u16; CHANNELS],
+}
+
+impl<'d> LightSensor<'d> {
+    pub fn new(adc: Adc<'d, Async>, left: Channel<'d>, right: Channel<'d>, dma: Peri<'d, AnyChannel>) -> Self {
+        Self {
+            adc,
+            channels: [left, right],
+            dma,
+            ambient: [0; CHANNELS],
+        }
+    }
+
+    /// The ADC, for other readings in between the light samples
+    pub fn adc(&mut self) -> &mut Adc<'d, Async> {
+        &mut self.adc
+    }
+
+    /// Average a burst of samples from both channels
+    async fn sample(&mut self) -> [u16; CHANNELS] {
+        let mut buf = [0u16; SAMPLES * CHANNELS];
+        if let Err(e) = self
+            .adc
+            .read_many_multichannel(&mut self.channels, &mut buf, ADC_DIV, self.dma.reborrow())
+            .await
+        {
+            defmt::warn!("light sensor read failed: {:?}", e);
+            return self.ambient;
+        }
+
+        // Samples are interleaved, [left, right, left, right, ...]
+        let mut sums = [0u32; CHANNELS];
+        for pair in buf.chunks_exact(CHANNELS) {
+            for (sum, &sample) in sums.iter_mut().zip(pair) {
+                *sum += sample as u32;
+            }
+        }
+        sums.map(|sum| (sum / SAMPLES as u32) as u16)
+    }
+
+    /// Measure the ambient light, the car should see no light source while this runs
+    pub async fn calibrate(&mut self) {
+        let mut sums = [0u32; CHANNELS];
+        for _ in 0..CALIBRATION_ROUNDS {
+            let sample = self.sample().await;
+            for (sum, value) in sums.iter_mut().zip(sample) {
+                *sum += value as u32;
+            }
+            Timer::after_millis(10).await;
+        }
+        self.ambient = sums.map(|sum| (sum / CALIBRATION_ROUNDS) as u16);
+        defmt::info!("ambient light left={} right={}", self.ambient[0], self.ambient[1]);
+    }
+
+    pub async fn read(&mut self) -> LightReading {
+        let [left, right] = self.sample().await;
+        LightReading {
+            left: left.saturating_sub(self.ambient[0]),
+            right: right.saturating_sub(self.ambient[1]),
+        }
+    }
+}
+
+/// Proportional steering towards (or away from) the brighter side
+pub fn steer(reading: LightReading, target: LightTarget) -> Motion {
+    let left = reading.left as i32;
+    let right = reading.right as i32;
+    let total = left + right;
+    if total < MIN_LIGHT as i32 {
+        // Nothing to follow
+        return Motion::Stop;
+    }
+
+    // Imbalance from -1000 (all light on the left) to 1000 (all light on the right)
+    let mut error = (right - left) * 1000 / total;
+    if target == LightTarget::Darker {
+        error = -error;
+    }
+
+    // Speed up the side away from the target so the car turns towards it
+    let correction = error * STEER_GAIN / 1000;
+    Motion::Drive {
+        left: (BASE_SPEED + correction).clamp(-100, 100) as i8,
+        right: (BASE_SPEED - correction).clamp(-100, 100) as i8,
+    }
+}
@@ -0,0 +1,130 @@
+//! Queue and control loop that every input source drives the car through
+//!
+//! The TCP server, the IR remote and the autonomous modes queue a [`MotionRequest`] for the
+//! motion task, which owns the [`Car`] and runs the [`Controller`] at a fixed rate on core 1. See
+//! [`crusty_core::motion`] for how the sources share the car.
+
+use core::cell::Cell;
+
+pub use crusty_core::motion::{Arbiter, Motion, MotionRequest, Source, CONTROL_PERIOD};
+use crusty_core::motion::{Controller, Wheels};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use shared::config::{Calibration, DriveLimits};
+use shared::event_log::Event;
+use shared::mission::MissionStatus;
+use shared::{DriveMode, Melody};
+use smart_leds::RGB8;
+
+use crate::buzzer::{self, Note, Sound};
+use crate::car::Car;
+use crate::{event_log, imu, leds, obstacle, telemetry};
+
+/// Requests for the motion task
+pub static MOTION_REQUESTS: Channel<CriticalSectionRawMutex, MotionRequest, 8> = Channel::new();
+
+/// A new motor calibration for the car, taken on the next tick
+static CALIBRATION: Signal<CriticalSectionRawMutex, Calibration> = Signal::new();
+
+static MODE: Mutex<CriticalSectionRawMutex, Cell<DriveMode>> = Mutex::new(Cell::new(DriveMode::Manual));
+
+static STOPPED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(true));
+
+/// The active drive mode
+pub fn mode() -> DriveMode {
+    MODE.lock(Cell::get)
+}
+
+/// Switch drive mode, the autonomous tasks pick it up on their next update
+pub fn set_mode(mode: DriveMode) {
+    MODE.lock(|cell| cell.set(mode));
+    telemetry::update(|telemetry| telemetry.mode = mode);
+}
+
+/// Whether every motor is off, erasing flash stalls core 1 and is only safe then
+pub fn stopped() -> bool {
+    STOPPED.lock(Cell::get)
+}
+
+/// Correct the motors with `calibration` from the motion task's next tick on
+pub fn set_calibration(calibration: Calibration) {
+    CALIBRATION.signal(calibration);
+}
+
+/// Queue a motion request for the motion task, which picks it up on its next tick
+pub async fn request(source: Source, motion: Motion) {
+    MOTION_REQUESTS.send(MotionRequest::Set { source, motion }).await;
+}
+
+/// Run the control loop forever, holding the car to `limits` until the server changes them
+///
+/// Meant to run alone on core 1, so Wi-Fi and the other core 0 tasks can't delay it. The time
+/// from a tick being due to the motors being updated is tracked and the worst case is reported
+/// as `loop_latency_us` in the telemetry.
+pub async fn run(car: &mut Car<'_>, arbiter: Arbiter, limits: DriveLimits) -> ! {
+    let mut controller = Controller::new(arbiter);
+    controller.set_limits(limits);
+    let mut worst = Duration::from_ticks(0);
+    let mut mission = MissionStatus::Idle;
+    let mut due = Instant::now();
+
+    loop {
+        Timer::at(due).await;
+        let now = Instant::now();
+        controller.set_heading(imu::heading());
+
+        while let Ok(request) = MOTION_REQUESTS.try_receive() {
+            if let Some(mode) = controller.request(request, mode(), now) {
+                set_mode(mode);
+            }
+        }
+
+        // Apply a new calibration to the speed the wheels are already at
+        if let Some(calibration) = CALIBRATION.try_take() {
+            car.set_calibration(calibration);
+            car.drive_wheels(controller.output()).unwrap();
+        }
+
+        let tick = controller.tick(now, controller.too_close(obstacle::distance()));
+        if tick.obstacle {
+            defmt::warn!("obstacle at {:?} cm", obstacle::distance());
+            buzzer::play(Sound::Melody(Melody::Obstacle));
+        }
+        if tick.deadman == Some(Source::Network) {
+            event_log::record(Event::Failsafe);
+        }
+        if let Some((freq, ms)) = tick.beep {
+            buzzer::play(Sound::Tone(Note { freq, ms }));
+        }
+        if let Some((r, g, b)) = tick.lights {
+            leds::set(Some(RGB8::new(r, g, b)));
+        }
+        if controller.mission() != mission {
+            mission = controller.mission();
+            telemetry::update(|telemetry| telemetry.mission = mission);
+            if !matches!(mission, MissionStatus::Running { .. }) {
+                leds::set(None);
+            }
+        }
+        if let Some(wheels) = tick.wheels {
+            car.drive_wheels(wheels).unwrap();
+            STOPPED.lock(|stopped| stopped.set(wheels == Wheels::STOP));
+            telemetry::update(|telemetry| {
+                telemetry.motor_left = wheels.left();
+                telemetry.motor_right = wheels.right();
+            });
+        }
+
+        let latency = Instant::now() - due;
+        if latency > worst {
+            worst = latency;
+            telemetry::update(|telemetry| telemetry.loop_latency_us = worst.as_micros() as u32);
+        }
+
+        // Skip ticks that were missed instead of running them back to back
+        due = (due + CONTROL_PERIOD).max(Instant::now());
+    }
+}
@@ -0,0 +1,66 @@
+//! HC-SR04 ultrasonic ranging for the obstacle stop
+//!
+//! The sensor task on core 0 publishes the latest distance, the motion loop on core 1 reads it on
+//! every tick and stops at the distance of the driving profile. The echo is awaited through the
+//! GPIO interrupt, which only core 0 has enabled, so the sensor can't be read from core 1. A
+//! missing or silent sensor never reports an obstacle, so the car still drives without one.
+
+use core::cell::Cell;
+
+use embassy_rp::gpio::{Input, Output};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{block_for, with_timeout, Duration, Instant};
+
+use crate::telemetry;
+
+/// The HC-SR04 needs 60 ms between measurements to let old echoes die out
+pub const SAMPLE_PERIOD: Duration = Duration::from_millis(60);
+
+/// A reading older than this is ignored
+const MAX_AGE: Duration = Duration::from_millis(200);
+
+/// Echoes take about 23 ms from 4 m, the sensor's range
+const ECHO_TIMEOUT: Duration = Duration::from_millis(25);
+
+static DISTANCE: Mutex<CriticalSectionRawMutex, Cell<Option<(u16, Instant)>>> = Mutex::new(Cell::new(None));
+
+/// Distance to the nearest obstacle in cm, `None` if nothing was measured recently
+pub fn distance() -> Option<u16> {
+    DISTANCE
+        .lock(Cell::get)
+        .filter(|&(_, at)| at.elapsed() < MAX_AGE)
+        .map(|(cm, _)| cm)
+}
+
+/// Store a measurement for the motion loop
+pub fn publish(cm: Option<u16>) {
+    DISTANCE.lock(|cell| cell.set(cm.map(|cm| (cm, Instant::now()))));
+    telemetry::update(|telemetry| telemetry.distance_cm = cm);
+}
+
+pub struct Ultrasonic<'d> {
+    trig: Output<'d>,
+    echo: Input<'d>,
+}
+
+impl<'d> Ultrasonic<'d> {
+    pub fn new(trig: Output<'d>, echo: Input<'d>) -> Self {
+        Self { trig, echo }
+    }
+
+    /// Measure the distance in cm, `None` if no echo came back
+    pub async fn measure(&mut self) -> Option<u16> {
+        // A 10 us pulse starts a measurement
+        self.trig.set_high();
+        block_for(Duration::from_micros(10));
+        self.trig.set_low();
+
+        with_timeout(ECHO_TIMEOUT, self.echo.wait_for_high()).await.ok()?;
+        let start = Instant::now();
+        with_timeout(ECHO_TIMEOUT, self.echo.wait_for_low()).await.ok()?;
+
+        // Sound travels 1 cm and back in about 58 us
+        Some((start.elapsed().as_micros() / 58) as u16)
+    }
+}
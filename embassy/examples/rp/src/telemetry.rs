@@ -0,0 +1,19 @@
+//! Latest state of the car, updated by the tasks that own each part of it
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use shared::Telemetry;
+
+static TELEMETRY: Mutex<CriticalSectionRawMutex, RefCell<Telemetry>> = Mutex::new(RefCell::new(Telemetry::new()));
+
+/// Change part of the telemetry
+pub fn update(f: impl FnOnce(&mut Telemetry)) {
+    TELEMETRY.lock(|telemetry| f(&mut telemetry.borrow_mut()));
+}
+
+/// Copy of the current telemetry, for sending to a client
+pub fn snapshot() -> Telemetry {
+    TELEMETRY.lock(|telemetry| telemetry.borrow().clone())
+}
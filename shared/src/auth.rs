@@ -0,0 +1,319 @@
+//! Challenge-response authentication over a pre-shared key.
+//!
+//! On connect the car sends a random server nonce. The client answers with its own nonce and an
+//! HMAC-SHA256 proof over both nonces keyed with the pre-shared key. Both sides then derive a
+//! session key from the two nonces, and every command carries a sequence number and a truncated
+//! HMAC tag over `(seq, command)` computed with that session key. Fresh nonces make every session
+//! key unique, so recorded traffic can't be replayed into a new session, and sequence numbers
+//! must strictly increase within a session. Keys carried by a signed command are XORed with a
+//! keystream derived from the session key and the sequence number, so they never cross the network
+//! in the clear once a session is up. Unlocking the driving profile proves the admin key with an
+//! HMAC over the connection's challenge instead of sending it, see [`unlock_proof`]. Drive
+//! setpoints sent over UDP are tagged with a key derived from the session key, see
+//! [`crate::drive`].
+
+use bincode::enc::write::Writer;
+use bincode::error::EncodeError;
+use bincode::{Decode, Encode};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{CarCommand, ProfileLock};
+
+pub(crate) type HmacSha256 = Hmac<Sha256>;
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 16;
+pub const TAG_LEN: usize = 16;
+
+pub type Key = [u8; KEY_LEN];
+pub type Nonce = [u8; NONCE_LEN];
+pub type Tag = [u8; TAG_LEN];
+
+const PROOF_LABEL: &[u8] = b"crusty-auth-proof";
+const SESSION_LABEL: &[u8] = b"crusty-session-key";
+const DRIVE_LABEL: &[u8] = b"crusty-drive-key";
+const WRAP_LABEL: &[u8] = b"crusty-key-wrap";
+const UNLOCK_LABEL: &[u8] = b"crusty-profile-unlock";
+
+// Sent by the client in answer to the car's challenge
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct AuthResponse {
+    pub client_nonce: Nonce,
+    pub proof: [u8; 32], // HMAC(key, label || server nonce || client nonce)
+}
+
+// A command authenticated with the session key
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct SignedCommand {
+    pub seq: u32,
+    pub command: CarCommand,
+    pub tag: Tag, // Truncated HMAC(session key, seq || command)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    NotAuthenticated,
+    BadProof,
+    BadTag,
+    Replay,
+}
+
+/// Derive a key from a passphrase entered by the user
+pub fn key_from_passphrase(passphrase: &str) -> Key {
+    Sha256::digest(passphrase.as_bytes()).into()
+}
+
+/// Proof of the admin key that unlocks the driving profile, answering the car's
+/// `ServerMessage::ProfileChallenge`
+pub fn unlock_proof(admin: &Key, challenge: &Nonce) -> [u8; 32] {
+    unlock_mac(admin, challenge).finalize().into_bytes().into()
+}
+
+/// Whether `proof` was made with the admin key for `challenge`
+pub fn verify_unlock(admin: &Key, challenge: &Nonce, proof: &[u8; 32]) -> bool {
+    unlock_mac(admin, challenge).verify_slice(proof).is_ok()
+}
+
+fn unlock_mac(admin: &Key, challenge: &Nonce) -> HmacSha256 {
+    let mut mac = mac(admin);
+    mac.update(UNLOCK_LABEL);
+    mac.update(challenge);
+    mac
+}
+
+pub(crate) fn mac(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+fn proof_mac(key: &Key, server_nonce: &Nonce, client_nonce: &Nonce) -> HmacSha256 {
+    let mut mac = mac(key);
+    mac.update(PROOF_LABEL);
+    mac.update(server_nonce);
+    mac.update(client_nonce);
+    mac
+}
+
+fn session_key(key: &Key, server_nonce: &Nonce, client_nonce: &Nonce) -> Key {
+    let mut mac = mac(key);
+    mac.update(SESSION_LABEL);
+    mac.update(server_nonce);
+    mac.update(client_nonce);
+    mac.finalize().into_bytes().into()
+}
+
+// Keeps the datagram tags apart from the command tags of the same session
+fn drive_key(session_key: &Key) -> Key {
+    let mut mac = mac(session_key);
+    mac.update(DRIVE_LABEL);
+    mac.finalize().into_bytes().into()
+}
+
+// XORs the keys `command` carries with a keystream only the two ends of the session know, wrapping
+// a wrapped key unwraps it
+fn wrap_keys(session_key: &Key, seq: u32, command: &mut CarCommand) {
+    if let CarCommand::SetAuthKey(Some(key)) | CarCommand::LockProfile(ProfileLock::Lock { admin: key }) = command {
+        let mut mac = mac(session_key);
+        mac.update(WRAP_LABEL);
+        mac.update(&seq.to_le_bytes());
+        let pad = mac.finalize().into_bytes();
+        key.iter_mut().zip(pad).for_each(|(byte, pad)| *byte ^= pad);
+    }
+}
+
+/// Feeds the bincode encoding of a value straight into a MAC
+struct MacWriter<'a>(&'a mut HmacSha256);
+
+impl Writer for MacWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
+        self.0.update(bytes);
+        Ok(())
+    }
+}
+
+fn command_mac(session_key: &Key, seq: u32, command: &CarCommand) -> HmacSha256 {
+    let mut mac = mac(session_key);
+    mac.update(&seq.to_le_bytes());
+    // MacWriter never fails, so neither can the encoding
+    let _ = bincode::encode_into_writer(command, MacWriter(&mut mac), crate::frame::config());
+    mac
+}
+
+/// Car side of a connection to a car with a pre-shared key
+pub struct ServerSession {
+    key: Key,
+    server_nonce: Nonce,
+    session_key: Option<Key>,
+    last_seq: Option<u32>,
+}
+
+impl ServerSession {
+    /// Start a session, `server_nonce` must be freshly generated for every connection
+    pub fn new(key: Key, server_nonce: Nonce) -> Self {
+        Self {
+            key,
+            server_nonce,
+            session_key: None,
+            last_seq: None,
+        }
+    }
+
+    /// The challenge to send to the client in `ServerMessage::Hello`
+    pub fn challenge(&self) -> Nonce {
+        self.server_nonce
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.session_key.is_some()
+    }
+
+    /// Check the client's proof of the pre-shared key and establish the session key
+    pub fn authenticate(&mut self, response: &AuthResponse) -> Result<(), AuthError> {
+        proof_mac(&self.key, &self.server_nonce, &response.client_nonce)
+            .verify_slice(&response.proof)
+            .map_err(|_| AuthError::BadProof)?;
+        self.session_key = Some(session_key(&self.key, &self.server_nonce, &response.client_nonce));
+        self.last_seq = None;
+        Ok(())
+    }
+
+    /// The key the client tags drive setpoints with, once it has authenticated
+    pub fn drive_key(&self) -> Option<Key> {
+        self.session_key.as_ref().map(drive_key)
+    }
+
+    /// Verify a signed command and unwrap the keys it carries, rejecting bad tags and sequence
+    /// numbers that were already used
+    pub fn open(&mut self, signed: &SignedCommand) -> Result<CarCommand, AuthError> {
+        let session_key = self.session_key.as_ref().ok_or(AuthError::NotAuthenticated)?;
+        command_mac(session_key, signed.seq, &signed.command)
+            .verify_truncated_left(&signed.tag)
+            .map_err(|_| AuthError::BadTag)?;
+        if self.last_seq.is_some_and(|last| signed.seq <= last) {
+            return Err(AuthError::Replay);
+        }
+        self.last_seq = Some(signed.seq);
+        let mut command = signed.command.clone();
+        wrap_keys(session_key, signed.seq, &mut command);
+        Ok(command)
+    }
+}
+
+/// Client side of an authenticated connection
+pub struct ClientSession {
+    session_key: Key,
+    next_seq: u32,
+}
+
+impl ClientSession {
+    /// Answer the car's challenge, `client_nonce` must be freshly generated for every connection
+    pub fn respond(key: &Key, server_nonce: &Nonce, client_nonce: Nonce) -> (Self, AuthResponse) {
+        let proof = proof_mac(key, server_nonce, &client_nonce).finalize().into_bytes().into();
+        let session = Self {
+            session_key: session_key(key, server_nonce, &client_nonce),
+            next_seq: 0,
+        };
+        (session, AuthResponse { client_nonce, proof })
+    }
+
+    /// Wrap the keys the next command of this session carries and sign it
+    pub fn sign(&mut self, mut command: CarCommand) -> SignedCommand {
+        let seq = self.next_seq;
+        wrap_keys(&self.session_key, seq, &mut command);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let full_tag = command_mac(&self.session_key, seq, &command).finalize().into_bytes();
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&full_tag[..TAG_LEN]);
+        SignedCommand { seq, command, tag }
+    }
+
+    /// The key to tag drive setpoints with
+    pub fn drive_key(&self) -> Key {
+        drive_key(&self.session_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_NONCE: Nonce = [1; NONCE_LEN];
+    const CLIENT_NONCE: Nonce = [2; NONCE_LEN];
+
+    fn handshake(server_key: Key, client_key: Key) -> (ServerSession, ClientSession, Result<(), AuthError>) {
+        let mut server = ServerSession::new(server_key, SERVER_NONCE);
+        let (client, response) = ClientSession::respond(&client_key, &server.challenge(), CLIENT_NONCE);
+        let result = server.authenticate(&response);
+        (server, client, result)
+    }
+
+    #[test]
+    fn signed_commands_are_accepted_in_order() {
+        let key = key_from_passphrase("hunter2");
+        let (mut server, mut client, result) = handshake(key, key);
+        assert_eq!(result, Ok(()));
+        assert_eq!(server.drive_key(), Some(client.drive_key()));
+
+        let first = client.sign(CarCommand::Forward(50));
+        let second = client.sign(CarCommand::Stop);
+        assert_eq!(server.open(&first), Ok(CarCommand::Forward(50)));
+        assert_eq!(server.open(&second), Ok(CarCommand::Stop));
+    }
+
+    #[test]
+    fn keys_are_wrapped_on_the_wire() {
+        let key = key_from_passphrase("hunter2");
+        let (mut server, mut client, _) = handshake(key, key);
+
+        let new_key = key_from_passphrase("correct horse");
+        let first = client.sign(CarCommand::SetAuthKey(Some(new_key)));
+        let second = client.sign(CarCommand::SetAuthKey(Some(new_key)));
+        // Each command wraps the key differently
+        assert_ne!(first.command, CarCommand::SetAuthKey(Some(new_key)));
+        assert_ne!(first.command, second.command);
+        assert_eq!(server.open(&first), Ok(CarCommand::SetAuthKey(Some(new_key))));
+        assert_eq!(server.open(&second), Ok(CarCommand::SetAuthKey(Some(new_key))));
+        assert_eq!(server.open(&client.sign(CarCommand::SetAuthKey(None))), Ok(CarCommand::SetAuthKey(None)));
+
+        let lock = CarCommand::LockProfile(ProfileLock::Lock { admin: new_key });
+        let signed = client.sign(lock.clone());
+        assert_ne!(signed.command, lock);
+        assert_eq!(server.open(&signed), Ok(lock));
+    }
+
+    #[test]
+    fn unlock_proofs_answer_one_challenge() {
+        let admin = key_from_passphrase("grown-up");
+        let proof = unlock_proof(&admin, &SERVER_NONCE);
+        assert!(verify_unlock(&admin, &SERVER_NONCE, &proof));
+        assert!(!verify_unlock(&admin, &CLIENT_NONCE, &proof));
+        assert!(!verify_unlock(&key_from_passphrase("kid"), &SERVER_NONCE, &proof));
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let (_, _, result) = handshake(key_from_passphrase("right"), key_from_passphrase("wrong"));
+        assert_eq!(result, Err(AuthError::BadProof));
+    }
+
+    #[test]
+    fn replayed_and_tampered_commands_are_rejected() {
+        let key = key_from_passphrase("hunter2");
+        let (mut server, mut client, _) = handshake(key, key);
+
+        let signed = client.sign(CarCommand::Forward(50));
+        assert!(server.open(&signed).is_ok());
+        assert_eq!(server.open(&signed), Err(AuthError::Replay));
+
+        let mut tampered = client.sign(CarCommand::Forward(50));
+        tampered.command = CarCommand::Forward(100);
+        assert_eq!(server.open(&tampered), Err(AuthError::BadTag));
+    }
+
+    #[test]
+    fn commands_require_a_session() {
+        let mut server = ServerSession::new(key_from_passphrase("hunter2"), SERVER_NONCE);
+        let (mut client, _) = ClientSession::respond(&key_from_passphrase("hunter2"), &SERVER_NONCE, CLIENT_NONCE);
+        assert_eq!(server.open(&client.sign(CarCommand::Stop)), Err(AuthError::NotAuthenticated));
+    }
+}
@@ -0,0 +1,385 @@
+//! Configuration persisted in the car's flash
+
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode};
+
+use crate::auth::Key;
+
+/// Layout version of the [`CarConfig`] stored in flash, bump it whenever a field changes
+pub const CONFIG_VERSION: u8 = 1;
+
+// `auth_key` and `profile_lock` have to stay the first fields, so both keys survive a layout the
+// firmware doesn't know
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct CarConfig {
+    pub auth_key: Option<Key>,     // Pre-shared key, commands must be authenticated while one is set
+    pub profile_lock: Option<Key>, // Admin key the profile is locked with, it can't be switched until unlocked with it
+    pub deadman_ms: u16,           // Stop network driven motion not refreshed within this time, 0 disables
+    pub ir_keymap: IrKeymap,       // IR remote key codes and what they do
+    pub calibration: Calibration,  // How each wheel's motor is corrected
+    pub chassis: Chassis,          // Which wheels the car has, decides how CarCommand::Holonomic drives it
+    pub pwm: PwmSettings,          // How the motor driver is pulsed, taken on the next boot
+    pub profile: Profile,          // Limits every driver is held to
+}
+
+impl Default for CarConfig {
+    fn default() -> Self {
+        Self {
+            auth_key: None,
+            profile_lock: None,
+            deadman_ms: 0,
+            ir_keymap: DEFAULT_IR_KEYMAP,
+            calibration: Calibration::new(),
+            chassis: Chassis::Tank,
+            pwm: PwmSettings::new(),
+            profile: Profile::Race,
+        }
+    }
+}
+
+impl CarConfig {
+    /// The part of the configuration clients can read back
+    pub fn settings(&self) -> Settings {
+        Settings {
+            deadman_ms: self.deadman_ms,
+            ir_keymap: self.ir_keymap,
+            chassis: self.chassis,
+            pwm: self.pwm,
+        }
+    }
+
+    pub fn apply(&mut self, settings: Settings) {
+        self.deadman_ms = settings.deadman_ms;
+        self.ir_keymap = settings.ir_keymap;
+        self.chassis = settings.chassis;
+        self.pwm = settings.pwm;
+    }
+}
+
+/// How much of a stored config [`decode_stored`] could read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Restored {
+    All,     // The current layout
+    KeyOnly, // A layout this firmware doesn't know, only the keys were kept
+}
+
+/// Decode a config stored with layout `version`
+///
+/// Falls back to keeping just the car key and the profile lock, so a car never drops either over a
+/// layout mismatch. Fails only if not even those can be read.
+pub fn decode_stored(version: u8, payload: &[u8]) -> Result<(CarConfig, Restored), DecodeError> {
+    let current = bincode::decode_from_slice(payload, crate::frame::config())
+        .ok()
+        .filter(|&(_, len)| version == CONFIG_VERSION && len == payload.len());
+    if let Some((config, _)) = current {
+        return Ok((config, Restored::All));
+    }
+    let ((auth_key, profile_lock), _) = bincode::decode_from_slice(payload, crate::frame::config())?;
+    let config = CarConfig {
+        auth_key,
+        profile_lock,
+        ..CarConfig::default()
+    };
+    Ok((config, Restored::KeyOnly))
+}
+
+// Everything in the configuration except the key, which can only be replaced
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct Settings {
+    pub deadman_ms: u16,
+    pub ir_keymap: IrKeymap,
+    pub chassis: Chassis,
+    pub pwm: PwmSettings,
+}
+
+// The wheels the car is built with
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum Chassis {
+    Tank,    // Plain wheels, each side is driven together and the car can't move sideways
+    Mecanum, // Mecanum wheels, each wheel is driven on its own so the car can strafe
+}
+
+// Named set of limits the car holds every driver to, whichever client or input source drives it
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum Profile {
+    Kid,    // Slow, gentle and stops well clear of obstacles
+    Indoor, // Moderate speed for driving around furniture
+    Race,   // The car's full performance, how it drove before profiles
+}
+
+impl Profile {
+    pub const ALL: [Profile; 3] = [Profile::Kid, Profile::Indoor, Profile::Race];
+
+    pub const fn limits(self) -> DriveLimits {
+        match self {
+            Profile::Kid => DriveLimits {
+                max_speed: 40,
+                accel_per_s: 100,
+                max_turn: 25,
+                stop_distance_cm: 40,
+            },
+            Profile::Indoor => DriveLimits {
+                max_speed: 70,
+                accel_per_s: 250,
+                max_turn: 50,
+                stop_distance_cm: 30,
+            },
+            Profile::Race => DriveLimits {
+                max_speed: 100,
+                accel_per_s: 500,
+                max_turn: 100,
+                stop_distance_cm: 20,
+            },
+        }
+    }
+}
+
+// What a profile allows, speeds are in percent of full speed
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct DriveLimits {
+    pub max_speed: u8,         // Speed full speed requests are scaled down to, slower ones in proportion
+    pub accel_per_s: u16,      // Most a wheel's speed changes in a second, stopping is always immediate
+    pub max_turn: u8,          // Most each side turns faster than the other side's mean
+    pub stop_distance_cm: u16, // Forward motion is refused while an obstacle is closer than this
+}
+
+// How the motor PWM is generated, the divider and counter top are worked out from the frequency
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct PwmSettings {
+    pub frequency_hz: u32,   // Pulses per second, from 20000 on the motors no longer whine audibly
+    pub phase_correct: bool, // Centre the pulses by counting up and down, at the cost of half the resolution
+}
+
+impl PwmSettings {
+    /// 500 Hz, what the Freenove firmware uses
+    pub const fn new() -> Self {
+        Self {
+            frequency_hz: 500,
+            phase_correct: false,
+        }
+    }
+}
+
+impl Default for PwmSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Corrections for one wheel's motor, applied to every speed the car sets it to
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct WheelCalibration {
+    pub invert: bool, // The wheel turns the wrong way, the motor is mounted or wired the other way round
+    pub gain: u8,     // Percent of the requested speed the wheel is driven at, up to 100, trims a faster motor down
+    pub min_duty: u8, // Duty in percent the slowest speed starts at, to overcome stiction
+    pub deadband: u8, // Speeds up to this percent leave the motor off
+}
+
+impl WheelCalibration {
+    pub const fn new() -> Self {
+        Self {
+            invert: false,
+            gain: 100,
+            min_duty: 0,
+            deadband: 0,
+        }
+    }
+
+    /// The signed duty (-100 to 100) to drive the motor at for a requested speed
+    pub fn apply(&self, speed: i8) -> i8 {
+        let requested = (speed as i16).clamp(-100, 100);
+        if requested.abs() <= self.deadband as i16 {
+            return 0;
+        }
+        let trimmed = (requested.abs() * self.gain as i16 / 100).min(100);
+        if trimmed == 0 {
+            return 0;
+        }
+        // Spread the speeds over the range the motor actually turns in
+        let min_duty = self.min_duty.min(100) as i16;
+        let duty = (min_duty + trimmed * (100 - min_duty) / 100) as i8;
+        if (requested > 0) != self.invert {
+            duty
+        } else {
+            -duty
+        }
+    }
+}
+
+impl Default for WheelCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Corrections for every wheel's motor
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct Calibration {
+    pub front_left: WheelCalibration,
+    pub front_right: WheelCalibration,
+    pub rear_left: WheelCalibration,
+    pub rear_right: WheelCalibration,
+}
+
+impl Calibration {
+    /// Every wheel driven as requested
+    pub const fn new() -> Self {
+        Self {
+            front_left: WheelCalibration::new(),
+            front_right: WheelCalibration::new(),
+            rear_left: WheelCalibration::new(),
+            rear_right: WheelCalibration::new(),
+        }
+    }
+
+    /// Whether every gain only trims, a higher one would drive a wheel past the profile's limits
+    pub fn is_valid(&self) -> bool {
+        [self.front_left, self.front_right, self.rear_left, self.rear_right]
+            .iter()
+            .all(|wheel| wheel.gain <= 100)
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What pressing a key on the IR remote does
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum IrAction {
+    Forward,
+    Backward,
+    TurnLeft,
+    TurnRight,
+    Stop,
+    Horn,
+    SpeedUp,
+    SpeedDown,
+}
+
+// Binds the NEC command code of a remote key to an action
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct IrBinding {
+    pub command: u8,
+    pub action: IrAction,
+}
+
+pub const IR_KEYMAP_LEN: usize = 16;
+
+pub type IrKeymap = [Option<IrBinding>; IR_KEYMAP_LEN];
+
+const fn bind(command: u8, action: IrAction) -> Option<IrBinding> {
+    Some(IrBinding { command, action })
+}
+
+// Key codes of the remote shipped with the Freenove car
+pub const DEFAULT_IR_KEYMAP: IrKeymap = [
+    bind(0x40, IrAction::Forward),   // +
+    bind(0x19, IrAction::Backward),  // -
+    bind(0x07, IrAction::TurnLeft),  // |<<
+    bind(0x09, IrAction::TurnRight), // >>|
+    bind(0x15, IrAction::Stop),      // Play
+    bind(0x44, IrAction::Horn),      // Test
+    bind(0x47, IrAction::SpeedUp),   // Menu
+    bind(0x43, IrAction::SpeedDown), // Back
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+/// Look up the action bound to a key code
+pub fn ir_action(keymap: &IrKeymap, command: u8) -> Option<IrAction> {
+    keymap
+        .iter()
+        .flatten()
+        .find(|binding| binding.command == command)
+        .map(|binding| binding.action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_reverses_trims_and_lifts_the_speed() {
+        assert_eq!(WheelCalibration::new().apply(-40), -40);
+        assert_eq!(WheelCalibration::new().apply(-128), -100);
+
+        let reversed = WheelCalibration { invert: true, ..WheelCalibration::new() };
+        assert_eq!(reversed.apply(40), -40);
+        assert_eq!(reversed.apply(-40), 40);
+
+        let trimmed = WheelCalibration { gain: 90, ..WheelCalibration::new() };
+        assert_eq!(trimmed.apply(50), 45);
+        assert_eq!(trimmed.apply(-100), -90);
+
+        let sticky = WheelCalibration {
+            min_duty: 30,
+            deadband: 5,
+            ..WheelCalibration::new()
+        };
+        assert_eq!(sticky.apply(5), 0);
+        assert_eq!(sticky.apply(-6), -34);
+        assert_eq!(sticky.apply(50), 65);
+        assert_eq!(sticky.apply(100), 100);
+        assert_eq!(sticky.apply(0), 0);
+    }
+
+    fn encode(value: impl Encode, buffer: &mut [u8]) -> &[u8] {
+        let len = bincode::encode_into_slice(value, buffer, crate::frame::config()).unwrap();
+        &buffer[..len]
+    }
+
+    #[test]
+    fn stored_configs_keep_the_keys_across_layouts() {
+        let key = Some([7; crate::auth::KEY_LEN]);
+        let mut buffer = [0; 1024];
+
+        let current = CarConfig {
+            auth_key: key,
+            profile: Profile::Kid,
+            profile_lock: key,
+            ..CarConfig::default()
+        };
+        let stored = encode(&current, &mut buffer);
+        assert_eq!(decode_stored(CONFIG_VERSION, stored).ok(), Some((current, Restored::All)));
+
+        let admin = Some([9; crate::auth::KEY_LEN]);
+        let unknown = encode((key, admin, 250u16, [0xAAu8; 12]), &mut buffer);
+        let key_only = CarConfig {
+            auth_key: key,
+            profile_lock: admin,
+            ..CarConfig::default()
+        };
+        assert_eq!(decode_stored(CONFIG_VERSION + 1, unknown).ok(), Some((key_only.clone(), Restored::KeyOnly)));
+        assert_eq!(decode_stored(CONFIG_VERSION, unknown).ok(), Some((key_only, Restored::KeyOnly)));
+
+        assert!(decode_stored(CONFIG_VERSION, &[9]).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Drive setpoints sent as UDP datagrams next to the command connection.
+//!
+//! A retransmitted TCP segment holds up everything behind it, so a stale "forward" being resent
+//! can delay a newer "stop". Clients can send drive setpoints as datagrams to the command port
+//! over UDP instead: each carries a sequence number, the car applies the newest one and drops any
+//! that arrives after a later one. A setpoint of zero throttle and steer stops the car. Setpoints
+//! only carry throttle and steer, so strafing with `CarCommand::Holonomic` isn't available over
+//! UDP. Commands that must arrive, like settings and missions, stay on TCP.
+//!
+//! The channel belongs to a command connection. The car only takes setpoints from the address of
+//! the client connected over TCP, and when it has a key they must be tagged with the drive key of
+//! that connection's session, see [`crate::auth`]. A datagram is the bincode encoding of a
+//! [`Setpoint`] without the frame header.
+
+use bincode::{Decode, Encode};
+use hmac::Mac;
+
+use crate::auth::{self, AuthError, Key, Tag, TAG_LEN};
+use crate::frame::{self, FrameError};
+
+/// Largest datagram a setpoint takes
+pub const MAX_DATAGRAM_LEN: usize = 32;
+
+// Throttle and steer as in CarCommand::Drive
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct Setpoint {
+    pub seq: u32,
+    pub throttle: i8,
+    pub steer: i8,
+    pub tag: Option<Tag>, // Truncated HMAC(drive key, seq || throttle || steer), when the car has a key
+}
+
+fn setpoint_tag(key: &Key, seq: u32, throttle: i8, steer: i8) -> auth::HmacSha256 {
+    let mut mac = auth::mac(key);
+    mac.update(&seq.to_le_bytes());
+    mac.update(&[throttle as u8, steer as u8]);
+    mac
+}
+
+/// Encode `setpoint` as a datagram into `buf`, returning the number of bytes written
+pub fn encode(setpoint: &Setpoint, buf: &mut [u8]) -> Result<usize, FrameError> {
+    bincode::encode_into_slice(setpoint, buf, frame::config()).map_err(FrameError::Encode)
+}
+
+pub fn decode(datagram: &[u8]) -> Result<Setpoint, FrameError> {
+    frame::decode(datagram)
+}
+
+/// Client side of the drive channel
+pub struct Sender {
+    key: Option<Key>,
+    next_seq: u32,
+}
+
+impl Sender {
+    /// Tag setpoints with `key`, the drive key of the session when the car has a key
+    pub fn new(key: Option<Key>) -> Self {
+        Self { key, next_seq: 0 }
+    }
+
+    /// The next setpoint, newer than every one before it
+    pub fn setpoint(&mut self, throttle: i8, steer: i8) -> Setpoint {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let tag = self.key.map(|key| {
+            let full_tag = setpoint_tag(&key, seq, throttle, steer).finalize().into_bytes();
+            let mut tag = [0; TAG_LEN];
+            tag.copy_from_slice(&full_tag[..TAG_LEN]);
+            tag
+        });
+        Setpoint {
+            seq,
+            throttle,
+            steer,
+            tag,
+        }
+    }
+}
+
+/// Car side of the drive channel
+pub struct Receiver {
+    key: Option<Key>,
+    last_seq: Option<u32>,
+}
+
+impl Receiver {
+    /// Check setpoints against `key`, the drive key of the session when the car has a key
+    pub fn new(key: Option<Key>) -> Self {
+        Self { key, last_seq: None }
+    }
+
+    /// Throttle and steer of a setpoint, rejecting bad tags and setpoints older than the last one applied
+    pub fn accept(&mut self, setpoint: &Setpoint) -> Result<(i8, i8), AuthError> {
+        if let Some(key) = &self.key {
+            let tag = setpoint.tag.as_ref().ok_or(AuthError::BadTag)?;
+            setpoint_tag(key, setpoint.seq, setpoint.throttle, setpoint.steer)
+                .verify_truncated_left(tag)
+                .map_err(|_| AuthError::BadTag)?;
+        }
+        if self.last_seq.is_some_and(|last| setpoint.seq <= last) {
+            return Err(AuthError::Replay);
+        }
+        self.last_seq = Some(setpoint.seq);
+        Ok((setpoint.throttle, setpoint.steer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_setpoints_are_dropped() {
+        let mut sender = Sender::new(None);
+        let mut receiver = Receiver::new(None);
+        let forward = sender.setpoint(60, 0);
+        let stop = sender.setpoint(0, 0);
+
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let n = encode(&stop, &mut buf).unwrap();
+        assert_eq!(receiver.accept(&decode(&buf[..n]).unwrap()), Ok((0, 0)));
+        assert_eq!(receiver.accept(&forward), Err(AuthError::Replay));
+        assert_eq!(receiver.accept(&stop), Err(AuthError::Replay));
+    }
+
+    #[test]
+    fn setpoints_are_checked_against_the_drive_key() {
+        let key = auth::key_from_passphrase("hunter2");
+        let mut receiver = Receiver::new(Some(key));
+
+        let mut tampered = Sender::new(Some(key)).setpoint(20, 0);
+        tampered.throttle = 100;
+        assert_eq!(receiver.accept(&tampered), Err(AuthError::BadTag));
+        assert_eq!(receiver.accept(&Sender::new(None).setpoint(20, 0)), Err(AuthError::BadTag));
+        let other_key = auth::key_from_passphrase("hunter3");
+        assert_eq!(receiver.accept(&Sender::new(Some(other_key)).setpoint(20, 0)), Err(AuthError::BadTag));
+
+        let mut sender = Sender::new(Some(key));
+        let setpoint = sender.setpoint(-100, 127);
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        assert!(encode(&setpoint, &mut buf).unwrap() <= MAX_DATAGRAM_LEN);
+        assert_eq!(receiver.accept(&setpoint), Ok((-100, 127)));
+    }
+}
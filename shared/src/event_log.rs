@@ -0,0 +1,68 @@
+//! Events the car keeps a record of in flash, read back with `CarCommand::ReadLog`
+
+use bincode::{Decode, Encode};
+
+use crate::ErrorCode;
+
+/// Most entries in one `ServerMessage::Log` reply
+pub const LOG_PAGE_LEN: usize = 16;
+
+// One recorded event
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct LogEntry {
+    pub seq: u32,       // Number of the entry, counting up for as long as the log has been kept
+    pub boot: u16,      // Boot the event happened in, counting up for as long as the log has been kept
+    pub uptime_ms: u32, // Time since that boot
+    pub event: Event,
+}
+
+// What happened
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum Event {
+    Boot(ResetReason),                       // The firmware started
+    Connected,                               // A client connected to the command port or the browser page
+    Disconnected,                            // The client left or its connection failed
+    Failsafe,                                // The deadman stopped the car after network driving went quiet
+    Rejected { code: ErrorCode, count: u8 }, // Messages rejected on one connection, up to 255, with the first one's error
+    LowBattery,                              // The battery ran low, logged once until it is charged
+}
+
+// Why the car was reset before it booted
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum ResetReason {
+    PowerOn,  // Power was applied or the RUN pin was pulled low
+    Watchdog, // The watchdog was not fed in time
+    Forced,   // The watchdog was triggered on purpose, like a reset from the debugger
+}
+
+/// Entries of the log from `CarCommand::ReadLog`'s `from` on, oldest first, the page ends at the
+/// first `None`
+pub type LogPage = [Option<LogEntry>; LOG_PAGE_LEN];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerMessage;
+    use crate::frame::{self, HEADER_LEN, MAX_FRAME_LEN};
+
+    #[test]
+    fn full_pages_fit_in_a_frame() {
+        let entry = LogEntry {
+            seq: u32::MAX,
+            boot: u16::MAX,
+            uptime_ms: u32::MAX,
+            event: Event::Rejected {
+                code: ErrorCode::StorageFailed,
+                count: u8::MAX,
+            },
+        };
+        let mut buf = [0; HEADER_LEN + MAX_FRAME_LEN];
+        assert!(frame::encode(&ServerMessage::Log([Some(entry); LOG_PAGE_LEN]), &mut buf).is_ok());
+    }
+}
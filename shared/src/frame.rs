@@ -0,0 +1,99 @@
+//! Length-prefixed framing for messages on the command stream.
+//!
+//! Every message is sent as a little-endian `u16` byte count followed by its bincode encoding,
+//! so a single TCP read can carry several messages or only part of one.
+
+use bincode::config::Configuration;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+
+/// Size of the length prefix in bytes
+pub const HEADER_LEN: usize = 2;
+
+/// Largest payload a frame may carry
+pub const MAX_FRAME_LEN: usize = 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    TooLarge,
+    Encode(EncodeError),
+    Decode(DecodeError),
+}
+
+/// The bincode configuration used for every frame payload
+pub fn config() -> Configuration {
+    bincode::config::standard()
+}
+
+/// Encode `message` as a frame into `buf`, returning the number of bytes written
+pub fn encode<T: Encode>(message: &T, buf: &mut [u8]) -> Result<usize, FrameError> {
+    if buf.len() < HEADER_LEN {
+        return Err(FrameError::TooLarge);
+    }
+    let len = bincode::encode_into_slice(message, &mut buf[HEADER_LEN..], config()).map_err(FrameError::Encode)?;
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge);
+    }
+    buf[..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    Ok(HEADER_LEN + len)
+}
+
+/// Payload length announced by a frame header
+pub fn payload_len(header: [u8; HEADER_LEN]) -> usize {
+    u16::from_le_bytes(header) as usize
+}
+
+/// Decode a frame payload, without its header
+pub fn decode<T: Decode<()>>(payload: &[u8]) -> Result<T, FrameError> {
+    bincode::decode_from_slice(payload, config())
+        .map(|(message, _)| message)
+        .map_err(FrameError::Decode)
+}
+
+/// Accumulates bytes read from a stream and splits them into frames
+pub struct FrameReader<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FrameReader<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    /// Free space to read the next chunk of the stream into, report the bytes read with [`Self::filled`]
+    pub fn space(&mut self) -> &mut [u8] {
+        &mut self.buf[self.len..]
+    }
+
+    pub fn filled(&mut self, n: usize) {
+        self.len = (self.len + n).min(N);
+    }
+
+    /// Decode the next complete frame, or return `None` if more data is needed
+    pub fn next_message<T: Decode<()>>(&mut self) -> Option<Result<T, FrameError>> {
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        let payload = payload_len([self.buf[0], self.buf[1]]);
+        if payload > MAX_FRAME_LEN || HEADER_LEN + payload > N {
+            // The stream can't be resynchronised, drop everything buffered so far
+            self.len = 0;
+            return Some(Err(FrameError::TooLarge));
+        }
+        let end = HEADER_LEN + payload;
+        if self.len < end {
+            return None;
+        }
+        let result = decode(&self.buf[HEADER_LEN..end]);
+        self.buf.copy_within(end..self.len, 0);
+        self.len -= end;
+        Some(result)
+    }
+}
+
+impl<const N: usize> Default for FrameReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
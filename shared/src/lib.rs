@@ -1,14 +1,189 @@
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
+
+// The TypeScript types derived for the GUI are built with std's `vec!`
+#[cfg(feature = "specta")]
+#[macro_use]
+extern crate std;
+
+pub mod auth;
+pub mod config;
+pub mod drive;
+pub mod event_log;
+pub mod frame;
+pub mod mission;
 
 use bincode::{Decode, Encode};
 
+// TCP port the car accepts command connections on, drive setpoints go to the same port over UDP
+pub const COMMAND_PORT: u16 = 1234;
+
 // Define the command enum for controlling the car
-#[derive(Encode, Decode, Debug)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
 pub enum CarCommand {
-    Forward(u8),   // Forward with specified speed (0-100)
-    Backward(u8),  // Backward with specified speed (0-100)
-    TurnLeft(u8),  // Turn left with specified speed
-    TurnRight(u8), // Turn right with specified speed
-    Stop,          // Stop all motors
+    Forward(u8),                    // Forward with specified speed (0-100)
+    Backward(u8),                   // Backward with specified speed (0-100)
+    TurnLeft(u8),                   // Turn left with specified speed
+    TurnRight(u8),                  // Turn right with specified speed
+    Stop,                           // Stop all motors
+    SetAuthKey(Option<auth::Key>),  // Store a new pre-shared key on the car, None disables authentication, wrapped when signed
+    Beep { freq: u16, ms: u16 },    // Sound the buzzer at freq Hz for ms milliseconds
+    PlayMelody(Melody),             // Play one of the built-in melodies
+    SetIrKeymap(config::IrKeymap),  // Store new IR remote key bindings
+    SetMode(DriveMode),             // Switch between manual driving and the autonomous modes
+    GetTelemetry,                   // Ask for a ServerMessage::Telemetry snapshot
+    Drive { throttle: i8, steer: i8 }, // Arcade drive, -100 to 100 each, positive steer turns right
+    GetConfig,                      // Ask for the ServerMessage::Config settings
+    SetConfig(config::Settings),    // Store and apply new settings
+    RunMission(mission::Mission),   // Run the steps with the car's own timing, a stop aborts it
+    SetCalibration { calibration: config::Calibration, store: bool }, // Apply a motor calibration, storing it unless it is being tried out
+    GetCalibration,                 // Ask for the ServerMessage::Calibration stored on the car
+    Holonomic { vx: i8, vy: i8, omega: i8 }, // Forward, rightward and clockwise speed, -100 to 100 each, strafes with mecanum wheels
+    ReadLog { from: u32 },          // Ask for a ServerMessage::Log page of the event log from entry `from` on
+    SetProfile(config::Profile),    // Switch the driving profile and store it, refused while it is locked
+    LockProfile(ProfileLock),       // Lock the profile with an admin key, or unlock it by proving that key
+    TurnBy { degrees: i16 },        // Turn on the spot by this many degrees measured with the IMU, positive turns right
+    GetProfileChallenge,            // Ask for the ServerMessage::ProfileChallenge to unlock the profile with
+}
+
+// Changes the profile lock, the admin key itself only crosses the network to set the lock
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum ProfileLock {
+    Lock { admin: auth::Key },  // Lock the unlocked profile with this admin key, wrapped, so only on an authenticated session
+    Unlock { proof: [u8; 32] }, // Unlock it, auth::unlock_proof of the admin key for the connection's challenge
+}
+
+// Who decides where the car goes
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum DriveMode {
+    Manual,                   // Driven by the network and IR commands
+    LightFollow(LightTarget), // Steers using the photoresistor pair
+}
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum LightTarget {
+    Brighter, // Head towards the light
+    Darker,   // Run away from the light
+}
+
+// Snapshot of the car's state
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct Telemetry {
+    pub mode: DriveMode,
+    pub light_left: u16,  // Left photoresistor above the ambient level measured at startup
+    pub light_right: u16, // Right photoresistor above the ambient level measured at startup
+    pub distance_cm: Option<u16>, // Ultrasonic distance to the nearest obstacle, None without an echo
+    pub loop_latency_us: u32,     // Worst delay of the motion control loop since boot
+    pub motor_left: i8,           // Mean speed the left motors are driven at after ramping, -100 to 100
+    pub motor_right: i8,          // Mean speed the right motors are driven at after ramping, -100 to 100
+    pub mission: mission::MissionStatus, // Progress of the last CarCommand::RunMission
+    pub profile: config::Profile, // Driving profile the car is held to
+    pub profile_locked: bool,     // The profile can only be switched after unlocking it with the admin key
+    pub imu: Option<ImuReading>,  // Latest IMU reading, None without an IMU
+}
+
+// What the IMU measured, rotations are clockwise seen from above
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub struct ImuReading {
+    pub heading_deg: f32,  // Rotation since the IMU was calibrated at startup, -180 to 180
+    pub yaw_rate_dps: f32, // Rotation rate with the gyro's bias taken off
+    pub accel_g: [f32; 3], // Acceleration along the sensor's x, y and z axes, z is up
+}
+
+impl Telemetry {
+    pub const fn new() -> Self {
+        Self {
+            mode: DriveMode::Manual,
+            light_left: 0,
+            light_right: 0,
+            distance_cm: None,
+            loop_latency_us: 0,
+            motor_left: 0,
+            motor_right: 0,
+            mission: mission::MissionStatus::Idle,
+            profile: config::Profile::Race,
+            profile_locked: false,
+            imu: None,
+        }
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Built-in buzzer melodies
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum Melody {
+    Boot,
+    Connect,
+    Disconnect,
+    LowBattery,
+    Obstacle,
+    Horn,
+}
+
+// Messages sent from the client to the car
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    Command(CarCommand),         // Plain command, only accepted while the car has no key configured
+    Auth(auth::AuthResponse),    // Answer to the challenge sent in ServerMessage::Hello
+    Signed(auth::SignedCommand), // Command authenticated with the session key
+}
+
+// Messages sent from the car to the client
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    Hello { challenge: Option<auth::Nonce> }, // Sent on connect, a challenge means authentication is required
+    Authenticated,                            // The AuthResponse was accepted
+    Ack,                                      // The command was executed
+    Telemetry(Telemetry),                     // Reply to CarCommand::GetTelemetry
+    Config(config::Settings),                 // Reply to CarCommand::GetConfig
+    Error(ErrorCode),                         // The message was rejected
+    Calibration(config::Calibration),         // Reply to CarCommand::GetCalibration
+    Log(event_log::LogPage),                  // Reply to CarCommand::ReadLog
+    ProfileChallenge(auth::Nonce),            // Reply to CarCommand::GetProfileChallenge
+}
+
+// Reasons for the car to reject a message
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum ErrorCode {
+    Malformed,     // The frame could not be decoded
+    Unauthorized,  // The car requires an authenticated session
+    AuthFailed,    // The AuthResponse proof did not match the car's key
+    BadSignature,  // The command tag did not match the session key
+    Replay,        // The sequence number was not newer than the last accepted one
+    StorageFailed, // The configuration could not be written to flash
+    ProfileLocked, // The driving profile is locked by the admin, it and the calibration can't change
+    NoImu,         // The command needs an IMU and the car has none
+    OutOfRange,    // A value in the command is outside the range the car accepts
+}
+
+impl From<auth::AuthError> for ErrorCode {
+    fn from(err: auth::AuthError) -> Self {
+        match err {
+            auth::AuthError::NotAuthenticated => ErrorCode::Unauthorized,
+            auth::AuthError::BadProof => ErrorCode::AuthFailed,
+            auth::AuthError::BadTag => ErrorCode::BadSignature,
+            auth::AuthError::Replay => ErrorCode::Replay,
+        }
+    }
 }
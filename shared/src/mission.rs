@@ -0,0 +1,83 @@
+//! Move sequences the car runs with its own timing, sent with `CarCommand::RunMission`
+
+use bincode::{Decode, Encode};
+
+/// Most steps a mission can have
+pub const MISSION_LEN: usize = 32;
+
+// One step of a mission, the next one starts once it has lasted its ms
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum MissionStep {
+    Drive { throttle: i8, steer: i8, ms: u16 }, // Arcade drive like CarCommand::Drive
+    Turn { speed: i8, ms: u16 },                // Turn on the spot, positive turns right
+    Wait { ms: u16 },                           // Stand still
+    Beep { freq: u16, ms: u16 },                // Sound the buzzer, the next step starts right away
+    Lights { r: u8, g: u8, b: u8 },             // Light the LEDs in one colour until the mission ends
+}
+
+impl MissionStep {
+    /// How long the step holds up the mission
+    pub fn duration_ms(&self) -> u16 {
+        match *self {
+            MissionStep::Drive { ms, .. } | MissionStep::Turn { ms, .. } | MissionStep::Wait { ms } => ms,
+            MissionStep::Beep { .. } | MissionStep::Lights { .. } => 0,
+        }
+    }
+}
+
+/// The steps of a mission in order, it ends at the first `None`
+pub type Mission = [Option<MissionStep>; MISSION_LEN];
+
+/// A mission running `steps`, `None` if there are more than [`MISSION_LEN`]
+pub fn mission(steps: &[MissionStep]) -> Option<Mission> {
+    if steps.len() > MISSION_LEN {
+        return None;
+    }
+    let mut mission = [None; MISSION_LEN];
+    for (slot, step) in mission.iter_mut().zip(steps) {
+        *slot = Some(*step);
+    }
+    Some(mission)
+}
+
+// How far the car got with the last mission it was given
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "specta", derive(serde::Serialize, serde::Deserialize, specta::Type))]
+pub enum MissionStatus {
+    Idle,                 // No mission since boot
+    Running { step: u8 }, // Running the step at this index
+    Done,                 // Ran to the end
+    Aborted,              // Stopped early by a stop, the deadman, an obstacle or other driving
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{self, HEADER_LEN, MAX_FRAME_LEN};
+    use crate::{CarCommand, ClientMessage};
+
+    #[test]
+    fn full_missions_fit_in_a_frame() {
+        let step = MissionStep::Drive {
+            throttle: -100,
+            steer: -100,
+            ms: u16::MAX,
+        };
+        let command = CarCommand::RunMission(mission(&[step; MISSION_LEN]).unwrap());
+        let mut buf = [0; HEADER_LEN + MAX_FRAME_LEN];
+        assert!(frame::encode(&ClientMessage::Command(command), &mut buf).is_ok());
+    }
+
+    #[test]
+    fn missions_are_bounded() {
+        let step = MissionStep::Wait { ms: 10 };
+        assert!(mission(&[step; MISSION_LEN + 1]).is_none());
+
+        let short = mission(&[step, MissionStep::Beep { freq: 440, ms: 100 }]).unwrap();
+        assert_eq!(short.iter().flatten().count(), 2);
+        assert_eq!(short.iter().flatten().map(MissionStep::duration_ms).sum::<u16>(), 10);
+    }
+}